@@ -0,0 +1,2455 @@
+use image::imageops::FilterType;
+use image::{GrayImage, ImageBuffer, Luma, Rgba, RgbaImage};
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub struct PadifyError(pub String);
+
+impl std::fmt::Display for PadifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for PadifyError {}
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Settings for [`pad_image`], with the padding already resolved to pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PadOptions {
+    pub padding: Padding,
+    pub background: Rgba<u8>,
+    /// Trim partial top and bottom artifacts (see [`auto_crop_partial`]) before padding
+    pub crop: bool,
+    /// Minimum confidence a crop needs, in [0, 1]
+    pub crop_confidence: f32,
+    /// How far a pixel may differ from `background` and still count as background while
+    /// cropping (see [`BACKGROUND_THRESHOLD`])
+    pub bg_tolerance: u16,
+    /// Where to place the content on the canvas; `None` offsets it by the left/top padding
+    pub gravity: Option<Gravity>,
+    /// Interpolation used whenever the image has to be resized
+    pub filter: FilterType,
+    /// Corner radius of the output canvas in pixels; 0 keeps square corners
+    pub radius: u32,
+    /// Paint the rounded-off corners with `background` instead of making them transparent
+    pub radius_fill: bool,
+    /// Drop shadow beneath the content; the padding grows as needed to fit it
+    pub shadow: Option<Shadow>,
+    /// Frame drawn around the content inside the padding
+    pub border: Option<Border>,
+    /// Fill for the canvas instead of the solid `background`
+    pub backdrop: Option<Backdrop>,
+    /// Image stamped over the finished canvas, e.g. a logo in a corner
+    pub watermark: Option<Watermark>,
+    /// Alpha-composite the content over this color before placing it, so it comes out opaque
+    pub flatten: Option<Rgba<u8>>,
+}
+
+impl Default for PadOptions {
+    fn default() -> Self {
+        Self {
+            padding: Padding::default(),
+            background: Rgba([0, 0, 0, 0]),
+            crop: true,
+            crop_confidence: 0.0,
+            bg_tolerance: BACKGROUND_THRESHOLD,
+            gravity: None,
+            filter: FilterType::Lanczos3,
+            radius: 0,
+            radius_fill: false,
+            shadow: None,
+            border: None,
+            backdrop: None,
+            watermark: None,
+            flatten: None,
+        }
+    }
+}
+
+/// A solid frame around the content, drawn in the padding right next to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Border {
+    pub width: u32,
+    pub color: Rgba<u8>,
+    /// Radius of the frame's outer corners; the canvas itself is unaffected
+    pub radius: u32,
+}
+
+impl Border {
+    fn draw(&self, canvas: &mut RgbaImage, (x, y): (u32, u32), (w, h): (u32, u32)) {
+        if self.width == 0 {
+            return;
+        }
+        let outer = (
+            x.saturating_sub(self.width),
+            y.saturating_sub(self.width),
+            w.saturating_add(self.width.saturating_mul(2)),
+            h.saturating_add(self.width.saturating_mul(2)),
+        );
+        let (canvas_w, canvas_h) = canvas.dimensions();
+        for py in outer.1..outer.1.saturating_add(outer.3).min(canvas_h) {
+            for px in outer.0..outer.0.saturating_add(outer.2).min(canvas_w) {
+                let inside_content = (x..x + w).contains(&px) && (y..y + h).contains(&py);
+                if inside_content {
+                    continue;
+                }
+                let coverage = rounded_rect_coverage((px, py), outer, self.radius);
+                if coverage <= 0.0 {
+                    continue;
+                }
+                let alpha = (self.color[3] as f32 * coverage).round() as u8;
+                let [r, g, b, _] = self.color.0;
+                let pixel = canvas.get_pixel_mut(px, py);
+                *pixel = blend_over(Rgba([r, g, b, alpha]), *pixel);
+            }
+        }
+    }
+}
+
+/// Grows `padding` so an optional border and shadow fit around the content. The shadow
+/// is cast by the framed content, so its room is measured from the border's outer edge.
+pub fn reserve_decorations(
+    padding: Padding,
+    border: Option<&Border>,
+    shadow: Option<&Shadow>,
+) -> Padding {
+    let width = border.map_or(0, |border| border.width);
+    let outside = |side: u32| side.saturating_sub(width);
+    let mut framed = Padding {
+        top: outside(padding.top),
+        right: outside(padding.right),
+        bottom: outside(padding.bottom),
+        left: outside(padding.left),
+    };
+    if let Some(shadow) = shadow {
+        framed = shadow.reserve(framed);
+    }
+    let inside = |side: u32| side.saturating_add(width);
+    Padding {
+        top: inside(framed.top),
+        right: inside(framed.right),
+        bottom: inside(framed.bottom),
+        left: inside(framed.left),
+    }
+}
+
+/// An image alpha-blended over the finished canvas at the `gravity` side or corner.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    pub image: RgbaImage,
+    pub gravity: Gravity,
+    /// Multiplies the watermark's alpha, in [0, 1]
+    pub opacity: f32,
+    /// Width of the watermark as a fraction of the canvas width (keeping its aspect ratio);
+    /// `None` stamps it at its own size
+    pub scale: Option<f32>,
+}
+
+impl Watermark {
+    fn draw(&self, canvas: &mut RgbaImage, filter: FilterType) {
+        let (canvas_w, canvas_h) = canvas.dimensions();
+        let scaled;
+        let mark = match self.scale {
+            Some(scale) if self.image.width() > 0 => {
+                let w = ((canvas_w as f32 * scale).round() as u32).max(1);
+                let h = ((u64::from(self.image.height()) * u64::from(w)
+                    / u64::from(self.image.width())) as u32)
+                    .max(1);
+                scaled = image::imageops::resize(&self.image, w, h, filter);
+                &scaled
+            }
+            _ => &self.image,
+        };
+        let (x, y) = gravity_offset((canvas_w, canvas_h), mark.dimensions(), self.gravity);
+        for (mark_x, mark_y, pixel) in mark.enumerate_pixels() {
+            let (cx, cy) = (x + mark_x, y + mark_y);
+            if cx >= canvas_w || cy >= canvas_h {
+                continue;
+            }
+            let mut top = *pixel;
+            top[3] = (top[3] as f32 * self.opacity).round().clamp(0.0, 255.0) as u8;
+            let under = canvas.get_pixel_mut(cx, cy);
+            *under = blend_over(top, *under);
+        }
+    }
+}
+
+/// A blurred, offset silhouette of the content's bounding box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shadow {
+    /// Blur radius in pixels; the shadow fades out over this distance
+    pub blur: u32,
+    /// Shift from the content position as `(x, y)` pixels
+    pub offset: (i32, i32),
+    pub color: Rgba<u8>,
+}
+
+impl Shadow {
+    /// Grows `padding` so the blurred, offset shadow fits on the canvas without clipping.
+    pub fn reserve(&self, padding: Padding) -> Padding {
+        let blur = i64::from(self.blur);
+        let (dx, dy) = (i64::from(self.offset.0), i64::from(self.offset.1));
+        let need = |extent: i64| u32::try_from(extent.max(0)).unwrap_or(u32::MAX);
+        Padding {
+            top: padding.top.max(need(blur - dy)),
+            right: padding.right.max(need(blur + dx)),
+            bottom: padding.bottom.max(need(blur + dy)),
+            left: padding.left.max(need(blur - dx)),
+        }
+    }
+
+    /// Paints the shadow of a `content`-sized rectangle at `(x, y)` onto `canvas`.
+    fn draw(&self, canvas: &mut RgbaImage, (x, y): (u32, u32), (w, h): (u32, u32)) {
+        let (canvas_w, canvas_h) = canvas.dimensions();
+        let mut mask = GrayImage::new(canvas_w, canvas_h);
+        let left = (i64::from(x) + i64::from(self.offset.0)).clamp(0, i64::from(canvas_w)) as u32;
+        let top = (i64::from(y) + i64::from(self.offset.1)).clamp(0, i64::from(canvas_h)) as u32;
+        let right = (i64::from(left) + i64::from(w)).min(i64::from(canvas_w)) as u32;
+        let bottom = (i64::from(top) + i64::from(h)).min(i64::from(canvas_h)) as u32;
+        for my in top..bottom {
+            for mx in left..right {
+                mask.put_pixel(mx, my, Luma([255]));
+            }
+        }
+        if self.blur > 0 {
+            // Three sigmas cover the visible falloff of the blur
+            mask = image::imageops::fast_blur(&mask, self.blur as f32 / 3.0);
+        }
+
+        for (pixel, coverage) in canvas.pixels_mut().zip(mask.pixels()) {
+            if coverage[0] == 0 {
+                continue;
+            }
+            let alpha = u32::from(self.color[3]) * u32::from(coverage[0]) / 255;
+            let [r, g, b, _] = self.color.0;
+            *pixel = blend_over(Rgba([r, g, b, alpha as u8]), *pixel);
+        }
+    }
+}
+
+/// A fill painted across the whole canvas in place of the solid background.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backdrop {
+    Gradient(Gradient),
+    /// The source image scaled to cover the canvas and blurred over `radius` pixels
+    Blur {
+        radius: u32,
+    },
+    /// The content's outermost rows and columns carried out to the canvas borders
+    Extend(ExtendMode),
+    /// A texture repeated from the top-left corner, over the solid background where it is
+    /// translucent
+    Tile(RgbaImage),
+}
+
+impl Backdrop {
+    /// Paints a `w`x`h` canvas for `content` placed at `offset`, cut from `source`.
+    fn render(
+        &self,
+        (w, h): (u32, u32),
+        content: &RgbaImage,
+        offset: (u32, u32),
+        source: &RgbaImage,
+        opts: &PadOptions,
+    ) -> RgbaImage {
+        match self {
+            Self::Gradient(gradient) => gradient.render((w, h)),
+            Self::Extend(mode) => extend_edges(content, offset, (w, h), *mode),
+            Self::Tile(tile) => {
+                let mut canvas = ImageBuffer::from_pixel(w, h, opts.background);
+                let (tile_w, tile_h) = tile.dimensions();
+                if tile_w > 0 && tile_h > 0 {
+                    for y in (0..h).step_by(tile_h as usize) {
+                        for x in (0..w).step_by(tile_w as usize) {
+                            image::imageops::overlay(&mut canvas, tile, x.into(), y.into());
+                        }
+                    }
+                }
+                canvas
+            }
+            Self::Blur { radius } => {
+                let mut backdrop = cover(source, (w, h), opts.filter);
+                if *radius > 0 {
+                    backdrop = image::imageops::fast_blur(&backdrop, *radius as f32 / 3.0);
+                }
+                backdrop
+            }
+        }
+    }
+}
+
+/// How [`Backdrop::Extend`] carries the content's edges outward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExtendMode {
+    /// Repeat the nearest edge pixel (OpenCV's `BORDER_REPLICATE`)
+    #[default]
+    Clamp,
+    /// Mirror the content at its edges (OpenCV's `BORDER_REFLECT`), so texture continues
+    /// into the padding. Padding wider than the content repeats the far edge past the mirror.
+    Reflect,
+    /// Continue with the opposite side of the content (OpenCV's `BORDER_WRAP`), as if the
+    /// content were tiled
+    Wrap,
+}
+
+impl std::str::FromStr for ExtendMode {
+    type Err = PadifyError;
+
+    fn from_str(input: &str) -> std::result::Result<Self, Self::Err> {
+        match input.trim().to_ascii_lowercase().as_str() {
+            "clamp" => Ok(Self::Clamp),
+            "reflect" => Ok(Self::Reflect),
+            "wrap" => Ok(Self::Wrap),
+            _ => Err(PadifyError(format!(
+                "invalid pad mode '{input}', expected clamp, reflect or wrap"
+            ))),
+        }
+    }
+}
+
+/// A `w`x`h` canvas filled from `content` placed at `(x, y)`: outside the content every
+/// pixel takes the closest content pixel, its mirror image or the pixel a content width or
+/// height away, depending on `mode`.
+fn extend_edges(
+    content: &RgbaImage,
+    (x, y): (u32, u32),
+    (w, h): (u32, u32),
+    mode: ExtendMode,
+) -> RgbaImage {
+    let (content_w, content_h) = content.dimensions();
+    if content_w == 0 || content_h == 0 {
+        return RgbaImage::new(w, h);
+    }
+    // Maps a canvas coordinate to one inside a content side of `len` that starts at `start`
+    let source = |canvas: u32, start: u32, len: u32| {
+        let offset = i64::from(canvas) - i64::from(start);
+        let len = i64::from(len);
+        let index = match mode {
+            ExtendMode::Clamp => offset.clamp(0, len - 1),
+            ExtendMode::Reflect => {
+                let mirrored = if offset < 0 {
+                    -offset - 1
+                } else if offset >= len {
+                    2 * len - 1 - offset
+                } else {
+                    offset
+                };
+                mirrored.clamp(0, len - 1)
+            }
+            ExtendMode::Wrap => offset.rem_euclid(len),
+        };
+        index as u32
+    };
+    ImageBuffer::from_fn(w, h, |canvas_x, canvas_y| {
+        *content.get_pixel(
+            source(canvas_x, x, content_w),
+            source(canvas_y, y, content_h),
+        )
+    })
+}
+
+/// Scales `image` to fill a `w`x`h` canvas completely and crops the overflow evenly.
+fn cover(image: &RgbaImage, (w, h): (u32, u32), filter: FilterType) -> RgbaImage {
+    let (src_w, src_h) = image.dimensions();
+    if src_w == 0 || src_h == 0 {
+        return RgbaImage::new(w, h);
+    }
+    let scale = (w as f64 / src_w as f64).max(h as f64 / src_h as f64);
+    let scaled_w = ((src_w as f64 * scale).ceil() as u32).max(w);
+    let scaled_h = ((src_h as f64 * scale).ceil() as u32).max(h);
+    let scaled = image::imageops::resize(image, scaled_w, scaled_h, filter);
+    image::imageops::crop_imm(&scaled, (scaled_w - w) / 2, (scaled_h - h) / 2, w, h).to_image()
+}
+
+/// A linear gradient between two colors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gradient {
+    pub from: Rgba<u8>,
+    pub to: Rgba<u8>,
+    /// Direction in degrees as in CSS: 0 runs upwards, 90 to the right, 180 downwards
+    pub angle: f32,
+}
+
+impl Gradient {
+    /// Renders the gradient over a `w`x`h` image, with `from` and `to` in opposite corners
+    /// (or on opposite edges) along the gradient's direction.
+    pub fn render(&self, (w, h): (u32, u32)) -> RgbaImage {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        let (dx, dy) = (sin, -cos);
+        let length = (w as f32 * sin).abs() + (h as f32 * cos).abs();
+        ImageBuffer::from_fn(w, h, |x, y| {
+            let along =
+                (x as f32 + 0.5 - w as f32 / 2.0) * dx + (y as f32 + 0.5 - h as f32 / 2.0) * dy;
+            let t = if length > 0.0 {
+                along / length + 0.5
+            } else {
+                0.5
+            };
+            mix(self.from, self.to, t.clamp(0.0, 1.0))
+        })
+    }
+}
+
+impl std::str::FromStr for Gradient {
+    type Err = PadifyError;
+
+    /// Parses `#RRGGBB..#RRGGBB`, optionally followed by `@angle` (default 180, top to bottom).
+    fn from_str(input: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = |reason: String| {
+            PadifyError(format!(
+                "invalid gradient '{input}': {reason} (expected #RRGGBB..#RRGGBB[@ANGLE])"
+            ))
+        };
+        let (stops, angle) = match input.trim().split_once('@') {
+            Some((stops, angle)) => {
+                let angle = angle
+                    .trim()
+                    .trim_end_matches("deg")
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|angle| angle.is_finite())
+                    .ok_or_else(|| invalid(format!("bad angle '{angle}'")))?;
+                (stops, angle)
+            }
+            None => (input.trim(), 180.0),
+        };
+        let (from, to) = stops
+            .split_once("..")
+            .ok_or_else(|| invalid("missing '..' between the colors".into()))?;
+        let color = |stop: &str| parse_color(stop).map_err(|e| invalid(e.to_string()));
+        Ok(Self {
+            from: color(from)?,
+            to: color(to)?,
+            angle,
+        })
+    }
+}
+
+/// Interpolates between two colors with premultiplied alpha, so fading into a
+/// transparent stop doesn't drag its (invisible) color along.
+fn mix(from: Rgba<u8>, to: Rgba<u8>, t: f32) -> Rgba<u8> {
+    let from_a = from[3] as f32 / 255.0;
+    let to_a = to[3] as f32 / 255.0;
+    let alpha = from_a + (to_a - from_a) * t;
+    if alpha <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let channel = |i: usize| {
+        let premultiplied = from[i] as f32 * from_a * (1.0 - t) + to[i] as f32 * to_a * t;
+        (premultiplied / alpha).round().clamp(0.0, 255.0) as u8
+    };
+    Rgba([
+        channel(0),
+        channel(1),
+        channel(2),
+        (alpha * 255.0).round() as u8,
+    ])
+}
+
+/// Anchor for the content within the padded canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gravity {
+    Center,
+    North,
+    South,
+    East,
+    West,
+    NorthEast,
+    NorthWest,
+    SouthEast,
+    SouthWest,
+}
+
+impl std::str::FromStr for Gravity {
+    type Err = PadifyError;
+
+    fn from_str(input: &str) -> std::result::Result<Self, Self::Err> {
+        let normalized = input.trim().to_ascii_lowercase().replace(['-', '_'], "");
+        Ok(match normalized.as_str() {
+            "center" | "centre" => Self::Center,
+            "north" | "n" => Self::North,
+            "south" | "s" => Self::South,
+            "east" | "e" => Self::East,
+            "west" | "w" => Self::West,
+            "northeast" | "ne" => Self::NorthEast,
+            "northwest" | "nw" => Self::NorthWest,
+            "southeast" | "se" => Self::SouthEast,
+            "southwest" | "sw" => Self::SouthWest,
+            _ => {
+                return Err(PadifyError(format!(
+                    "invalid gravity '{input}', expected center, north, south, east, west, \
+                     northeast, northwest, southeast or southwest"
+                )));
+            }
+        })
+    }
+}
+
+/// Top-left offset that anchors `content` inside `canvas` according to `gravity`.
+/// Content larger than the canvas is pinned to the top-left edge instead of overflowing it.
+pub fn gravity_offset(
+    (canvas_w, canvas_h): (u32, u32),
+    (content_w, content_h): (u32, u32),
+    gravity: Gravity,
+) -> (u32, u32) {
+    let free_x = canvas_w.saturating_sub(content_w);
+    let free_y = canvas_h.saturating_sub(content_h);
+    let x = match gravity {
+        Gravity::West | Gravity::NorthWest | Gravity::SouthWest => 0,
+        Gravity::East | Gravity::NorthEast | Gravity::SouthEast => free_x,
+        Gravity::Center | Gravity::North | Gravity::South => free_x / 2,
+    };
+    let y = match gravity {
+        Gravity::North | Gravity::NorthEast | Gravity::NorthWest => 0,
+        Gravity::South | Gravity::SouthEast | Gravity::SouthWest => free_y,
+        Gravity::Center | Gravity::East | Gravity::West => free_y / 2,
+    };
+    (x, y)
+}
+
+/// Optionally crops `image`, then places it on a `background` canvas grown by `padding`.
+///
+/// Panics if the padded size overflows `u32`; [`padded_dimensions`] reports that as an error.
+pub fn pad_image(image: &RgbaImage, opts: &PadOptions) -> RgbaImage {
+    if opts.crop {
+        let cropped = auto_crop_partial(
+            image,
+            opts.background,
+            opts.bg_tolerance,
+            opts.crop_confidence,
+        )
+        .image;
+        pad_content(&cropped, image, opts)
+    } else {
+        pad_content(image, image, opts)
+    }
+}
+
+/// Like [`pad_image`] for `content` that the caller already cropped out of `source`
+/// (`opts.crop` is ignored). Backdrops that sample the image, such as [`Backdrop::Blur`],
+/// are drawn from the uncropped `source`.
+pub fn pad_content(content: &RgbaImage, source: &RgbaImage, opts: &PadOptions) -> RgbaImage {
+    let flattened;
+    let content = match opts.flatten {
+        Some(color) => {
+            flattened = flatten(content, color);
+            &flattened
+        }
+        None => content,
+    };
+    let padding = reserve_decorations(opts.padding, opts.border.as_ref(), opts.shadow.as_ref());
+    let size =
+        padded_dimensions(content.dimensions(), &padding).expect("padded size overflows u32");
+    let (content_w, content_h) = content.dimensions();
+    let width = opts.border.map_or(0, |border| border.width);
+    // Position the framed content so gravity keeps the border on the canvas too
+    let framed_size = (content_w + 2 * width, content_h + 2 * width);
+    let (frame_x, frame_y) = match opts.gravity {
+        Some(gravity) => gravity_offset(size, framed_size, gravity),
+        None => (padding.left - width, padding.top - width),
+    };
+    let offset = (frame_x + width, frame_y + width);
+
+    let mut canvas = match &opts.backdrop {
+        Some(backdrop) => backdrop.render(size, content, offset, source, opts),
+        None => ImageBuffer::from_pixel(size.0, size.1, opts.background),
+    };
+    if let Some(shadow) = &opts.shadow {
+        shadow.draw(&mut canvas, (frame_x, frame_y), framed_size);
+    }
+    image::imageops::replace(&mut canvas, content, offset.0.into(), offset.1.into());
+    if let Some(border) = &opts.border {
+        border.draw(&mut canvas, offset, content.dimensions());
+    }
+    if let Some(watermark) = &opts.watermark {
+        watermark.draw(&mut canvas, opts.filter);
+    }
+    let fill = opts.radius_fill.then_some(opts.background);
+    round_corners(&mut canvas, opts.radius, fill);
+    canvas
+}
+
+/// Places `left` and `right` next to each other, vertically centered, separated by a
+/// `gap`-pixel column of `divider`. The rest of the canvas is transparent.
+pub fn side_by_side(left: &RgbaImage, right: &RgbaImage, gap: u32, divider: Rgba<u8>) -> RgbaImage {
+    let width = left.width() + gap + right.width();
+    let height = left.height().max(right.height());
+    let mut canvas = RgbaImage::new(width, height);
+    image::imageops::replace(&mut canvas, left, 0, ((height - left.height()) / 2).into());
+    for y in 0..height {
+        for x in left.width()..left.width() + gap {
+            canvas.put_pixel(x, y, divider);
+        }
+    }
+    image::imageops::replace(
+        &mut canvas,
+        right,
+        (left.width() + gap).into(),
+        ((height - right.height()) / 2).into(),
+    );
+    canvas
+}
+
+/// Masks `canvas` to a rounded rectangle with anti-aliased corners. Pixels outside the
+/// shape become transparent, or are blended into `fill` when one is given. The radius
+/// is clamped to half the smaller side.
+pub fn round_corners(canvas: &mut RgbaImage, radius: u32, fill: Option<Rgba<u8>>) {
+    let (w, h) = canvas.dimensions();
+    let radius = radius.min(w / 2).min(h / 2);
+    if radius == 0 {
+        return;
+    }
+    for y in (0..radius).chain(h - radius..h) {
+        for x in (0..radius).chain(w - radius..w) {
+            let coverage = rounded_rect_coverage((x, y), (0, 0, w, h), radius);
+            if coverage >= 1.0 {
+                continue;
+            }
+            let pixel = canvas.get_pixel_mut(x, y);
+            let alpha = (pixel[3] as f32 * coverage).round() as u8;
+            let masked = Rgba([pixel[0], pixel[1], pixel[2], alpha]);
+            *pixel = match fill {
+                Some(fill) => blend_over(masked, fill),
+                None => masked,
+            };
+        }
+    }
+}
+
+/// How much of pixel `(x, y)` lies inside the rectangle `(left, top, width, height)` whose
+/// corners are rounded with `radius` (clamped to half the smaller side), in [0, 1].
+fn rounded_rect_coverage(
+    (x, y): (u32, u32),
+    (left, top, width, height): (u32, u32, u32, u32),
+    radius: u32,
+) -> f32 {
+    let inside_x = x >= left && x - left < width;
+    let inside_y = y >= top && y - top < height;
+    if !inside_x || !inside_y {
+        return 0.0;
+    }
+    let r = radius.min(width / 2).min(height / 2) as f32;
+    let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+    let (left, top) = (left as f32, top as f32);
+    let (right, bottom) = (left + width as f32, top + height as f32);
+    // Distance into a corner square along each axis; zero along the straight edges
+    let dx = (left + r - cx).max(cx - (right - r)).max(0.0);
+    let dy = (top + r - cy).max(cy - (bottom - r)).max(0.0);
+    if dx == 0.0 || dy == 0.0 {
+        return 1.0;
+    }
+    // Coverage of a one-pixel-wide band straddling the arc
+    (r - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0)
+}
+
+/// Alpha-composites `image` over a solid `color`.
+pub fn flatten(image: &RgbaImage, color: Rgba<u8>) -> RgbaImage {
+    let mut out = image.clone();
+    for pixel in out.pixels_mut() {
+        *pixel = blend_over(*pixel, color);
+    }
+    out
+}
+
+/// Alpha-composites `image` over a light and dark checkerboard of `square`-pixel cells,
+/// so transparent areas stay visible in viewers that show them as black.
+pub fn checkerboard(image: &RgbaImage, square: u32) -> RgbaImage {
+    let square = square.max(1);
+    let mut out = image.clone();
+    for (x, y, pixel) in out.enumerate_pixels_mut() {
+        let cell = if (x / square + y / square).is_multiple_of(2) {
+            Rgba([255, 255, 255, 255])
+        } else {
+            Rgba([204, 204, 204, 255])
+        };
+        *pixel = blend_over(*pixel, cell);
+    }
+    out
+}
+
+fn blend_over(top: Rgba<u8>, bottom: Rgba<u8>) -> Rgba<u8> {
+    let top_a = top[3] as f32 / 255.0;
+    let bottom_a = bottom[3] as f32 / 255.0;
+    let out_a = top_a + bottom_a * (1.0 - top_a);
+    if out_a <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let channel = |i: usize| {
+        let value = (top[i] as f32 * top_a + bottom[i] as f32 * bottom_a * (1.0 - top_a)) / out_a;
+        value.round().clamp(0.0, 255.0) as u8
+    };
+    Rgba([
+        channel(0),
+        channel(1),
+        channel(2),
+        (out_a * 255.0).round() as u8,
+    ])
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Padding {
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub left: u32,
+}
+
+impl Padding {
+    pub fn uniform(pad: u32) -> Self {
+        Self {
+            top: pad,
+            right: pad,
+            bottom: pad,
+            left: pad,
+        }
+    }
+}
+
+pub fn padded_dimensions((w, h): (u32, u32), padding: &Padding) -> Result<(u32, u32)> {
+    let new_w = w
+        .checked_add(padding.left)
+        .and_then(|v| v.checked_add(padding.right))
+        .ok_or_else(|| PadifyError("resulting width is too large".into()))?;
+    let new_h = h
+        .checked_add(padding.top)
+        .and_then(|v| v.checked_add(padding.bottom))
+        .ok_or_else(|| PadifyError("resulting height is too large".into()))?;
+    Ok((new_w, new_h))
+}
+
+/// Grows `padding` along the short axis, split evenly across both sides, until the
+/// padded size matches the `ratio_w:ratio_h` aspect ratio.
+pub fn expand_to_aspect(
+    content: (u32, u32),
+    padding: Padding,
+    (ratio_w, ratio_h): (u32, u32),
+) -> Result<Padding> {
+    let (w, h) = padded_dimensions(content, &padding)?;
+    let (w64, h64) = (w as u64, h as u64);
+    let (ratio_w, ratio_h) = (ratio_w as u64, ratio_h as u64);
+    let mut padding = padding;
+    if w64 * ratio_h < h64 * ratio_w {
+        let target = (h64 * ratio_w).div_ceil(ratio_h);
+        let extra = u32::try_from(target - w64)
+            .map_err(|_| PadifyError("resulting width is too large".into()))?;
+        padding.left += extra / 2;
+        padding.right += extra - extra / 2;
+    } else if w64 * ratio_h > h64 * ratio_w {
+        let target = (w64 * ratio_h).div_ceil(ratio_w);
+        let extra = u32::try_from(target - h64)
+            .map_err(|_| PadifyError("resulting height is too large".into()))?;
+        padding.top += extra / 2;
+        padding.bottom += extra - extra / 2;
+    }
+    padded_dimensions(content, &padding)?;
+    Ok(padding)
+}
+
+/// Moves the content so its top-left corner lands on a multiple of `step` pixels (rounded
+/// to whole pixels) from the canvas corner, trading padding between opposite sides so the
+/// canvas keeps its size. No side drops below `min`; an axis without a grid position in
+/// that range keeps its padding.
+pub fn snap_padding(padding: Padding, step: f64, min: Padding) -> Padding {
+    let (left, right) = snap_axis((padding.left, padding.right), step, (min.left, min.right));
+    let (top, bottom) = snap_axis((padding.top, padding.bottom), step, (min.top, min.bottom));
+    Padding {
+        top,
+        right,
+        bottom,
+        left,
+    }
+}
+
+fn snap_axis(
+    (before, after): (u32, u32),
+    step: f64,
+    (min_before, min_after): (u32, u32),
+) -> (u32, u32) {
+    let total = u64::from(before) + u64::from(after);
+    let (lo, hi) = (
+        u64::from(min_before),
+        total.saturating_sub(u64::from(min_after)),
+    );
+    if step <= 0.0 || lo > hi {
+        return (before, after);
+    }
+    let first = (lo as f64 / step).floor() as u64;
+    let last = (hi as f64 / step).ceil() as u64;
+    let snapped = (first..=last)
+        .map(|k| (k as f64 * step).round() as u64)
+        .filter(|position| (lo..=hi).contains(position))
+        .min_by_key(|position| position.abs_diff(u64::from(before)));
+    match snapped {
+        Some(position) => (position as u32, (total - position) as u32),
+        None => (before, after),
+    }
+}
+
+/// Grows `padding`, split evenly across both sides, until the padded size is at least
+/// `min_w`x`min_h`. Axes that are already large enough keep their padding.
+pub fn expand_to_min(
+    content: (u32, u32),
+    padding: Padding,
+    (min_w, min_h): (u32, u32),
+) -> Result<Padding> {
+    let (w, h) = padded_dimensions(content, &padding)?;
+    let mut padding = padding;
+    let extra_w = min_w.saturating_sub(w);
+    padding.left += extra_w / 2;
+    padding.right += extra_w - extra_w / 2;
+    let extra_h = min_h.saturating_sub(h);
+    padding.top += extra_h / 2;
+    padding.bottom += extra_h - extra_h / 2;
+    Ok(padding)
+}
+
+/// Grows `padding`, split evenly across both sides, until both padded dimensions are
+/// multiples of `step`. A step of 0 or 1 leaves it unchanged.
+pub fn round_up_to_multiple(content: (u32, u32), padding: Padding, step: u32) -> Result<Padding> {
+    if step <= 1 {
+        return Ok(padding);
+    }
+    let (w, h) = padded_dimensions(content, &padding)?;
+    let round = |side: u32, axis: &str| {
+        side.div_ceil(step)
+            .checked_mul(step)
+            .ok_or_else(|| PadifyError(format!("resulting {axis} is too large")))
+    };
+    expand_to_min(content, padding, (round(w, "width")?, round(h, "height")?))
+}
+
+/// Padding that centers `content` on a canvas of exactly `target` pixels, without scaling.
+pub fn padding_to_size((w, h): (u32, u32), (target_w, target_h): (u32, u32)) -> Result<Padding> {
+    if w > target_w || h > target_h {
+        return Err(Box::new(PadifyError(format!(
+            "content is {w}x{h}, larger than the target size {target_w}x{target_h}"
+        ))));
+    }
+    let (extra_w, extra_h) = (target_w - w, target_h - h);
+    Ok(Padding {
+        top: extra_h / 2,
+        right: extra_w - extra_w / 2,
+        bottom: extra_h - extra_h / 2,
+        left: extra_w / 2,
+    })
+}
+
+pub fn auto_pad(value: u32, ratio: f32, min: u32, max: u32) -> u32 {
+    let scaled = ((value as f32) * ratio).round() as u32;
+    clamp_u32(scaled, min, max)
+}
+
+/// Solves `(w + 2p) * (h + 2p) = w * h / coverage` for the uniform padding `p`.
+pub fn coverage_pad((w, h): (u32, u32), coverage: f32) -> Result<u32> {
+    if !(coverage > 0.0 && coverage <= 1.0) {
+        return Err(Box::new(PadifyError(
+            "coverage must be greater than 0 and at most 1".into(),
+        )));
+    }
+    let (w, h) = (w as f64, h as f64);
+    let sum = w + h;
+    let area_term = w * h * (1.0 - 1.0 / coverage as f64);
+    let pad = (-sum + (sum * sum - 4.0 * area_term).sqrt()) / 4.0;
+    Ok(pad.max(0.0).round() as u32)
+}
+
+pub fn percent_pad(value: u32, percent: f32) -> Result<u32> {
+    if !percent.is_finite() || percent < 0.0 {
+        return Err(Box::new(PadifyError(
+            "pad-percent must be a non-negative number".into(),
+        )));
+    }
+    Ok((value as f64 * percent as f64 / 100.0).round() as u32)
+}
+
+pub fn parse_color(input: &str) -> Result<Rgba<u8>> {
+    let trimmed = input.trim();
+    if trimmed.eq_ignore_ascii_case("transparent") {
+        return Ok(Rgba([0, 0, 0, 0]));
+    }
+    if let Some((_, [r, g, b])) = CSS_COLORS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(trimmed))
+    {
+        return Ok(Rgba([*r, *g, *b, 255]));
+    }
+
+    let hex = trimmed.strip_prefix('#').unwrap_or(trimmed);
+    if hex.len() != 6 && hex.len() != 8 {
+        return Err(Box::new(PadifyError(
+            "color must be #RRGGBB, #RRGGBBAA, a CSS color name, or 'transparent'".into(),
+        )));
+    }
+
+    let r = parse_hex_byte(&hex[0..2])?;
+    let g = parse_hex_byte(&hex[2..4])?;
+    let b = parse_hex_byte(&hex[4..6])?;
+    let a = if hex.len() == 8 {
+        parse_hex_byte(&hex[6..8])?
+    } else {
+        255
+    };
+
+    Ok(Rgba([r, g, b, a]))
+}
+
+/// The CSS named colors (CSS Color Module Level 4), which [`parse_color`] accepts by name.
+const CSS_COLORS: &[(&str, [u8; 3])] = &[
+    ("aliceblue", [0xf0, 0xf8, 0xff]),
+    ("antiquewhite", [0xfa, 0xeb, 0xd7]),
+    ("aqua", [0x00, 0xff, 0xff]),
+    ("aquamarine", [0x7f, 0xff, 0xd4]),
+    ("azure", [0xf0, 0xff, 0xff]),
+    ("beige", [0xf5, 0xf5, 0xdc]),
+    ("bisque", [0xff, 0xe4, 0xc4]),
+    ("black", [0x00, 0x00, 0x00]),
+    ("blanchedalmond", [0xff, 0xeb, 0xcd]),
+    ("blue", [0x00, 0x00, 0xff]),
+    ("blueviolet", [0x8a, 0x2b, 0xe2]),
+    ("brown", [0xa5, 0x2a, 0x2a]),
+    ("burlywood", [0xde, 0xb8, 0x87]),
+    ("cadetblue", [0x5f, 0x9e, 0xa0]),
+    ("chartreuse", [0x7f, 0xff, 0x00]),
+    ("chocolate", [0xd2, 0x69, 0x1e]),
+    ("coral", [0xff, 0x7f, 0x50]),
+    ("cornflowerblue", [0x64, 0x95, 0xed]),
+    ("cornsilk", [0xff, 0xf8, 0xdc]),
+    ("crimson", [0xdc, 0x14, 0x3c]),
+    ("cyan", [0x00, 0xff, 0xff]),
+    ("darkblue", [0x00, 0x00, 0x8b]),
+    ("darkcyan", [0x00, 0x8b, 0x8b]),
+    ("darkgoldenrod", [0xb8, 0x86, 0x0b]),
+    ("darkgray", [0xa9, 0xa9, 0xa9]),
+    ("darkgreen", [0x00, 0x64, 0x00]),
+    ("darkgrey", [0xa9, 0xa9, 0xa9]),
+    ("darkkhaki", [0xbd, 0xb7, 0x6b]),
+    ("darkmagenta", [0x8b, 0x00, 0x8b]),
+    ("darkolivegreen", [0x55, 0x6b, 0x2f]),
+    ("darkorange", [0xff, 0x8c, 0x00]),
+    ("darkorchid", [0x99, 0x32, 0xcc]),
+    ("darkred", [0x8b, 0x00, 0x00]),
+    ("darksalmon", [0xe9, 0x96, 0x7a]),
+    ("darkseagreen", [0x8f, 0xbc, 0x8f]),
+    ("darkslateblue", [0x48, 0x3d, 0x8b]),
+    ("darkslategray", [0x2f, 0x4f, 0x4f]),
+    ("darkslategrey", [0x2f, 0x4f, 0x4f]),
+    ("darkturquoise", [0x00, 0xce, 0xd1]),
+    ("darkviolet", [0x94, 0x00, 0xd3]),
+    ("deeppink", [0xff, 0x14, 0x93]),
+    ("deepskyblue", [0x00, 0xbf, 0xff]),
+    ("dimgray", [0x69, 0x69, 0x69]),
+    ("dimgrey", [0x69, 0x69, 0x69]),
+    ("dodgerblue", [0x1e, 0x90, 0xff]),
+    ("firebrick", [0xb2, 0x22, 0x22]),
+    ("floralwhite", [0xff, 0xfa, 0xf0]),
+    ("forestgreen", [0x22, 0x8b, 0x22]),
+    ("fuchsia", [0xff, 0x00, 0xff]),
+    ("gainsboro", [0xdc, 0xdc, 0xdc]),
+    ("ghostwhite", [0xf8, 0xf8, 0xff]),
+    ("gold", [0xff, 0xd7, 0x00]),
+    ("goldenrod", [0xda, 0xa5, 0x20]),
+    ("gray", [0x80, 0x80, 0x80]),
+    ("green", [0x00, 0x80, 0x00]),
+    ("greenyellow", [0xad, 0xff, 0x2f]),
+    ("grey", [0x80, 0x80, 0x80]),
+    ("honeydew", [0xf0, 0xff, 0xf0]),
+    ("hotpink", [0xff, 0x69, 0xb4]),
+    ("indianred", [0xcd, 0x5c, 0x5c]),
+    ("indigo", [0x4b, 0x00, 0x82]),
+    ("ivory", [0xff, 0xff, 0xf0]),
+    ("khaki", [0xf0, 0xe6, 0x8c]),
+    ("lavender", [0xe6, 0xe6, 0xfa]),
+    ("lavenderblush", [0xff, 0xf0, 0xf5]),
+    ("lawngreen", [0x7c, 0xfc, 0x00]),
+    ("lemonchiffon", [0xff, 0xfa, 0xcd]),
+    ("lightblue", [0xad, 0xd8, 0xe6]),
+    ("lightcoral", [0xf0, 0x80, 0x80]),
+    ("lightcyan", [0xe0, 0xff, 0xff]),
+    ("lightgoldenrodyellow", [0xfa, 0xfa, 0xd2]),
+    ("lightgray", [0xd3, 0xd3, 0xd3]),
+    ("lightgreen", [0x90, 0xee, 0x90]),
+    ("lightgrey", [0xd3, 0xd3, 0xd3]),
+    ("lightpink", [0xff, 0xb6, 0xc1]),
+    ("lightsalmon", [0xff, 0xa0, 0x7a]),
+    ("lightseagreen", [0x20, 0xb2, 0xaa]),
+    ("lightskyblue", [0x87, 0xce, 0xfa]),
+    ("lightslategray", [0x77, 0x88, 0x99]),
+    ("lightslategrey", [0x77, 0x88, 0x99]),
+    ("lightsteelblue", [0xb0, 0xc4, 0xde]),
+    ("lightyellow", [0xff, 0xff, 0xe0]),
+    ("lime", [0x00, 0xff, 0x00]),
+    ("limegreen", [0x32, 0xcd, 0x32]),
+    ("linen", [0xfa, 0xf0, 0xe6]),
+    ("magenta", [0xff, 0x00, 0xff]),
+    ("maroon", [0x80, 0x00, 0x00]),
+    ("mediumaquamarine", [0x66, 0xcd, 0xaa]),
+    ("mediumblue", [0x00, 0x00, 0xcd]),
+    ("mediumorchid", [0xba, 0x55, 0xd3]),
+    ("mediumpurple", [0x93, 0x70, 0xdb]),
+    ("mediumseagreen", [0x3c, 0xb3, 0x71]),
+    ("mediumslateblue", [0x7b, 0x68, 0xee]),
+    ("mediumspringgreen", [0x00, 0xfa, 0x9a]),
+    ("mediumturquoise", [0x48, 0xd1, 0xcc]),
+    ("mediumvioletred", [0xc7, 0x15, 0x85]),
+    ("midnightblue", [0x19, 0x19, 0x70]),
+    ("mintcream", [0xf5, 0xff, 0xfa]),
+    ("mistyrose", [0xff, 0xe4, 0xe1]),
+    ("moccasin", [0xff, 0xe4, 0xb5]),
+    ("navajowhite", [0xff, 0xde, 0xad]),
+    ("navy", [0x00, 0x00, 0x80]),
+    ("oldlace", [0xfd, 0xf5, 0xe6]),
+    ("olive", [0x80, 0x80, 0x00]),
+    ("olivedrab", [0x6b, 0x8e, 0x23]),
+    ("orange", [0xff, 0xa5, 0x00]),
+    ("orangered", [0xff, 0x45, 0x00]),
+    ("orchid", [0xda, 0x70, 0xd6]),
+    ("palegoldenrod", [0xee, 0xe8, 0xaa]),
+    ("palegreen", [0x98, 0xfb, 0x98]),
+    ("paleturquoise", [0xaf, 0xee, 0xee]),
+    ("palevioletred", [0xdb, 0x70, 0x93]),
+    ("papayawhip", [0xff, 0xef, 0xd5]),
+    ("peachpuff", [0xff, 0xda, 0xb9]),
+    ("peru", [0xcd, 0x85, 0x3f]),
+    ("pink", [0xff, 0xc0, 0xcb]),
+    ("plum", [0xdd, 0xa0, 0xdd]),
+    ("powderblue", [0xb0, 0xe0, 0xe6]),
+    ("purple", [0x80, 0x00, 0x80]),
+    ("rebeccapurple", [0x66, 0x33, 0x99]),
+    ("red", [0xff, 0x00, 0x00]),
+    ("rosybrown", [0xbc, 0x8f, 0x8f]),
+    ("royalblue", [0x41, 0x69, 0xe1]),
+    ("saddlebrown", [0x8b, 0x45, 0x13]),
+    ("salmon", [0xfa, 0x80, 0x72]),
+    ("sandybrown", [0xf4, 0xa4, 0x60]),
+    ("seagreen", [0x2e, 0x8b, 0x57]),
+    ("seashell", [0xff, 0xf5, 0xee]),
+    ("sienna", [0xa0, 0x52, 0x2d]),
+    ("silver", [0xc0, 0xc0, 0xc0]),
+    ("skyblue", [0x87, 0xce, 0xeb]),
+    ("slateblue", [0x6a, 0x5a, 0xcd]),
+    ("slategray", [0x70, 0x80, 0x90]),
+    ("slategrey", [0x70, 0x80, 0x90]),
+    ("snow", [0xff, 0xfa, 0xfa]),
+    ("springgreen", [0x00, 0xff, 0x7f]),
+    ("steelblue", [0x46, 0x82, 0xb4]),
+    ("tan", [0xd2, 0xb4, 0x8c]),
+    ("teal", [0x00, 0x80, 0x80]),
+    ("thistle", [0xd8, 0xbf, 0xd8]),
+    ("tomato", [0xff, 0x63, 0x47]),
+    ("turquoise", [0x40, 0xe0, 0xd0]),
+    ("violet", [0xee, 0x82, 0xee]),
+    ("wheat", [0xf5, 0xde, 0xb3]),
+    ("white", [0xff, 0xff, 0xff]),
+    ("whitesmoke", [0xf5, 0xf5, 0xf5]),
+    ("yellow", [0xff, 0xff, 0x00]),
+    ("yellowgreen", [0x9a, 0xcd, 0x32]),
+];
+
+fn parse_hex_byte(s: &str) -> Result<u8> {
+    u8::from_str_radix(s, 16).map_err(|_| {
+        Box::new(PadifyError(format!(
+            "invalid color component '{s}', expected hex"
+        ))) as Box<dyn std::error::Error>
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CropReport {
+    pub original_height: u32,
+    pub new_height: u32,
+    /// Why the bottom was (or wasn't) trimmed
+    pub reason: &'static str,
+    /// How sure the heuristic is that the trimmed rows are an artifact, in [0, 1]
+    pub confidence: f32,
+    /// Rows trimmed from the top, i.e. where the cropped image starts in the original
+    pub top: u32,
+    /// Why the top was (or wasn't) trimmed, e.g. `partial_line_top`
+    pub top_reason: &'static str,
+    pub original_width: u32,
+    pub new_width: u32,
+    /// Columns trimmed from the left, i.e. where the cropped image starts in the original
+    pub left: u32,
+    /// Why the left and right edges were (or weren't) trimmed; `not_checked` unless
+    /// [`crop_partial_columns`] ran
+    pub column_reason: &'static str,
+}
+
+#[derive(Debug, Clone)]
+pub struct CropResult {
+    pub image: RgbaImage,
+    pub report: CropReport,
+}
+
+impl CropResult {
+    pub fn no_crop(image: RgbaImage, reason: &'static str) -> Self {
+        let (original_width, original_height) = image.dimensions();
+        Self {
+            image,
+            report: CropReport {
+                original_height,
+                new_height: original_height,
+                reason,
+                confidence: 0.0,
+                top: 0,
+                top_reason: reason,
+                original_width,
+                new_width: original_width,
+                left: 0,
+                column_reason: "not_checked",
+            },
+        }
+    }
+
+    /// Crops `image` to `new_height` rows unless `confidence` is below `min_confidence`,
+    /// in which case the image is kept intact and reported as `low_confidence`.
+    fn cropped(
+        image: &RgbaImage,
+        new_height: u32,
+        reason: &'static str,
+        confidence: f32,
+        min_confidence: f32,
+    ) -> Self {
+        let original_height = image.height();
+        if confidence < min_confidence {
+            let mut result = Self::no_crop(image.clone(), "low_confidence");
+            result.report.confidence = confidence;
+            result.report.top_reason = "not_checked";
+            return result;
+        }
+        let cropped = image::imageops::crop_imm(image, 0, 0, image.width(), new_height).to_image();
+        Self {
+            image: cropped,
+            report: CropReport {
+                original_height,
+                new_height,
+                reason,
+                confidence,
+                top: 0,
+                top_reason: "not_checked",
+                original_width: image.width(),
+                new_width: image.width(),
+                left: 0,
+                column_reason: "not_checked",
+            },
+        }
+    }
+}
+
+/// Trims partial lines and cursor residue from the bottom like [`auto_crop_bottom_partial`],
+/// then runs the same analysis upside down to trim them from the top as well. Top crops are
+/// reported as `partial_line_top` or `cursor_residue_top`, with their offset in `top`.
+pub fn auto_crop_partial(
+    image: &RgbaImage,
+    bg: Rgba<u8>,
+    tolerance: u16,
+    min_confidence: f32,
+) -> CropResult {
+    let mut result = auto_crop_bottom_partial(image, bg, tolerance, min_confidence);
+    let flipped = image::imageops::flip_vertical(&result.image);
+    let (w, h) = result.image.dimensions();
+    result.report.top_reason = match detect_bottom_partial(&flipped, bg, tolerance) {
+        Err(reason) => reason,
+        Ok((_, _, confidence)) if confidence < min_confidence => "low_confidence",
+        Ok((kept, reason, confidence)) => {
+            let top = h - kept;
+            // Report the weaker of the two crops
+            let bottom_cropped = h < result.report.original_height;
+            result.report.confidence = if bottom_cropped {
+                result.report.confidence.min(confidence)
+            } else {
+                confidence
+            };
+            result.image = image::imageops::crop_imm(&result.image, 0, top, w, kept).to_image();
+            result.report.new_height = kept;
+            result.report.top = top;
+            match reason {
+                "partial_line" => "partial_line_top",
+                _ => "cursor_residue_top",
+            }
+        }
+    };
+    result
+}
+
+/// Trims thin partial column blocks, such as residue from a neighbouring pane in a
+/// side-by-side terminal capture, from the right and left edges of an already row-cropped
+/// `result`. Each edge is analysed like the bottom in [`auto_crop_bottom_partial`], on the
+/// image turned so that edge is at the bottom. Crops are reported as `partial_column`, with
+/// the left offset in `left` (relative to the original, like `top`).
+pub fn crop_partial_columns(
+    result: CropResult,
+    bg: Rgba<u8>,
+    tolerance: u16,
+    min_confidence: f32,
+) -> CropResult {
+    let CropResult {
+        mut image,
+        mut report,
+    } = result;
+    let mut cropped = report.new_height < report.original_height;
+    let mut reason = "clean";
+    // Turned clockwise the rightmost column is the bottom row; counter-clockwise the leftmost
+    for right in [true, false] {
+        let turned = if right {
+            image::imageops::rotate90(&image)
+        } else {
+            image::imageops::rotate270(&image)
+        };
+        match detect_bottom_partial(&turned, bg, tolerance) {
+            Ok((_, _, confidence)) if confidence < min_confidence => {
+                if reason != "partial_column" {
+                    reason = "low_confidence";
+                }
+            }
+            Ok((kept, _, confidence)) => {
+                let (w, h) = image.dimensions();
+                let x = if right { 0 } else { w - kept };
+                image = image::imageops::crop_imm(&image, x, 0, kept, h).to_image();
+                report.left += x;
+                // Report the weakest of the crops made
+                report.confidence = if cropped {
+                    report.confidence.min(confidence)
+                } else {
+                    confidence
+                };
+                cropped = true;
+                reason = "partial_column";
+            }
+            Err(_) => {}
+        }
+    }
+    report.new_width = image.width();
+    report.column_reason = reason;
+    CropResult { image, report }
+}
+
+/// Trims a partial last line or cursor residue from the bottom of `image`. Pixels within
+/// `tolerance` of `bg` count as background (see [`BACKGROUND_THRESHOLD`]).
+pub fn auto_crop_bottom_partial(
+    image: &RgbaImage,
+    bg: Rgba<u8>,
+    tolerance: u16,
+    min_confidence: f32,
+) -> CropResult {
+    match detect_bottom_partial(image, bg, tolerance) {
+        Ok((new_height, reason, confidence)) => {
+            CropResult::cropped(image, new_height, reason, confidence, min_confidence)
+        }
+        Err(reason) => CropResult::no_crop(image.clone(), reason),
+    }
+}
+
+/// Finds rows at the bottom of `image` that look like a cut-off artifact, as the height to
+/// keep, the reason and the confidence; or why nothing should be trimmed.
+fn detect_bottom_partial(
+    image: &RgbaImage,
+    bg: Rgba<u8>,
+    tolerance: u16,
+) -> std::result::Result<(u32, &'static str, f32), &'static str> {
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return Err("empty");
+    }
+
+    let major_threshold = 0.02f32;
+    let minor_threshold = 0.005f32;
+
+    let ratios = line_ratios(image, bg, tolerance, false);
+
+    let major_rows: Vec<bool> = ratios.iter().map(|&r| r > major_threshold).collect();
+    let minor_rows: Vec<bool> = ratios.iter().map(|&r| r > minor_threshold).collect();
+
+    let mut clusters: Vec<(u32, u32)> = Vec::new();
+    let mut in_cluster = false;
+    let mut start = 0u32;
+    for (i, &has_content) in major_rows.iter().enumerate() {
+        if has_content && !in_cluster {
+            start = i as u32;
+            in_cluster = true;
+        } else if !has_content && in_cluster {
+            let end = i.saturating_sub(1) as u32;
+            clusters.push((start, end));
+            in_cluster = false;
+        }
+    }
+    if in_cluster {
+        clusters.push((start, h.saturating_sub(1)));
+    }
+
+    if clusters.is_empty() {
+        return Err("no_clusters");
+    }
+
+    let bottom_margin_major = major_rows
+        .iter()
+        .rev()
+        .take_while(|&&has_content| !has_content)
+        .count() as u32;
+
+    let mut heights: Vec<u32> = clusters
+        .iter()
+        .take(clusters.len().saturating_sub(1))
+        .map(|(s, e)| e.saturating_sub(*s) + 1)
+        .filter(|h| *h >= 4)
+        .collect();
+
+    if heights.is_empty() {
+        heights = clusters
+            .iter()
+            .map(|(s, e)| e.saturating_sub(*s) + 1)
+            .collect();
+    }
+
+    let median = median_u32(&mut heights).unwrap_or(0.0);
+
+    if clusters.len() >= 2 && bottom_margin_major <= 2 && median > 0.0 {
+        let (last_start, last_end) = *clusters.last().unwrap();
+        let last_height = last_end.saturating_sub(last_start) + 1;
+        if (last_height as f32) < median * 0.7 && last_start > 0 {
+            // The shorter the last line is compared to a typical one, the likelier it was cut off
+            let confidence = 1.0 - last_height as f32 / median;
+            return Ok((last_start, "partial_line", confidence));
+        }
+    }
+
+    let last_major = major_rows.iter().rposition(|&v| v);
+    let last_minor = minor_rows.iter().rposition(|&v| v);
+    if let (Some(last_major), Some(last_minor)) = (last_major, last_minor)
+        && last_minor > last_major
+    {
+        let mut start_minor = last_minor;
+        while start_minor > 0 && minor_rows[start_minor - 1] {
+            start_minor -= 1;
+        }
+        let block_height = (last_minor - start_minor + 1) as u32;
+        let gap = start_minor.saturating_sub(last_major + 1) as u32;
+        let line_height = if median > 0.0 {
+            median
+        } else {
+            clamp_u32(h / 30, 12, 28) as f32
+        };
+        let thin_block = (block_height as f32) < line_height * 0.35;
+        let min_gap = std::cmp::max(2, (line_height * 0.2).round() as u32);
+        let gap_ok = gap >= min_gap || (thin_block && gap >= 1);
+        if gap_ok && (block_height as f32) < line_height * 0.6 {
+            let confidence = 1.0 - block_height as f32 / line_height;
+            return Ok((start_minor as u32, "cursor_residue", confidence));
+        }
+    }
+
+    Err("clean")
+}
+
+/// Default difference from the background, summed over the four RGBA channel differences
+/// (so 0 to 1020), up to which a pixel counts as background
+pub const BACKGROUND_THRESHOLD: u16 = 18;
+
+/// Fraction of non-background pixels in each row, or in each column when `columns` is set.
+/// Long lines are sampled at a stride of up to 400 pixels per line.
+fn line_ratios(image: &RgbaImage, bg: Rgba<u8>, tolerance: u16, columns: bool) -> Vec<f32> {
+    let (w, h) = image.dimensions();
+    let (lines, length) = if columns { (w, h) } else { (h, w) };
+    let stride = std::cmp::max(1, length / 400) as usize;
+    (0..lines)
+        .map(|line| {
+            let mut samples = 0u32;
+            let mut non_bg = 0u32;
+            for along in (0..length).step_by(stride) {
+                samples += 1;
+                let (x, y) = if columns {
+                    (line, along)
+                } else {
+                    (along, line)
+                };
+                if !is_background(*image.get_pixel(x, y), bg, tolerance) {
+                    non_bg += 1;
+                }
+            }
+            if samples == 0 {
+                0.0
+            } else {
+                non_bg as f32 / samples as f32
+            }
+        })
+        .collect()
+}
+
+/// Bounding box `(x, y, width, height)` of the pixels that differ from `bg` by more than
+/// `tolerance` (summed over RGBA), found by scanning inward from each edge. `None` when the
+/// whole image is background.
+pub fn trim_bounds(
+    image: &RgbaImage,
+    bg: Rgba<u8>,
+    tolerance: u16,
+) -> Option<(u32, u32, u32, u32)> {
+    let (w, h) = image.dimensions();
+    let row_is_bg = |y: u32| (0..w).all(|x| is_background(*image.get_pixel(x, y), bg, tolerance));
+    let top = (0..h).find(|&y| !row_is_bg(y))?;
+    let bottom = (top..h).rev().find(|&y| !row_is_bg(y))?;
+    let column_is_bg =
+        |x: u32| (top..=bottom).all(|y| is_background(*image.get_pixel(x, y), bg, tolerance));
+    let left = (0..w).find(|&x| !column_is_bg(x))?;
+    let right = (left..w).rev().find(|&x| !column_is_bg(x))?;
+    Some((left, top, right - left + 1, bottom - top + 1))
+}
+
+/// Removes background-only rows and columns from all four sides (like ImageMagick's
+/// `-trim`). An image that is all background is returned unchanged rather than emptied.
+pub fn trim(image: &RgbaImage, bg: Rgba<u8>, tolerance: u16) -> RgbaImage {
+    match trim_bounds(image, bg, tolerance) {
+        Some((x, y, w, h)) => image::imageops::crop_imm(image, x, y, w, h).to_image(),
+        None => image.clone(),
+    }
+}
+
+/// How far [`smart_pad`] may move each side away from the base padding, as a fraction of it
+const SMART_RANGE: f32 = 0.3;
+
+/// Rebalances `base` so edges where content crowds the border get more padding and
+/// emptier edges get less, each side staying within ±30% of its base value.
+///
+/// Crowding is the share of non-background pixels in a band along each edge
+/// (a twentieth of the image), compared across the four edges.
+pub fn smart_pad(image: &RgbaImage, bg: Rgba<u8>, base: Padding) -> Padding {
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return base;
+    }
+    let band_density = |ratios: &[f32], from_end: bool| {
+        let band = (ratios.len() / 20).max(1);
+        let lines = if from_end {
+            &ratios[ratios.len() - band..]
+        } else {
+            &ratios[..band]
+        };
+        lines.iter().sum::<f32>() / band as f32
+    };
+    let rows = line_ratios(image, bg, BACKGROUND_THRESHOLD, false);
+    let columns = line_ratios(image, bg, BACKGROUND_THRESHOLD, true);
+    let density = [
+        band_density(&rows, false),
+        band_density(&columns, true),
+        band_density(&rows, true),
+        band_density(&columns, false),
+    ];
+    let max = density.iter().copied().fold(f32::MIN, f32::max);
+    let min = density.iter().copied().fold(f32::MAX, f32::min);
+    let spread = max - min;
+    if spread < 0.01 {
+        return base;
+    }
+    let mean = density.iter().sum::<f32>() / 4.0;
+    let adjust = |side: u32, density: f32| {
+        let factor = 1.0 + SMART_RANGE * ((density - mean) / spread).clamp(-1.0, 1.0);
+        (side as f32 * factor).round() as u32
+    };
+    Padding {
+        top: adjust(base.top, density[0]),
+        right: adjust(base.right, density[1]),
+        bottom: adjust(base.bottom, density[2]),
+        left: adjust(base.left, density[3]),
+    }
+}
+
+fn is_background(pixel: Rgba<u8>, bg: Rgba<u8>, threshold: u16) -> bool {
+    let dr = (pixel[0] as i16 - bg[0] as i16).unsigned_abs();
+    let dg = (pixel[1] as i16 - bg[1] as i16).unsigned_abs();
+    let db = (pixel[2] as i16 - bg[2] as i16).unsigned_abs();
+    let da = (pixel[3] as i16 - bg[3] as i16).unsigned_abs();
+    dr + dg + db + da <= threshold
+}
+
+fn median_u32(values: &mut [u32]) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    let mid = values.len() / 2;
+    if values.len() % 2 == 1 {
+        Some(values[mid] as f32)
+    } else {
+        Some((values[mid - 1] as f32 + values[mid] as f32) / 2.0)
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct Bucket {
+    count: u32,
+    sum_r: u64,
+    sum_g: u64,
+    sum_b: u64,
+    sum_a: u64,
+}
+
+impl Bucket {
+    /// The average color of the pixels in the bucket.
+    fn color(&self) -> Rgba<u8> {
+        let count = u64::from(self.count.max(1));
+        Rgba([
+            (self.sum_r / count) as u8,
+            (self.sum_g / count) as u8,
+            (self.sum_b / count) as u8,
+            (self.sum_a / count) as u8,
+        ])
+    }
+}
+
+pub fn deduce_background(image: &RgbaImage) -> Rgba<u8> {
+    detect_background(image).color
+}
+
+/// The background [`detect_background`] settled on, with how sure it is about it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackgroundEstimate {
+    pub color: Rgba<u8>,
+    /// Share (0 to 1) of the sampled pixels that back the pick: the dominant color's share
+    /// of the opaque samples, or the transparent share when the border is mostly
+    /// transparent. `0.0` when nothing was confident enough and transparent was assumed.
+    /// For [`edge_average_background`], the share of border samples that were averaged.
+    pub confidence: f32,
+    /// How the color was picked: "border" (the border's dominant color), "transparent"
+    /// (a mostly transparent border), "overall" (the whole image's dominant color, when the
+    /// border had no clear winner), "edge_average" or "fallback" (transparent, for lack of
+    /// a confident pick)
+    pub source: &'static str,
+    /// Share (0 to 1) of the sampled border pixels that are fully transparent
+    pub transparent_ratio: f32,
+}
+
+/// Steps between sampled pixels so that about 200x200 of them are looked at.
+fn sample_strides((w, h): (u32, u32)) -> (usize, usize) {
+    (
+        std::cmp::max(1, w / 200) as usize,
+        std::cmp::max(1, h / 200) as usize,
+    )
+}
+
+/// The `n` most common colors of `image` (sampled, with similar shades grouped together),
+/// most common first, each with its share of the non-transparent samples. Fully transparent
+/// pixels are left out.
+pub fn dominant_colors(image: &RgbaImage, n: usize) -> Vec<(Rgba<u8>, f32)> {
+    let (stride_x, stride_y) = sample_strides(image.dimensions());
+    let sample = dominant_sample(image, stride_x, stride_y, |_x, _y| true);
+    let non_transparent = sample.total.saturating_sub(sample.transparent);
+    sample
+        .buckets
+        .iter()
+        .take(n)
+        .map(|bucket| (bucket.color(), bucket.count as f32 / non_transparent as f32))
+        .collect()
+}
+
+/// Like [`deduce_background`], but also reports the confidence of the detection.
+pub fn detect_background(image: &RgbaImage) -> BackgroundEstimate {
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return BackgroundEstimate::fallback(1.0);
+    }
+
+    let (stride_x, stride_y) = sample_strides((w, h));
+    let border = border_sample(image);
+    let transparent_ratio = border.transparent_ratio();
+
+    if let Some(color) = border.color_if_confident(0.2) {
+        return BackgroundEstimate {
+            color,
+            confidence: border.dominant_ratio(),
+            source: "border",
+            transparent_ratio,
+        };
+    }
+
+    if transparent_ratio >= 0.6 {
+        return BackgroundEstimate {
+            color: Rgba([0, 0, 0, 0]),
+            confidence: transparent_ratio,
+            source: "transparent",
+            transparent_ratio,
+        };
+    }
+
+    let overall = dominant_sample(image, stride_x, stride_y, |_x, _y| true);
+    match overall.color_if_confident(0.1) {
+        Some(color) => BackgroundEstimate {
+            color,
+            confidence: overall.dominant_ratio(),
+            source: "overall",
+            transparent_ratio,
+        },
+        None => BackgroundEstimate::fallback(transparent_ratio),
+    }
+}
+
+impl BackgroundEstimate {
+    /// Transparent, with no confidence, for when no color could be picked.
+    fn fallback(transparent_ratio: f32) -> Self {
+        Self {
+            color: Rgba([0, 0, 0, 0]),
+            confidence: 0.0,
+            source: "fallback",
+            transparent_ratio,
+        }
+    }
+}
+
+struct SampleResult {
+    total: u32,
+    transparent: u32,
+    /// Color buckets of the non-transparent samples, most common first
+    buckets: Vec<Bucket>,
+}
+
+impl SampleResult {
+    fn color_if_confident(&self, threshold: f32) -> Option<Rgba<u8>> {
+        let bucket = self.buckets.first()?;
+        if self.dominant_ratio() < threshold {
+            return None;
+        }
+        Some(bucket.color())
+    }
+
+    /// Share of the non-transparent samples that fall in the dominant bucket.
+    fn dominant_ratio(&self) -> f32 {
+        let non_transparent = self.total.saturating_sub(self.transparent);
+        match self.buckets.first() {
+            Some(bucket) if non_transparent > 0 => bucket.count as f32 / non_transparent as f32,
+            _ => 0.0,
+        }
+    }
+
+    fn transparent_ratio(&self) -> f32 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        self.transparent as f32 / self.total as f32
+    }
+}
+
+/// Samples the band along all four edges of `image` (a twentieth of the smaller side,
+/// 8 to 64 pixels wide) that background detection looks at.
+fn border_sample(image: &RgbaImage) -> SampleResult {
+    let (w, h) = image.dimensions();
+    let (stride_x, stride_y) = sample_strides((w, h));
+    let band = clamp_u32(std::cmp::min(w, h) / 20, 8, 64);
+    dominant_sample(image, stride_x, stride_y, |x, y| {
+        x < band || x >= w.saturating_sub(band) || y < band || y >= h.saturating_sub(band)
+    })
+}
+
+/// Like [`detect_background`], but averages the border band instead of picking its most
+/// common color, which suits subtle gradients where no single color dominates. Transparent
+/// pixels are left out of the average; the confidence is the share of border samples that
+/// went into it, and a mostly transparent border gives transparent.
+pub fn edge_average_background(image: &RgbaImage) -> BackgroundEstimate {
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return BackgroundEstimate::fallback(1.0);
+    }
+    let border = border_sample(image);
+    let transparent_ratio = border.transparent_ratio();
+    if transparent_ratio >= 0.6 {
+        return BackgroundEstimate {
+            color: Rgba([0, 0, 0, 0]),
+            confidence: transparent_ratio,
+            source: "transparent",
+            transparent_ratio,
+        };
+    }
+    let mut sum = Bucket::default();
+    for bucket in &border.buckets {
+        sum.count += bucket.count;
+        sum.sum_r += bucket.sum_r;
+        sum.sum_g += bucket.sum_g;
+        sum.sum_b += bucket.sum_b;
+        sum.sum_a += bucket.sum_a;
+    }
+    BackgroundEstimate {
+        color: sum.color(),
+        confidence: sum.count as f32 / border.total as f32,
+        source: "edge_average",
+        transparent_ratio,
+    }
+}
+
+fn dominant_sample<F>(
+    image: &RgbaImage,
+    stride_x: usize,
+    stride_y: usize,
+    include: F,
+) -> SampleResult
+where
+    F: Fn(u32, u32) -> bool,
+{
+    let (w, h) = image.dimensions();
+    let mut buckets: HashMap<u32, Bucket> = HashMap::new();
+    let mut total: u32 = 0;
+    let mut transparent: u32 = 0;
+
+    for y in (0..h).step_by(stride_y) {
+        for x in (0..w).step_by(stride_x) {
+            if !include(x, y) {
+                continue;
+            }
+            let pixel = image.get_pixel(x, y);
+            total = total.saturating_add(1);
+            if pixel[3] <= 5 {
+                transparent = transparent.saturating_add(1);
+                continue;
+            }
+
+            let key = quantize_key(*pixel);
+            let entry = buckets.entry(key).or_default();
+            entry.count = entry.count.saturating_add(1);
+            entry.sum_r += pixel[0] as u64;
+            entry.sum_g += pixel[1] as u64;
+            entry.sum_b += pixel[2] as u64;
+            entry.sum_a += pixel[3] as u64;
+        }
+    }
+
+    // Ties go to the lower key so the order doesn't depend on hashing
+    let mut buckets: Vec<(u32, Bucket)> = buckets.into_iter().collect();
+    buckets.sort_unstable_by_key(|&(key, bucket)| (std::cmp::Reverse(bucket.count), key));
+
+    SampleResult {
+        total,
+        transparent,
+        buckets: buckets.into_iter().map(|(_, bucket)| bucket).collect(),
+    }
+}
+
+fn clamp_u32(value: u32, min: u32, max: u32) -> u32 {
+    if value < min {
+        min
+    } else if value > max {
+        max
+    } else {
+        value
+    }
+}
+
+fn quantize_key(pixel: Rgba<u8>) -> u32 {
+    let r = (pixel[0] >> 3) as u32;
+    let g = (pixel[1] >> 3) as u32;
+    let b = (pixel[2] >> 3) as u32;
+    let a = (pixel[3] >> 3) as u32;
+    (r << 15) | (g << 10) | (b << 5) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkerboard_shows_through_transparency_only() {
+        let mut image = RgbaImage::from_pixel(4, 2, Rgba([0, 0, 0, 0]));
+        image.put_pixel(3, 1, Rgba([255, 0, 0, 255]));
+        let preview = checkerboard(&image, 2);
+        assert_eq!(*preview.get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+        assert_eq!(*preview.get_pixel(2, 1), Rgba([204, 204, 204, 255]));
+        assert_eq!(*preview.get_pixel(3, 1), Rgba([255, 0, 0, 255]));
+        // The input keeps its alpha
+        assert_eq!(image.get_pixel(0, 0)[3], 0);
+    }
+
+    #[test]
+    fn pad_image_places_content_inside_padding() {
+        let content = RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 255]));
+        let opts = PadOptions {
+            padding: Padding {
+                top: 1,
+                right: 2,
+                bottom: 3,
+                left: 4,
+            },
+            background: Rgba([0, 0, 0, 255]),
+            crop: false,
+            ..PadOptions::default()
+        };
+        let padded = pad_image(&content, &opts);
+        assert_eq!(padded.dimensions(), (8, 6));
+        assert_eq!(*padded.get_pixel(4, 1), Rgba([255, 255, 255, 255]));
+        assert_eq!(*padded.get_pixel(3, 1), Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn rounds_corners_with_soft_edges() {
+        let mut canvas = RgbaImage::from_pixel(40, 30, Rgba([255, 0, 0, 255]));
+        round_corners(&mut canvas, 50, None);
+        // Radius is clamped to 15: corners are cut, edge midpoints untouched, the arc partial
+        assert_eq!(canvas.get_pixel(0, 0)[3], 0);
+        assert_eq!(canvas.get_pixel(39, 29)[3], 0);
+        assert_eq!(canvas.get_pixel(20, 0)[3], 255);
+        assert!(canvas.get_pixel(0, 15)[3] >= 250);
+        let edge = canvas.get_pixel(4, 4)[3];
+        assert!(
+            edge > 0 && edge < 255,
+            "expected partial coverage, got {edge}"
+        );
+
+        let mut unchanged = RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255]));
+        round_corners(&mut unchanged, 0, None);
+        assert!(unchanged.pixels().all(|p| *p == Rgba([1, 2, 3, 255])));
+    }
+
+    #[test]
+    fn shadow_reserves_room_and_darkens_below_content() {
+        let shadow = Shadow {
+            blur: 4,
+            offset: (0, 6),
+            color: Rgba([0, 0, 0, 255]),
+        };
+        assert_eq!(
+            shadow.reserve(Padding::uniform(2)),
+            Padding {
+                top: 2,
+                right: 4,
+                bottom: 10,
+                left: 4,
+            }
+        );
+
+        let content = RgbaImage::from_pixel(10, 10, Rgba([255, 255, 255, 255]));
+        let opts = PadOptions {
+            padding: Padding::uniform(2),
+            background: Rgba([200, 200, 200, 255]),
+            crop: false,
+            shadow: Some(shadow),
+            ..PadOptions::default()
+        };
+        let padded = pad_image(&content, &opts);
+        assert_eq!(padded.dimensions(), (18, 22));
+        // Just below the content the shadow darkens the background; far above it does not
+        assert!(padded.get_pixel(9, 14)[0] < 200);
+        assert_eq!(padded.get_pixel(9, 0)[0], 200);
+        assert_eq!(*padded.get_pixel(9, 5), Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn border_frames_content_on_transparent_canvas() {
+        let content = RgbaImage::from_pixel(4, 4, Rgba([255, 255, 255, 255]));
+        let opts = PadOptions {
+            padding: Padding::uniform(1),
+            crop: false,
+            border: Some(Border {
+                width: 2,
+                color: Rgba([255, 0, 0, 255]),
+                radius: 0,
+            }),
+            ..PadOptions::default()
+        };
+        let padded = pad_image(&content, &opts);
+        // Padding grows to fit the border, which hugs the content
+        assert_eq!(padded.dimensions(), (8, 8));
+        assert_eq!(*padded.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(*padded.get_pixel(1, 3), Rgba([255, 0, 0, 255]));
+        assert_eq!(*padded.get_pixel(2, 2), Rgba([255, 255, 255, 255]));
+
+        let opts = PadOptions {
+            padding: Padding::uniform(3),
+            ..opts
+        };
+        let padded = pad_image(&content, &opts);
+        assert_eq!(padded.get_pixel(0, 0)[3], 0);
+        assert_eq!(*padded.get_pixel(1, 1), Rgba([255, 0, 0, 255]));
+
+        let no_border = PadOptions {
+            border: Some(Border {
+                width: 0,
+                color: Rgba([255, 0, 0, 255]),
+                radius: 4,
+            }),
+            ..opts.clone()
+        };
+        assert_eq!(
+            pad_image(&content, &no_border),
+            pad_image(
+                &content,
+                &PadOptions {
+                    border: None,
+                    ..opts
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parses_css_color_names_before_hex() {
+        let color = |input: &str| parse_color(input).expect("valid color");
+        assert_eq!(color("white"), Rgba([255, 255, 255, 255]));
+        assert_eq!(color(" CornflowerBlue "), Rgba([100, 149, 237, 255]));
+        assert_eq!(color("#11223380"), Rgba([0x11, 0x22, 0x33, 0x80]));
+        assert_eq!(color("transparent"), Rgba([0, 0, 0, 0]));
+        // "beaded" is valid hex, not a name
+        assert_eq!(color("beaded"), Rgba([0xbe, 0xad, 0xed, 255]));
+        let error = parse_color("notacolor").expect_err("neither name nor hex");
+        assert!(error.to_string().contains("CSS color name"));
+    }
+
+    #[test]
+    fn renders_gradients_with_alpha() {
+        let gradient: Gradient = "#000000..#ffffff".parse().expect("valid gradient");
+        assert_eq!(gradient.angle, 180.0);
+        let vertical = gradient.render((1, 4));
+        assert!(vertical.get_pixel(0, 0)[0] < 40);
+        assert!(vertical.get_pixel(0, 3)[0] > 215);
+
+        let fade: Gradient = "#ff000000..#0000ff@90".parse().expect("valid gradient");
+        let horizontal = fade.render((3, 1));
+        // The transparent red stop contributes no color, only less alpha
+        assert_eq!(horizontal.get_pixel(1, 0).0, [0, 0, 255, 128]);
+        assert!("#000000-#ffffff".parse::<Gradient>().is_err());
+        assert!("#000000..#ffffff@up".parse::<Gradient>().is_err());
+    }
+
+    #[test]
+    fn blurred_backdrop_covers_canvas_from_uncropped_source() {
+        // Red top half, blue bottom half; the "content" is only the red part
+        let source = ImageBuffer::from_fn(20, 10, |_, y| {
+            if y < 5 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        });
+        let content = image::imageops::crop_imm(&source, 0, 0, 20, 5).to_image();
+        let opts = PadOptions {
+            padding: Padding::uniform(10),
+            crop: false,
+            backdrop: Some(Backdrop::Blur { radius: 0 }),
+            ..PadOptions::default()
+        };
+        let padded = pad_content(&content, &source, &opts);
+        assert_eq!(padded.dimensions(), (40, 25));
+        assert!(padded.pixels().all(|p| p[3] == 255));
+        assert_eq!(padded.get_pixel(0, 24).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn extend_backdrop_replicates_content_edges() {
+        let mut content = RgbaImage::from_pixel(2, 2, Rgba([0, 0, 255, 255]));
+        content.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        content.put_pixel(1, 1, Rgba([0, 255, 0, 255]));
+        let opts = PadOptions {
+            padding: Padding {
+                top: 2,
+                right: 1,
+                bottom: 3,
+                left: 2,
+            },
+            crop: false,
+            backdrop: Some(Backdrop::Extend(ExtendMode::Clamp)),
+            ..PadOptions::default()
+        };
+        let padded = pad_content(&content, &content, &opts);
+        assert_eq!(padded.dimensions(), (5, 7));
+        assert_eq!(*padded.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(*padded.get_pixel(4, 6), Rgba([0, 255, 0, 255]));
+        assert_eq!(*padded.get_pixel(4, 0), Rgba([0, 0, 255, 255]));
+        assert_eq!(*padded.get_pixel(0, 6), Rgba([0, 0, 255, 255]));
+        assert_eq!(*padded.get_pixel(2, 0), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn wrap_backdrop_tiles_content_and_reflect_clamps_past_the_mirror() {
+        let content = RgbaImage::from_fn(2, 1, |x, _| Rgba([10 * (x as u8 + 1), 0, 0, 255]));
+        let row = |mode| {
+            let opts = PadOptions {
+                padding: Padding {
+                    top: 0,
+                    right: 3,
+                    bottom: 0,
+                    left: 3,
+                },
+                crop: false,
+                backdrop: Some(Backdrop::Extend(mode)),
+                ..PadOptions::default()
+            };
+            let padded = pad_content(&content, &content, &opts);
+            (0..padded.width())
+                .map(|x| padded.get_pixel(x, 0)[0])
+                .collect::<Vec<u8>>()
+        };
+        assert_eq!(row(ExtendMode::Wrap), [20, 10, 20, 10, 20, 10, 20, 10]);
+        assert_eq!(row(ExtendMode::Reflect), [20, 20, 10, 10, 20, 20, 10, 10]);
+    }
+
+    #[test]
+    fn flatten_makes_translucent_content_opaque_before_padding() {
+        let mut content = RgbaImage::from_pixel(2, 1, Rgba([255, 0, 0, 255]));
+        content.put_pixel(1, 0, Rgba([0, 0, 0, 0]));
+        let opts = PadOptions {
+            padding: Padding::uniform(1),
+            background: Rgba([0, 0, 255, 255]),
+            crop: false,
+            flatten: Some(Rgba([255, 255, 255, 255])),
+            ..PadOptions::default()
+        };
+        let padded = pad_content(&content, &content, &opts);
+        assert_eq!(*padded.get_pixel(1, 1), Rgba([255, 0, 0, 255]));
+        // Without flattening the hole would show through to transparency, not the margin color
+        assert_eq!(*padded.get_pixel(2, 1), Rgba([255, 255, 255, 255]));
+        assert_eq!(*padded.get_pixel(0, 0), Rgba([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn reflect_backdrop_mirrors_content_at_its_edges() {
+        let mut content = RgbaImage::new(3, 1);
+        for (x, value) in [(0, 10), (1, 20), (2, 30)] {
+            content.put_pixel(x, 0, Rgba([value, 0, 0, 255]));
+        }
+        let opts = PadOptions {
+            padding: Padding {
+                top: 0,
+                right: 4,
+                bottom: 0,
+                left: 2,
+            },
+            crop: false,
+            backdrop: Some(Backdrop::Extend(ExtendMode::Reflect)),
+            ..PadOptions::default()
+        };
+        let padded = pad_content(&content, &content, &opts);
+        let row: Vec<u8> = (0..padded.width())
+            .map(|x| padded.get_pixel(x, 0)[0])
+            .collect();
+        assert_eq!(row, [20, 10, 10, 20, 30, 30, 20, 10, 10]);
+    }
+
+    #[test]
+    fn tile_backdrop_repeats_over_background() {
+        let mut tile = RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]));
+        tile.put_pixel(1, 1, Rgba([0, 0, 0, 0]));
+        let content = RgbaImage::from_pixel(1, 1, Rgba([0, 255, 0, 255]));
+        let opts = PadOptions {
+            padding: Padding::uniform(2),
+            background: Rgba([0, 0, 255, 255]),
+            crop: false,
+            backdrop: Some(Backdrop::Tile(tile)),
+            ..PadOptions::default()
+        };
+        let padded = pad_content(&content, &content, &opts);
+        assert_eq!(padded.dimensions(), (5, 5));
+        assert_eq!(*padded.get_pixel(4, 4), Rgba([255, 0, 0, 255]));
+        assert_eq!(*padded.get_pixel(3, 3), Rgba([0, 0, 255, 255]));
+        assert_eq!(*padded.get_pixel(1, 1), Rgba([0, 0, 255, 255]));
+        assert_eq!(*padded.get_pixel(2, 2), Rgba([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn watermark_blends_into_the_chosen_corner() {
+        let content = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+        let opts = PadOptions {
+            padding: Padding::uniform(2),
+            background: Rgba([255, 255, 255, 255]),
+            crop: false,
+            filter: FilterType::Nearest,
+            watermark: Some(Watermark {
+                image: RgbaImage::from_pixel(2, 1, Rgba([255, 0, 0, 255])),
+                gravity: Gravity::SouthEast,
+                opacity: 0.5,
+                scale: None,
+            }),
+            ..PadOptions::default()
+        };
+        let padded = pad_content(&content, &content, &opts);
+        assert_eq!(*padded.get_pixel(7, 7), Rgba([255, 127, 127, 255]));
+        assert_eq!(*padded.get_pixel(6, 7), Rgba([255, 127, 127, 255]));
+        assert_eq!(*padded.get_pixel(5, 7), Rgba([255, 255, 255, 255]));
+        assert_eq!(*padded.get_pixel(7, 6), Rgba([255, 255, 255, 255]));
+
+        // Scaled to half the canvas width, keeping the 2:1 aspect ratio
+        let scaled = PadOptions {
+            watermark: Some(Watermark {
+                opacity: 1.0,
+                scale: Some(0.5),
+                gravity: Gravity::NorthWest,
+                ..opts.watermark.clone().unwrap()
+            }),
+            ..opts
+        };
+        let padded = pad_content(&content, &content, &scaled);
+        assert_eq!(*padded.get_pixel(3, 1), Rgba([255, 0, 0, 255]));
+        assert_eq!(*padded.get_pixel(3, 2), Rgba([0, 0, 0, 255]));
+        assert_eq!(*padded.get_pixel(4, 0), Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn smart_pad_favors_crowded_edges() {
+        let bg = Rgba([255, 255, 255, 255]);
+        // Dark content flush against the left edge, vertically centered
+        let image = ImageBuffer::from_fn(100, 60, |x, y| {
+            if x < 50 && (20..40).contains(&y) {
+                Rgba([0, 0, 0, 255])
+            } else {
+                bg
+            }
+        });
+        let padding = smart_pad(&image, bg, Padding::uniform(100));
+        assert!(padding.left > padding.right);
+        assert!(padding.left <= 130 && padding.right >= 70);
+        assert_eq!(padding.top, padding.bottom);
+
+        let blank = ImageBuffer::from_pixel(10, 10, bg);
+        assert_eq!(
+            smart_pad(&blank, bg, Padding::uniform(8)),
+            Padding::uniform(8)
+        );
+    }
+
+    #[test]
+    fn snaps_offsets_to_grid_without_resizing() {
+        let padding = Padding {
+            top: 20,
+            right: 30,
+            bottom: 44,
+            left: 50,
+        };
+        // 0.125in at 300 DPI
+        let snapped = snap_padding(padding, 37.5, Padding::default());
+        assert_eq!((snapped.left, snapped.right), (38, 42));
+        assert_eq!((snapped.top, snapped.bottom), (38, 26));
+
+        // No grid position leaves 28px on both sides vertically
+        let kept = snap_padding(padding, 37.5, Padding::uniform(28));
+        assert_eq!((kept.left, kept.right), (38, 42));
+        assert_eq!((kept.top, kept.bottom), (20, 44));
+        let no_room = snap_padding(Padding::uniform(5), 100.0, Padding::uniform(4));
+        assert_eq!(no_room, Padding::uniform(5));
+    }
+
+    #[test]
+    fn expands_to_minimum_size() {
+        let padding = expand_to_min((40, 100), Padding::uniform(10), (128, 64)).expect("fits");
+        assert_eq!(
+            padded_dimensions((40, 100), &padding).expect("fits"),
+            (128, 120)
+        );
+        assert_eq!((padding.left, padding.right), (44, 44));
+        assert_eq!((padding.top, padding.bottom), (10, 10));
+    }
+
+    #[test]
+    fn rounds_padded_size_up_to_a_multiple() {
+        let padding = round_up_to_multiple((37, 20), Padding::uniform(4), 16).expect("fits");
+        assert_eq!(
+            padded_dimensions((37, 20), &padding).expect("fits"),
+            (48, 32)
+        );
+        assert_eq!((padding.left, padding.right), (5, 6));
+        assert_eq!((padding.top, padding.bottom), (6, 6));
+        let unchanged = round_up_to_multiple((37, 20), Padding::uniform(4), 1).expect("fits");
+        assert_eq!(unchanged, Padding::uniform(4));
+    }
+
+    #[test]
+    fn composes_side_by_side_with_divider() {
+        let left = ImageBuffer::from_pixel(2, 4, Rgba([255, 0, 0, 255]));
+        let right = ImageBuffer::from_pixel(3, 2, Rgba([0, 0, 255, 255]));
+        let divider = Rgba([128, 128, 128, 255]);
+        let canvas = side_by_side(&left, &right, 1, divider);
+        assert_eq!(canvas.dimensions(), (6, 4));
+        assert_eq!(*canvas.get_pixel(2, 0), divider);
+        assert_eq!(canvas.get_pixel(3, 0)[3], 0);
+        assert_eq!(canvas.get_pixel(3, 1).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn pads_to_exact_size_or_refuses() {
+        let padding = padding_to_size((301, 200), (800, 600)).expect("content fits");
+        assert_eq!(
+            padding_to_size((301, 200), (800, 600)).ok(),
+            Some(Padding {
+                top: 200,
+                right: 250,
+                bottom: 200,
+                left: 249,
+            })
+        );
+        assert_eq!(
+            padded_dimensions((301, 200), &padding).expect("fits"),
+            (800, 600)
+        );
+        assert!(padding_to_size((900, 200), (800, 600)).is_err());
+    }
+
+    #[test]
+    fn gravity_anchors_content_and_clamps() {
+        assert_eq!(
+            gravity_offset((100, 60), (20, 10), Gravity::Center),
+            (40, 25)
+        );
+        assert_eq!(
+            gravity_offset((100, 60), (20, 10), Gravity::SouthEast),
+            (80, 50)
+        );
+        assert_eq!(gravity_offset((100, 60), (20, 10), Gravity::North), (40, 0));
+        assert_eq!(
+            gravity_offset((10, 10), (20, 30), Gravity::SouthEast),
+            (0, 0)
+        );
+        assert_eq!(
+            "north-west".parse::<Gravity>().ok(),
+            Some(Gravity::NorthWest)
+        );
+        assert!("up".parse::<Gravity>().is_err());
+    }
+
+    #[test]
+    fn blends_translucent_pixels_over_background() {
+        assert_eq!(
+            blend_over(Rgba([255, 0, 0, 128]), Rgba([0, 0, 255, 255])),
+            Rgba([128, 0, 127, 255])
+        );
+        assert_eq!(
+            blend_over(Rgba([10, 20, 30, 0]), Rgba([0, 0, 0, 0])),
+            Rgba([0, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn padded_dimensions_add_each_side() {
+        let padding = Padding {
+            top: 10,
+            right: 20,
+            bottom: 30,
+            left: 40,
+        };
+        assert_eq!(
+            padded_dimensions((100, 50), &padding).expect("fits"),
+            (160, 90)
+        );
+        assert!(padded_dimensions((u32::MAX, 1), &Padding::uniform(1)).is_err());
+    }
+
+    #[test]
+    fn percent_padding_rounds_to_pixels() {
+        assert_eq!(percent_pad(200, 7.5).expect("valid percent"), 15);
+        assert_eq!(percent_pad(101, 10.0).expect("valid percent"), 10);
+        assert!(percent_pad(100, -1.0).is_err());
+    }
+
+    #[test]
+    fn square_expands_short_side_evenly() {
+        let padding = expand_to_aspect((100, 41), Padding::uniform(10), (1, 1)).expect("fits");
+        assert_eq!(
+            padded_dimensions((100, 41), &padding).expect("fits"),
+            (120, 120)
+        );
+        assert_eq!((padding.top, padding.bottom), (39, 40));
+        let already = expand_to_aspect((50, 50), Padding::uniform(5), (1, 1)).expect("fits");
+        assert_eq!(already, Padding::uniform(5));
+    }
+
+    #[test]
+    fn low_confidence_crop_keeps_image_intact() {
+        let bg = Rgba([0, 0, 0, 255]);
+        let mut image = RgbaImage::from_pixel(100, 50, bg);
+        // Three full lines of text and a short one cut off by the bottom edge
+        for (start, height) in [(2, 10), (16, 10), (30, 10), (46, 4)] {
+            for y in start..start + height {
+                for x in 0..100 {
+                    image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+                }
+            }
+        }
+
+        let result = auto_crop_bottom_partial(&image, bg, BACKGROUND_THRESHOLD, 0.5);
+        assert_eq!(result.report.reason, "partial_line");
+        assert_eq!(result.report.new_height, 46);
+        assert!((result.report.confidence - 0.6).abs() < 1e-6);
+
+        let result = auto_crop_bottom_partial(&image, bg, BACKGROUND_THRESHOLD, 0.8);
+        assert_eq!(result.report.reason, "low_confidence");
+        assert_eq!(result.image.height(), 50);
+    }
+
+    #[test]
+    fn crops_partial_columns_after_rows() {
+        let bg = Rgba([0, 0, 0, 255]);
+        let mut image = RgbaImage::from_pixel(62, 56, bg);
+        // Three full columns of text and a sliver of the next pane at the right edge, below a
+        // line cut off by the top edge
+        for (start, width) in [(4, 12), (20, 12), (36, 12), (59, 3)] {
+            for x in start..start + width {
+                for y in 8..56 {
+                    image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+                }
+            }
+        }
+        for x in 0..62 {
+            for y in 0..3 {
+                image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+            }
+        }
+
+        let rows = auto_crop_partial(&image, bg, BACKGROUND_THRESHOLD, 0.0);
+        assert_eq!(rows.report.top, 3);
+        let result = crop_partial_columns(rows, bg, BACKGROUND_THRESHOLD, 0.0);
+        assert_eq!(result.report.column_reason, "partial_column");
+        assert_eq!((result.report.left, result.report.top), (0, 3));
+        assert_eq!(result.report.new_width, 59);
+        assert_eq!(result.image.dimensions(), (59, 53));
+
+        let clean = CropResult::no_crop(result.image.clone(), "disabled");
+        let again = crop_partial_columns(clean, bg, BACKGROUND_THRESHOLD, 0.0);
+        assert_eq!(again.report.column_reason, "clean");
+        assert_eq!(again.image.dimensions(), (59, 53));
+    }
+
+    #[test]
+    fn crops_partial_lines_at_both_ends() {
+        let bg = Rgba([0, 0, 0, 255]);
+        let mut image = RgbaImage::from_pixel(100, 56, bg);
+        // A line cut off by the top edge, three full lines, and one cut off at the bottom
+        for (start, height) in [(0, 3), (8, 10), (22, 10), (36, 10), (52, 4)] {
+            for y in start..start + height {
+                for x in 0..100 {
+                    image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+                }
+            }
+        }
+
+        let result = auto_crop_partial(&image, bg, BACKGROUND_THRESHOLD, 0.0);
+        assert_eq!(result.report.reason, "partial_line");
+        assert_eq!(result.report.top_reason, "partial_line_top");
+        assert_eq!(result.report.top, 3);
+        assert_eq!(result.report.new_height, 49);
+        assert_eq!(result.image.height(), 49);
+        assert_eq!(result.image.get_pixel(0, 5).0, [255, 255, 255, 255]);
+
+        let bottom_only = auto_crop_bottom_partial(&image, bg, BACKGROUND_THRESHOLD, 0.0);
+        assert_eq!(bottom_only.report.top, 0);
+        assert_eq!(bottom_only.image.height(), 52);
+    }
+
+    #[test]
+    fn bg_tolerance_ignores_compression_noise() {
+        let bg = Rgba([0, 0, 0, 255]);
+        let mut image = RgbaImage::from_pixel(100, 56, bg);
+        for (start, height) in [(8, 10), (22, 10), (36, 10)] {
+            for y in start..start + height {
+                for x in 0..100 {
+                    image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+                }
+            }
+        }
+        // Faint JPEG-like ringing under the last line, 10 off per channel
+        for y in 50..56 {
+            for x in 0..100 {
+                image.put_pixel(x, y, Rgba([10, 10, 10, 255]));
+            }
+        }
+
+        let strict = auto_crop_bottom_partial(&image, bg, BACKGROUND_THRESHOLD, 0.0);
+        assert_eq!(strict.report.reason, "partial_line");
+        assert!(strict.image.height() < 56);
+        let tolerant = auto_crop_bottom_partial(&image, bg, 40, 0.0);
+        assert_eq!(tolerant.report.reason, "clean");
+        assert_eq!(tolerant.image.height(), 56);
+    }
+
+    #[test]
+    fn background_confidence_drops_with_busy_borders() {
+        let plain = RgbaImage::from_pixel(100, 100, Rgba([10, 20, 30, 255]));
+        let estimate = detect_background(&plain);
+        assert_eq!(estimate.color, Rgba([10, 20, 30, 255]));
+        assert!((estimate.confidence - 1.0).abs() < 1e-6);
+        assert_eq!(estimate.source, "border");
+        assert_eq!(estimate.transparent_ratio, 0.0);
+
+        let mut busy = plain.clone();
+        for (x, y, pixel) in busy.enumerate_pixels_mut() {
+            if (x / 10 + y / 10) % 3 == 0 {
+                *pixel = Rgba([240, 240, 240, 255]);
+            }
+        }
+        let busy_estimate = detect_background(&busy);
+        assert_eq!(busy_estimate.color, Rgba([10, 20, 30, 255]));
+        assert!(busy_estimate.confidence < 0.8);
+        assert_eq!(deduce_background(&busy), busy_estimate.color);
+
+        let clear = detect_background(&RgbaImage::new(40, 40));
+        assert_eq!(
+            (clear.source, clear.transparent_ratio),
+            ("transparent", 1.0)
+        );
+    }
+
+    #[test]
+    fn edge_average_smooths_gradient_backgrounds() {
+        // A left-to-right gradient from 100 to 139 gray with opaque content in the middle
+        let mut image = RgbaImage::from_fn(160, 160, |x, _| {
+            let v = 100 + (x / 4) as u8;
+            Rgba([v, v, v, 255])
+        });
+        for y in 60..100 {
+            for x in 60..100 {
+                image.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+            }
+        }
+        let estimate = edge_average_background(&image);
+        let [r, g, b, a] = estimate.color.0;
+        assert!((115..=125).contains(&r), "got {r}");
+        assert_eq!((g, b, a), (r, r, 255));
+        assert!((estimate.confidence - 1.0).abs() < 1e-6);
+
+        let transparent = RgbaImage::new(40, 40);
+        assert_eq!(
+            edge_average_background(&transparent).color,
+            Rgba([0, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn trims_uniform_borders_but_never_to_nothing() {
+        let bg = Rgba([255, 255, 255, 255]);
+        let mut image = RgbaImage::from_pixel(20, 10, bg);
+        image.put_pixel(3, 2, Rgba([0, 0, 0, 255]));
+        image.put_pixel(15, 6, Rgba([0, 0, 0, 255]));
+        image.put_pixel(18, 8, Rgba([250, 250, 250, 255]));
+        assert_eq!(
+            trim_bounds(&image, bg, BACKGROUND_THRESHOLD),
+            Some((3, 2, 13, 5))
+        );
+        assert_eq!(trim_bounds(&image, bg, 0), Some((3, 2, 16, 7)));
+        assert_eq!(trim(&image, bg, BACKGROUND_THRESHOLD).dimensions(), (13, 5));
+
+        let blank = RgbaImage::from_pixel(20, 10, bg);
+        assert_eq!(trim_bounds(&blank, bg, BACKGROUND_THRESHOLD), None);
+        assert_eq!(
+            trim(&blank, bg, BACKGROUND_THRESHOLD).dimensions(),
+            (20, 10)
+        );
+    }
+
+    #[test]
+    fn lists_dominant_colors_by_share() {
+        let mut image = RgbaImage::from_pixel(10, 10, Rgba([20, 40, 60, 255]));
+        for y in 0..3 {
+            for x in 0..10 {
+                image.put_pixel(x, y, Rgba([200, 0, 0, 255]));
+            }
+        }
+        for x in 0..10 {
+            image.put_pixel(x, 9, Rgba([0, 0, 0, 0]));
+        }
+        let colors = dominant_colors(&image, 5);
+        assert_eq!(colors.len(), 2);
+        assert_eq!(colors[0].0, Rgba([20, 40, 60, 255]));
+        assert!((colors[0].1 - 6.0 / 9.0).abs() < 1e-6);
+        assert_eq!(colors[1].0, Rgba([200, 0, 0, 255]));
+        assert_eq!(dominant_colors(&image, 1).len(), 1);
+        assert!(dominant_colors(&RgbaImage::new(4, 4), 3).is_empty());
+    }
+
+    #[test]
+    fn coverage_padding_matches_target_area() {
+        assert_eq!(coverage_pad((300, 200), 0.5).expect("valid coverage"), 50);
+        assert_eq!(coverage_pad((300, 200), 1.0).expect("valid coverage"), 0);
+        assert!(coverage_pad((300, 200), 0.0).is_err());
+        assert!(coverage_pad((300, 200), 1.5).is_err());
+    }
+}
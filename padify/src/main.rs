@@ -1,7 +1,34 @@
-use clap::Parser;
-use image::{DynamicImage, ImageBuffer, Rgba, RgbaImage};
+use clap::{Parser, ValueEnum};
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::tiff::TiffEncoder;
+#[cfg(feature = "webp")]
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{
+    AnimationDecoder, DynamicImage, Frame, GenericImageView, ImageDecoder, ImageEncoder,
+    ImageFormat, Rgba, RgbaImage,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use padify::{
+    BACKGROUND_THRESHOLD, Backdrop, BackgroundEstimate, Border, CropReport, CropResult, ExtendMode,
+    Gradient, Gravity, PadOptions, Padding, PadifyError, Result, Shadow, Watermark,
+    auto_crop_partial, auto_pad, checkerboard, coverage_pad, crop_partial_columns,
+    detect_background, dominant_colors, edge_average_background, expand_to_aspect, expand_to_min,
+    flatten, gravity_offset, pad_content, padded_dimensions, padding_to_size, parse_color,
+    percent_pad, reserve_decorations, round_up_to_multiple, side_by_side, smart_pad, snap_padding,
+    trim_bounds,
+};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Cursor, IsTerminal, Seek, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use walkdir::WalkDir;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -10,12 +37,20 @@ use std::path::{Path, PathBuf};
     about = "Add padding to images with auto padding and background."
 )]
 struct Args {
-    /// Input image path (png, jpg, etc.)
-    input: PathBuf,
+    /// Input image(s) or directories, optionally followed by an output path when padding a
+    /// single image. With several inputs each output goes to <input>_pad.<ext>.
+    #[arg(value_name = "INPUT [OUTPUT]", required_unless_present = "from_file")]
+    paths: Vec<PathBuf>,
 
-    /// Output path (defaults to <input>_pad.<ext>)
+    /// Write the padded image to PATH, even when it already exists (a single input only)
+    #[arg(short, long, value_name = "PATH", conflicts_with_all = ["from_file", "out_dir"])]
     output: Option<PathBuf>,
 
+    /// Also pad the inputs listed in LIST, one path per line (blank lines and lines starting
+    /// with # are skipped); relative paths are resolved against LIST's directory
+    #[arg(long, value_name = "LIST")]
+    from_file: Option<PathBuf>,
+
     /// Horizontal padding in pixels (left/right). If set, vertical padding matches it.
     #[arg(long, value_name = "PX", conflicts_with = "all")]
     pad_x: Option<u32>,
@@ -28,479 +63,2488 @@ struct Args {
     #[arg(long, value_name = "PX", alias = "pad")]
     all: Option<u32>,
 
-    /// Background color: "auto", "transparent", or hex (#RRGGBB or #RRGGBBAA)
+    /// Padding as a percentage of the smaller image dimension (e.g. 7.5)
+    #[arg(long, value_name = "PERCENT", conflicts_with_all = ["all", "pad_x", "pad_y", "coverage"])]
+    pad_percent: Option<f32>,
+
+    /// Top padding in pixels (overrides the symmetric value for this side)
+    #[arg(long, value_name = "PX")]
+    pad_top: Option<u32>,
+
+    /// Right padding in pixels (overrides the symmetric value for this side)
+    #[arg(long, value_name = "PX")]
+    pad_right: Option<u32>,
+
+    /// Bottom padding in pixels (overrides the symmetric value for this side)
+    #[arg(long, value_name = "PX")]
+    pad_bottom: Option<u32>,
+
+    /// Left padding in pixels (overrides the symmetric value for this side)
+    #[arg(long, value_name = "PX")]
+    pad_left: Option<u32>,
+
+    /// Pick padding so the content covers this fraction of the canvas area, in (0, 1]
+    #[arg(
+        long,
+        value_name = "RATIO",
+        conflicts_with_all = ["all", "pad_x", "pad_y", "pad_top", "pad_right", "pad_bottom", "pad_left"]
+    )]
+    coverage: Option<f32>,
+
+    /// Automatic padding as a fraction of the smaller image dimension (auto mode only)
+    #[arg(
+        long,
+        value_name = "RATIO",
+        default_value_t = 0.06,
+        conflicts_with_all = ["all", "pad_x", "pad_y", "pad_percent", "coverage"]
+    )]
+    auto_ratio: f32,
+
+    /// Lower clamp for automatic padding, in pixels (auto mode only)
+    #[arg(
+        long,
+        value_name = "PX",
+        default_value_t = 48,
+        conflicts_with_all = ["all", "pad_x", "pad_y", "pad_percent", "coverage"]
+    )]
+    auto_min: u32,
+
+    /// Upper clamp for automatic padding, in pixels (auto mode only)
+    #[arg(
+        long,
+        value_name = "PX",
+        default_value_t = 320,
+        conflicts_with_all = ["all", "pad_x", "pad_y", "pad_percent", "coverage"]
+    )]
+    auto_max: u32,
+
+    /// Background: "auto", "auto-edge" (the average of the border instead of its most common
+    /// color, for gradients), "transparent", "blur" (a blurred copy of the input), "extend"
+    /// (the content's edge pixels repeated outward, as --pad-mode clamp), a CSS color name (e.g. white), or hex
+    /// (#RRGGBB or #RRGGBBAA)
     #[arg(long, value_name = "HEX", default_value = "auto")]
     bg: String,
 
+    /// Blur radius in pixels for --bg blur
+    #[arg(long, value_name = "PX", default_value_t = 40)]
+    bg_blur_radius: u32,
+
+    /// Fill the canvas with a linear gradient: "#RRGGBB..#RRGGBB[@ANGLE]" (CSS degrees, default 180)
+    #[arg(long, value_name = "FROM..TO[@ANGLE]", conflicts_with = "bg")]
+    bg_gradient: Option<Gradient>,
+
+    /// Fill the padding from the content's edges instead of the background: "clamp" (the
+    /// default) repeats the edge pixels, "reflect" mirrors the content outward, "wrap" tiles it
+    #[arg(
+        long,
+        alias = "extend",
+        value_name = "MODE",
+        num_args = 0..=1,
+        default_missing_value = "clamp",
+        conflicts_with_all = ["bg_gradient", "bg_tile"]
+    )]
+    pad_mode: Option<ExtendMode>,
+
+    /// Fill the canvas by repeating this image (translucent parts show the background color)
+    #[arg(long, value_name = "PATH", conflicts_with = "bg_gradient")]
+    bg_tile: Option<PathBuf>,
+
     /// Disable auto-cropping of partial bottom artifacts
     #[arg(long)]
     no_crop: bool,
 
-    /// Print crop decisions to stderr
+    /// Also crop thin partial column blocks (e.g. a sliver of a neighbouring pane) from the
+    /// left and right edges
+    #[arg(long, conflicts_with = "no_crop")]
+    crop_columns: bool,
+
+    /// Print crop decisions (and --smart/--min-* padding) to stderr
     #[arg(long)]
     debug_crop: bool,
-}
 
-#[derive(Debug)]
-struct PadifyError(String);
+    /// Only auto-crop when the heuristic's confidence is at least this value, in [0, 1]
+    #[arg(long, value_name = "VALUE", default_value_t = 0.0)]
+    crop_confidence: f32,
 
-impl std::fmt::Display for PadifyError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
-    }
-}
+    /// Remove background-only rows and columns from all four sides before padding
+    #[arg(long)]
+    trim: bool,
+
+    /// How far (summed over RGBA) a pixel may differ from the background and still be trimmed
+    /// (defaults to --bg-tolerance)
+    #[arg(long, value_name = "VALUE", requires = "trim")]
+    trim_tolerance: Option<u16>,
+
+    /// How far a pixel may differ from the background (sum of the RGBA channel differences,
+    /// 0-1020) and still count as background when cropping and trimming
+    #[arg(long, value_name = "N", default_value_t = BACKGROUND_THRESHOLD)]
+    bg_tolerance: u16,
+
+    /// Where to place the content on the padded canvas: center, north, south, east, west,
+    /// northeast, northwest, southeast or southwest (default: offset by the left/top padding)
+    #[arg(long, value_name = "GRAVITY")]
+    gravity: Option<Gravity>,
+
+    /// Print resolution in dots per inch, for lengths given in in, cm or mm
+    #[arg(long, value_name = "DPI")]
+    dpi: Option<f64>,
+
+    /// Place the content on a grid of this step from the canvas corner (e.g. 0.125in, 3mm or
+    /// 32px), moving padding between opposite sides so the output size stays the same
+    #[arg(long, value_name = "LENGTH", conflicts_with = "gravity")]
+    snap: Option<String>,
+
+    /// Round the output's corners with this radius in pixels (clamped to half the smaller side)
+    #[arg(long, value_name = "PX", default_value_t = 0)]
+    radius: u32,
+
+    /// Fill the rounded-off corners with the background color instead of transparency
+    #[arg(long, requires = "radius")]
+    radius_fill: bool,
+
+    /// Draw a soft drop shadow beneath the content
+    #[arg(long)]
+    shadow: bool,
+
+    /// Blur radius of the shadow in pixels
+    #[arg(long, value_name = "PX", default_value_t = 16, requires = "shadow")]
+    shadow_blur: u32,
+
+    /// Offset of the shadow from the content as X,Y pixels
+    #[arg(
+        long,
+        value_name = "X,Y",
+        default_value = "0,8",
+        requires = "shadow",
+        allow_hyphen_values = true
+    )]
+    shadow_offset: String,
+
+    /// Shadow color as hex (#RRGGBB or #RRGGBBAA) or a CSS color name
+    #[arg(
+        long,
+        value_name = "HEX",
+        default_value = "#00000080",
+        requires = "shadow"
+    )]
+    shadow_color: String,
+
+    /// Draw a border of PX pixels in color HEX around the content (e.g. 4:#ffffff)
+    #[arg(long, value_name = "PX:HEX")]
+    border: Option<String>,
+
+    /// Round the border's outer corners with this radius in pixels
+    #[arg(long, value_name = "PX", default_value_t = 0, requires = "border")]
+    border_radius: u32,
+
+    /// Stamp this image (e.g. a logo) over each output
+    #[arg(long, value_name = "PATH")]
+    watermark: Option<PathBuf>,
+
+    /// Where to put the watermark: center, north, south, east, west, northeast, northwest,
+    /// southeast or southwest
+    #[arg(
+        long,
+        value_name = "GRAVITY",
+        default_value = "southeast",
+        requires = "watermark"
+    )]
+    watermark_gravity: Gravity,
+
+    /// Opacity of the watermark, in [0, 1]
+    #[arg(
+        long,
+        value_name = "VALUE",
+        default_value_t = 1.0,
+        requires = "watermark"
+    )]
+    watermark_opacity: f32,
+
+    /// Scale the watermark to this fraction of the output width, in (0, 1]
+    #[arg(long, value_name = "RATIO", requires = "watermark")]
+    watermark_scale: Option<f32>,
+
+    /// Encode outputs as this format regardless of their file name (png, jpg, webp, ...)
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<String>,
+
+    /// Encoder quality for lossy formats (JPEG, and WebP, which is otherwise lossless), from 1
+    /// (smallest) to 100 (best)
+    #[arg(long, value_name = "1-100", value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+
+    /// Encode WebP output losslessly, keeping transparency exactly (the default without --quality)
+    #[arg(long, conflicts_with = "quality")]
+    lossless: bool,
+
+    /// Interpolation filter for every resize in the run
+    #[arg(long, value_enum, default_value = "lanczos3")]
+    filter: Filter,
+
+    /// Scale the input to WxH, or by a percentage such as 50%, before cropping and padding
+    #[arg(long, value_name = "WxH|PCT%")]
+    resize: Option<String>,
+
+    /// Give sides where content crowds the edge more padding (within ±30%) for balanced margins
+    #[arg(long, conflicts_with = "to_size")]
+    smart: bool,
+
+    /// Pad (never scale) so the output is exactly WxH, centering the content
+    #[arg(
+        long,
+        value_name = "WxH",
+        conflicts_with_all = [
+            "all", "pad_x", "pad_y", "pad_percent", "coverage",
+            "pad_top", "pad_right", "pad_bottom", "pad_left", "square",
+        ]
+    )]
+    to_size: Option<String>,
+
+    /// Scale the content to fit inside an exactly WxH canvas (keeping its aspect ratio),
+    /// centered or placed per --gravity
+    #[arg(
+        long,
+        value_name = "WxH",
+        conflicts_with_all = [
+            "to_size", "all", "pad_x", "pad_y", "pad_percent", "coverage", "pad_top",
+            "pad_right", "pad_bottom", "pad_left", "square", "smart", "min_width",
+            "min_height", "max_width", "max_height",
+        ]
+    )]
+    canvas: Option<String>,
+
+    /// Place the content unscaled on an exactly WxH canvas, centered or placed per --gravity,
+    /// cropping it (from the side --gravity points away from) where it is larger
+    #[arg(
+        long,
+        value_name = "WxH",
+        conflicts_with_all = [
+            "to_size", "canvas", "all", "pad_x", "pad_y", "pad_percent", "coverage", "pad_top",
+            "pad_right", "pad_bottom", "pad_left", "square", "smart", "min_width",
+            "min_height", "max_width", "max_height",
+        ]
+    )]
+    fit: Option<String>,
+
+    /// Expand the shorter side after padding so the output is square
+    #[arg(long)]
+    square: bool,
+
+    /// Add the least padding that makes the output width and height multiples of N
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["to_size", "canvas", "fit"]
+    )]
+    round_to: Option<u32>,
+
+    /// Add padding until the output is at least this wide (never scales)
+    #[arg(long, value_name = "PX", conflicts_with = "to_size")]
+    min_width: Option<u32>,
+
+    /// Add padding until the output is at least this tall (never scales)
+    #[arg(long, value_name = "PX", conflicts_with = "to_size")]
+    min_height: Option<u32>,
+
+    /// Downscale the content so the padded output is at most this wide
+    #[arg(long, value_name = "PX", conflicts_with = "to_size")]
+    max_width: Option<u32>,
+
+    /// Downscale the content so the padded output is at most this tall
+    #[arg(long, value_name = "PX", conflicts_with = "to_size")]
+    max_height: Option<u32>,
+
+    /// Split the padded image into tiles of at most WxH pixels (saved as <output>_r0c0.<ext>, ...)
+    #[arg(long, value_name = "WxH")]
+    split: Option<String>,
+
+    /// Link byte-identical outputs of this run to the first copy instead of writing them again
+    #[arg(long)]
+    dedupe: bool,
+
+    /// Comma-separated extensions to pick up when walking directory inputs
+    #[arg(
+        long,
+        value_name = "EXTS",
+        value_delimiter = ',',
+        default_value = "png,jpg,jpeg,gif,bmp,tif,tiff,webp"
+    )]
+    ext: Vec<String>,
+
+    /// Write outputs under DIR, mirroring the layout of directory inputs
+    #[arg(long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
+
+    /// Print what would be done for each file (background, crop, sizes) without writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Print the N most common colors of each input (hex and share) instead of padding it
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    palette: Option<u16>,
+
+    /// Print where the content sits in each input (x, y, width, height, after cropping and
+    /// --trim) instead of padding it
+    #[arg(long, conflicts_with_all = ["palette", "resize"])]
+    bbox: bool,
+
+    /// Print --bbox results as JSON, one object per line
+    #[arg(long, requires = "bbox")]
+    json: bool,
+
+    /// Overwrite existing output files instead of refusing (single input) or skipping them (batches)
+    #[arg(long)]
+    force: bool,
+
+    /// Also write the original and the padded result side by side to FILE, for checking
+    /// crop and padding settings (single input only)
+    #[arg(long, value_name = "FILE")]
+    compare: Option<PathBuf>,
+
+    /// Composite the content over this color (default: the background if it is opaque, else
+    /// white) before padding, so it comes out fully opaque; implied for JPEG output
+    #[arg(long, value_name = "COLOR", num_args = 0..=1)]
+    flatten: Option<Option<String>>,
+
+    /// Write both a flattened (<output>_flat.<ext>) and a transparent (<output>_alpha.<ext>) variant
+    #[arg(long)]
+    variants: bool,
 
-impl std::error::Error for PadifyError {}
+    /// Also write a preview of each output composited over a checkerboard to
+    /// <output>_preview.<ext>, so transparent padding is visible; the output keeps its alpha
+    #[arg(long)]
+    checker: bool,
+
+    /// Size of the --checker squares in pixels
+    #[arg(
+        long,
+        value_name = "PX",
+        default_value_t = 8,
+        requires = "checker",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    checker_size: u32,
+
+    /// Pad only the first frame of an animated GIF instead of every frame
+    #[arg(long)]
+    first_frame_only: bool,
+
+    /// Also write run statistics (content ratio, background detection, crop decision) as
+    /// JSON next to each output, to <output>.json, and print the detected background to stderr
+    #[arg(long)]
+    stats: bool,
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+    /// Pad up to N files of a batch at once (default: the number of CPUs)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+
+    /// Don't show the progress bar for batches
+    #[arg(long)]
+    quiet: bool,
+}
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    let input = &args.input;
-    let output = args
-        .output
-        .clone()
-        .unwrap_or_else(|| default_output_path(input));
-
-    let image = image::open(input)?;
-    let rgba = image.to_rgba8();
-    let bg = if args.bg.trim().eq_ignore_ascii_case("auto") {
-        deduce_background(&rgba)
+    let settings = Settings::from_args(&args)?;
+
+    let mut jobs = resolve_jobs(
+        &args.paths,
+        args.output.as_deref(),
+        &args.ext,
+        args.out_dir.as_deref(),
+        settings.format,
+    )?;
+    if let Some(list) = &args.from_file {
+        let listed = read_manifest(list)?;
+        jobs.extend(batch_jobs(
+            &listed,
+            &args.ext,
+            args.out_dir.as_deref(),
+            settings.format,
+        ));
+    }
+    if args.compare.is_some() && jobs.len() > 1 {
+        return Err(Box::new(PadifyError(
+            "--compare needs a single input image".into(),
+        )));
+    }
+    // The bar goes to stderr, but only makes sense while someone is watching the output
+    let show_progress = jobs.len() > 1
+        && !args.quiet
+        && !args.dry_run
+        && !args.report_only()
+        && std::io::stdout().is_terminal()
+        && std::io::stderr().is_terminal();
+    let progress = if show_progress {
+        ProgressBar::new(jobs.len() as u64).with_style(
+            ProgressStyle::with_template("[{bar:30}] {pos}/{len} {wide_msg}")?.progress_chars("#-"),
+        )
     } else {
-        parse_color(&args.bg)?
+        ProgressBar::hidden()
     };
-    let crop_result = if args.no_crop {
-        CropResult::no_crop(rgba.clone(), "disabled")
-    } else {
-        auto_crop_bottom_partial(&rgba, bg)
+    let writer = OutputWriter::new(args.dedupe, settings.format, args.quality, progress);
+
+    let outcomes = match jobs.as_slice() {
+        // A single named input fails the run with its own error
+        [job] if !job.from_dir && args.from_file.is_none() => {
+            let output = job_output(job, &settings);
+            if !args.force
+                && !args.dry_run
+                && !args.report_only()
+                && let Some(existing) = existing_output(&output, &args)
+            {
+                return Err(Box::new(PadifyError(format!(
+                    "{} already exists; use --force to overwrite it",
+                    existing.display()
+                ))));
+            }
+            process(&args, &settings, &job.input, output, &writer)?;
+            vec![Outcome::Done]
+        }
+        _ => {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(args.jobs.map_or(0, usize::from))
+                .build()?;
+            pool.install(|| {
+                jobs.par_iter()
+                    .map(|job| run_job(&args, &settings, job, &writer))
+                    .collect()
+            })
+        }
     };
-    if args.debug_crop {
+    writer.progress.finish_and_clear();
+
+    let mut failed = 0;
+    let mut skipped = 0;
+    for (job, outcome) in jobs.iter().zip(outcomes) {
+        match outcome {
+            Outcome::Done => {}
+            Outcome::Exists(existing) => {
+                eprintln!(
+                    "padify: skipping {}: {} already exists",
+                    job.input.display(),
+                    existing.display()
+                );
+                skipped += 1;
+            }
+            Outcome::Undecodable(err) => {
+                eprintln!("padify: skipping {}: {err}", job.input.display());
+            }
+            Outcome::Failed(err) => {
+                eprintln!("padify: {}: {err}", job.input.display());
+                failed += 1;
+            }
+        }
+    }
+    if args.dedupe {
         eprintln!(
-            "padify: crop {} -> {} ({})",
-            crop_result.report.original_height,
-            crop_result.report.new_height,
-            crop_result.report.reason
+            "padify: collapsed {} duplicate output(s)",
+            writer.lock().duplicates
         );
     }
-    let cropped = crop_result.image;
-    let (pad_x, pad_y) = resolve_padding(&args, cropped.dimensions())?;
-    let (new_w, new_h) = padded_dimensions(cropped.dimensions(), pad_x, pad_y)?;
-    let mut canvas = ImageBuffer::from_pixel(new_w, new_h, bg);
+    if skipped > 0 {
+        eprintln!("padify: skipped {skipped} existing output(s); use --force to overwrite");
+    }
+    if failed > 0 {
+        return Err(Box::new(PadifyError(format!(
+            "{failed} of {} file(s) failed",
+            jobs.len()
+        ))));
+    }
+    Ok(())
+}
 
-    image::imageops::replace(&mut canvas, &cropped, pad_x.into(), pad_y.into());
+/// How one file of a batch went. Messages are kept as text (the errors aren't `Send`) and
+/// reported in input order once the whole batch is done.
+enum Outcome {
+    Done,
+    /// Skipped because this output file already exists
+    Exists(PathBuf),
+    /// A file found in a directory that isn't an image padify can decode
+    Undecodable(String),
+    Failed(String),
+}
 
-    DynamicImage::ImageRgba8(canvas).save(&output)?;
-    println!("{}", output.display());
-    Ok(())
+/// Pads one file of a batch on a worker thread.
+fn run_job(args: &Args, settings: &Settings, job: &Job, writer: &OutputWriter) -> Outcome {
+    writer.progress.set_message(job.input.display().to_string());
+    let output = job_output(job, settings);
+    let outcome = if !args.force
+        && !args.dry_run
+        && !args.report_only()
+        && let Some(existing) = existing_output(&output, args)
+    {
+        Outcome::Exists(existing)
+    } else {
+        match process(args, settings, &job.input, output, writer) {
+            Ok(()) => Outcome::Done,
+            Err(err) if job.from_dir && err.is::<image::ImageError>() => {
+                Outcome::Undecodable(err.to_string())
+            }
+            Err(err) => Outcome::Failed(err.to_string()),
+        }
+    };
+    writer.progress.inc(1);
+    outcome
 }
 
-fn padded_dimensions((w, h): (u32, u32), pad_x: u32, pad_y: u32) -> Result<(u32, u32)> {
-    let pad_x2 = pad_x
-        .checked_mul(2)
-        .ok_or_else(|| PadifyError("horizontal padding is too large".into()))?;
-    let pad_y2 = pad_y
-        .checked_mul(2)
-        .ok_or_else(|| PadifyError("vertical padding is too large".into()))?;
-    let new_w = w
-        .checked_add(pad_x2)
-        .ok_or_else(|| PadifyError("resulting width is too large".into()))?;
-    let new_h = h
-        .checked_add(pad_y2)
-        .ok_or_else(|| PadifyError("resulting height is too large".into()))?;
-    Ok((new_w, new_h))
+/// Where `job` is written, with the extension of a forced format.
+fn job_output(job: &Job, settings: &Settings) -> PathBuf {
+    let output = job
+        .output
+        .clone()
+        .unwrap_or_else(|| default_output_path(&job.input, settings.format));
+    with_format_extension(output, &job.input, settings.format)
 }
 
-fn auto_pad(value: u32, ratio: f32, min: u32, max: u32) -> u32 {
-    let scaled = ((value as f32) * ratio).round() as u32;
-    clamp_u32(scaled, min, max)
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Filter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
 }
 
-fn resolve_padding(args: &Args, (w, h): (u32, u32)) -> Result<(u32, u32)> {
-    let auto = auto_pad(w.min(h), 0.06, 48, 320);
-    let pad = if let Some(all) = args.all {
-        all
-    } else {
-        match (args.pad_x, args.pad_y) {
-            (Some(x), Some(y)) => {
-                if x != y {
-                    return Err(Box::new(PadifyError(
-                        "pad-x and pad-y must be equal (or use --all/--pad)".into(),
-                    )));
-                }
-                x
-            }
-            (Some(x), None) => x,
-            (None, Some(y)) => y,
-            (None, None) => auto,
+impl From<Filter> for FilterType {
+    fn from(filter: Filter) -> Self {
+        match filter {
+            Filter::Nearest => FilterType::Nearest,
+            Filter::Triangle => FilterType::Triangle,
+            Filter::CatmullRom => FilterType::CatmullRom,
+            Filter::Gaussian => FilterType::Gaussian,
+            Filter::Lanczos3 => FilterType::Lanczos3,
         }
+    }
+}
+
+/// One image to pad. Without an explicit `output` it goes to `<input>_pad.<ext>`.
+#[derive(Debug, PartialEq)]
+struct Job {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    /// Found by walking a directory input rather than named on the command line
+    from_dir: bool,
+}
+
+/// Expands the positional paths into jobs. Two paths where the second doesn't exist yet
+/// keep the classic `padify <input> <output>` meaning, which `--output` spells out for a
+/// single input; anything else is a batch of inputs, with directories walked for files
+/// matching `extensions`.
+fn resolve_jobs(
+    paths: &[PathBuf],
+    output: Option<&Path>,
+    extensions: &[String],
+    out_dir: Option<&Path>,
+    format: Option<ImageFormat>,
+) -> Result<Vec<Job>> {
+    let Some(output) = output else {
+        if let [input, output] = paths
+            && !input.is_dir()
+            && !output.exists()
+        {
+            return Ok(vec![Job {
+                input: input.clone(),
+                output: Some(output.clone()),
+                from_dir: false,
+            }]);
+        }
+        return Ok(batch_jobs(paths, extensions, out_dir, format));
     };
-    Ok((pad, pad))
+    match paths {
+        [input] if !input.is_dir() => Ok(vec![Job {
+            input: input.clone(),
+            output: Some(output.to_path_buf()),
+            from_dir: false,
+        }]),
+        _ => Err(Box::new(PadifyError(
+            "--output needs a single input image".into(),
+        ))),
+    }
 }
 
-fn default_output_path(input: &Path) -> PathBuf {
-    let parent = input.parent().unwrap_or_else(|| Path::new("."));
-    let stem = input
-        .file_stem()
-        .map(|s| s.to_string_lossy().into_owned())
-        .filter(|s| !s.is_empty())
-        .unwrap_or_else(|| "output".to_string());
-    let ext = input.extension().map(|s| s.to_string_lossy());
+/// Jobs for a batch of inputs, each padded to its default output, with directories walked
+/// for files matching `extensions`.
+fn batch_jobs(
+    paths: &[PathBuf],
+    extensions: &[String],
+    out_dir: Option<&Path>,
+    format: Option<ImageFormat>,
+) -> Vec<Job> {
+    let mut jobs = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            let name = path.file_name().map(Path::new).unwrap_or(path);
+            jobs.push(Job {
+                input: path.clone(),
+                output: out_dir.map(|dir| default_output_path(&dir.join(name), format)),
+                from_dir: false,
+            });
+            continue;
+        }
+        for entry in WalkDir::new(path)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            let file = entry.path();
+            if !entry.file_type().is_file()
+                || !has_extension(file, extensions)
+                || is_padded_output(file)
+            {
+                continue;
+            }
+            let relative = file.strip_prefix(path).unwrap_or(file);
+            jobs.push(Job {
+                input: file.to_path_buf(),
+                output: out_dir.map(|dir| default_output_path(&dir.join(relative), format)),
+                from_dir: true,
+            });
+        }
+    }
+    jobs
+}
 
-    let file_name = match ext {
-        Some(ext) if !ext.is_empty() => format!("{stem}_pad.{ext}"),
-        _ => format!("{stem}_pad.png"),
+/// Reads the input paths listed in the `--from-file` manifest at `list`, one per line,
+/// skipping blank lines and `#` comments. Relative paths are taken from the manifest's
+/// directory, so a manifest works the same from wherever padify is run.
+fn read_manifest(list: &Path) -> Result<Vec<PathBuf>> {
+    let text = std::fs::read_to_string(list)
+        .map_err(|e| PadifyError(format!("failed to read {}: {e}", list.display())))?;
+    let base = list.parent().unwrap_or(Path::new(""));
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line))
+        .collect())
+}
+
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    let Some(ext) = path.extension().and_then(|ext| ext.to_str()) else {
+        return false;
     };
+    extensions.iter().any(|wanted| {
+        wanted
+            .trim()
+            .trim_start_matches('.')
+            .eq_ignore_ascii_case(ext)
+    })
+}
 
-    parent.join(file_name)
+/// Whether `path` looks like an earlier padify output, so re-running on a directory
+/// doesn't pad its own results again.
+fn is_padded_output(path: &Path) -> bool {
+    path.file_stem()
+        .is_some_and(|stem| stem.to_string_lossy().ends_with("_pad"))
 }
 
-fn parse_color(input: &str) -> Result<Rgba<u8>> {
-    let trimmed = input.trim();
-    if trimmed.eq_ignore_ascii_case("transparent") {
-        return Ok(Rgba([0, 0, 0, 0]));
+impl Args {
+    /// Whether the run only prints something about each input (--palette, --bbox) instead of
+    /// writing outputs.
+    fn report_only(&self) -> bool {
+        self.palette.is_some() || self.bbox
     }
+}
+
+/// Option values parsed once up front rather than for every input.
+struct Settings {
+    bg: Option<Rgba<u8>>,
+    /// Detect the background by averaging the border rather than by its dominant color
+    bg_from_edges: bool,
+    backdrop: Option<Backdrop>,
+    split: Option<(u32, u32)>,
+    shadow: Option<Shadow>,
+    border: Option<Border>,
+    watermark: Option<Watermark>,
+    /// --flatten, with `None` inside for the default color
+    flatten: Option<Option<Rgba<u8>>>,
+    format: Option<ImageFormat>,
+    resize: Option<Resize>,
+    to_size: Option<(u32, u32)>,
+    canvas: Option<(u32, u32)>,
+    fit: Option<(u32, u32)>,
+    /// Grid step for --snap, in pixels
+    snap: Option<f64>,
+}
 
-    let hex = trimmed.strip_prefix('#').unwrap_or(trimmed);
-    if hex.len() != 6 && hex.len() != 8 {
+impl Settings {
+    fn from_args(args: &Args) -> Result<Self> {
+        if !(0.0..=1.0).contains(&args.crop_confidence) {
+            return Err(Box::new(PadifyError(
+                "crop confidence must be between 0 and 1".into(),
+            )));
+        }
+        if !(args.auto_ratio.is_finite() && args.auto_ratio > 0.0) {
+            return Err(Box::new(PadifyError("auto ratio must be positive".into())));
+        }
+        if args.dpi.is_some_and(|dpi| !(dpi.is_finite() && dpi > 0.0)) {
+            return Err(Box::new(PadifyError("dpi must be positive".into())));
+        }
+        if args.auto_min > args.auto_max {
+            return Err(Box::new(PadifyError(format!(
+                "auto min ({}) must not exceed auto max ({})",
+                args.auto_min, args.auto_max
+            ))));
+        }
+        let mut backdrop = args.bg_gradient.map(Backdrop::Gradient);
+        let mut bg_from_edges = false;
+        let bg = match args.bg.trim().to_ascii_lowercase().as_str() {
+            "auto" => None,
+            "auto-edge" => {
+                bg_from_edges = true;
+                None
+            }
+            "blur" => {
+                backdrop = Some(Backdrop::Blur {
+                    radius: args.bg_blur_radius,
+                });
+                None
+            }
+            "extend" => {
+                backdrop = Some(Backdrop::Extend(ExtendMode::Clamp));
+                None
+            }
+            _ => Some(parse_color(&args.bg)?),
+        };
+        if let Some(mode) = args.pad_mode {
+            if backdrop.is_some() {
+                return Err(Box::new(PadifyError(
+                    "--pad-mode can't be combined with --bg blur or --bg extend".into(),
+                )));
+            }
+            backdrop = Some(Backdrop::Extend(mode));
+        }
+        if let Some(path) = &args.bg_tile {
+            if backdrop.is_some() {
+                return Err(Box::new(PadifyError(
+                    "--bg-tile can't be combined with --bg blur or --bg extend".into(),
+                )));
+            }
+            let tile = image::open(path)
+                .map_err(|e| PadifyError(format!("failed to load tile {}: {e}", path.display())))?;
+            backdrop = Some(Backdrop::Tile(tile.to_rgba8()));
+        }
+        if args.flatten.is_some() && bg.is_some_and(|bg| bg[3] == 0) {
+            return Err(Box::new(PadifyError(
+                "--flatten makes the content opaque, which doesn't fit with --bg transparent; \
+                 pick a background color or drop --flatten"
+                    .into(),
+            )));
+        }
+        if !(0.0..=1.0).contains(&args.watermark_opacity) {
+            return Err(Box::new(PadifyError(
+                "watermark opacity must be between 0 and 1".into(),
+            )));
+        }
+        if args
+            .watermark_scale
+            .is_some_and(|scale| !(scale > 0.0 && scale <= 1.0))
+        {
+            return Err(Box::new(PadifyError(
+                "watermark scale must be greater than 0 and at most 1".into(),
+            )));
+        }
+        let watermark = match &args.watermark {
+            Some(path) => {
+                let image = image::open(path).map_err(|e| {
+                    PadifyError(format!("failed to load watermark {}: {e}", path.display()))
+                })?;
+                Some(Watermark {
+                    image: image.to_rgba8(),
+                    gravity: args.watermark_gravity,
+                    opacity: args.watermark_opacity,
+                    scale: args.watermark_scale,
+                })
+            }
+            None => None,
+        };
+        let shadow = if args.shadow {
+            Some(Shadow {
+                blur: args.shadow_blur,
+                offset: parse_offset(&args.shadow_offset)?,
+                color: parse_color(&args.shadow_color)?,
+            })
+        } else {
+            None
+        };
+        let border = match &args.border {
+            Some(spec) => {
+                let (width, color) = parse_border(spec)?;
+                Some(Border {
+                    width,
+                    color,
+                    radius: args.border_radius,
+                })
+            }
+            None => None,
+        };
+        Ok(Self {
+            bg,
+            bg_from_edges,
+            backdrop,
+            split: args.split.as_deref().map(parse_size).transpose()?,
+            shadow,
+            border,
+            watermark,
+            flatten: match &args.flatten {
+                Some(color) => Some(color.as_deref().map(parse_color).transpose()?),
+                None => None,
+            },
+            format: args.format.as_deref().map(parse_format).transpose()?,
+            resize: args.resize.as_deref().map(parse_resize).transpose()?,
+            to_size: args.to_size.as_deref().map(parse_size).transpose()?,
+            canvas: args.canvas.as_deref().map(parse_size).transpose()?,
+            fit: args.fit.as_deref().map(parse_size).transpose()?,
+            snap: args
+                .snap
+                .as_deref()
+                .map(|snap| parse_length(snap, args.dpi))
+                .transpose()?,
+        })
+    }
+}
+
+fn process(
+    args: &Args,
+    settings: &Settings,
+    input: &Path,
+    output: PathBuf,
+    writer: &OutputWriter,
+) -> Result<()> {
+    let format = match settings.format {
+        Some(format) => format,
+        None => ImageFormat::from_path(&output)?,
+    };
+    if args.out_dir.is_some()
+        && !args.dry_run
+        && !args.report_only()
+        && let Some(parent) = output.parent()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let (image, icc) = load_image(input)?;
+    let mut rgba = image.to_rgba8();
+    let mut animation =
+        if format == ImageFormat::Gif && !args.first_frame_only && !args.report_only() {
+            load_animation(input)?
+        } else {
+            None
+        };
+    // Everything after this, the padding math included, works on the resized image
+    if let Some(resize) = settings.resize {
+        let (w, h) = resize.dimensions(rgba.dimensions());
+        rgba = image::imageops::resize(&rgba, w, h, args.filter.into());
+        if let Some((frames, _)) = &mut animation {
+            for frame in frames.iter_mut() {
+                let delay = frame.delay();
+                let buffer = image::imageops::resize(frame.buffer(), w, h, args.filter.into());
+                *frame = Frame::from_parts(buffer, 0, 0, delay);
+            }
+        }
+    }
+    if animation.is_some() && (args.variants || args.split.is_some() || args.checker) {
         return Err(Box::new(PadifyError(
-            "color must be #RRGGBB, #RRGGBBAA, or 'transparent'".into(),
+            "--variants, --split and --checker can't be used on an animated GIF; \
+             add --first-frame-only to pad just its first frame"
+                .into(),
         )));
     }
+    if let Some(count) = args.palette {
+        let colors: Vec<String> = dominant_colors(&rgba, count.into())
+            .into_iter()
+            .map(|(Rgba([r, g, b, a]), share)| {
+                format!("#{r:02x}{g:02x}{b:02x}{a:02x} {:.1}%", share * 100.0)
+            })
+            .collect();
+        println!("{}: {}", input.display(), colors.join(", "));
+        return Ok(());
+    }
+    // Only auto-detected backgrounds have a confidence to report
+    let detected = settings.bg.is_none().then(|| {
+        if settings.bg_from_edges {
+            edge_average_background(&rgba)
+        } else {
+            detect_background(&rgba)
+        }
+    });
+    let bg = settings
+        .bg
+        .unwrap_or_else(|| detected.map_or(Rgba([0, 0, 0, 0]), |estimate| estimate.color));
+    if args.stats {
+        writer.note(&background_summary(input, bg, detected));
+    }
+    let note_debug =
+        |message: &str| writer.note(&format!("padify: {}: {message}", input.display()));
+    let debug: Option<&dyn Fn(&str)> = args.debug_crop.then_some(&note_debug);
+    let mut crop_result = if args.no_crop {
+        CropResult::no_crop(rgba.clone(), "disabled")
+    } else {
+        auto_crop_partial(&rgba, bg, args.bg_tolerance, args.crop_confidence)
+    };
+    if args.crop_columns {
+        crop_result =
+            crop_partial_columns(crop_result, bg, args.bg_tolerance, args.crop_confidence);
+    }
+    if let Some(debug) = debug {
+        debug(&format!(
+            "crop {} -> {} (bottom {}, top {} trimming {}, confidence {:.2})",
+            crop_result.report.original_height,
+            crop_result.report.new_height,
+            crop_result.report.reason,
+            crop_result.report.top_reason,
+            crop_result.report.top,
+            crop_result.report.confidence
+        ));
+        if args.crop_columns {
+            debug(&format!(
+                "crop columns {} -> {} ({}, left trimming {})",
+                crop_result.report.original_width,
+                crop_result.report.new_width,
+                crop_result.report.column_reason,
+                crop_result.report.left
+            ));
+        }
+    }
+    let report = crop_result.report;
+    let mut cropped = crop_result.image;
+    // Where the content sits in the input, so animation frames can be cut out the same way
+    let mut region = (report.left, report.top, report.new_width, report.new_height);
+    if args.trim {
+        let tolerance = args.trim_tolerance.unwrap_or(args.bg_tolerance);
+        let (w, h) = cropped.dimensions();
+        if let Some((x, y, new_w, new_h)) = trim_bounds(&cropped, bg, tolerance) {
+            cropped = image::imageops::crop_imm(&cropped, x, y, new_w, new_h).to_image();
+            region = (region.0 + x, region.1 + y, new_w, new_h);
+        }
+        if let Some(debug) = debug {
+            let (new_w, new_h) = cropped.dimensions();
+            debug(&format!("trim {w}x{h} -> {new_w}x{new_h}"));
+        }
+    }
+    if args.bbox {
+        println!("{}", bbox_line(input, region, args.json));
+        return Ok(());
+    }
+    if args.max_width.is_some() || args.max_height.is_some() {
+        cropped = fit_within_max(args, settings, &cropped, bg, debug)?;
+    }
+    if let Some(canvas) = settings.canvas {
+        cropped = fit_canvas(args, settings, &cropped, canvas, debug)?;
+    }
+    if let Some(fit) = settings.fit {
+        let (w, h) = cropped.dimensions();
+        let (x, y, new_w, new_h) = fit_window(args, settings, (w, h), fit)?;
+        if (new_w, new_h) != (w, h) {
+            cropped = image::imageops::crop_imm(&cropped, x, y, new_w, new_h).to_image();
+            region = (region.0 + x, region.1 + y, new_w, new_h);
+            if let Some(debug) = debug {
+                debug(&format!(
+                    "cropped content {w}x{h} -> {new_w}x{new_h} to fit the canvas"
+                ));
+            }
+        }
+    }
+    // Also surfaces an oversized canvas as an error before pad_content would panic on it
+    let padding = plan_padding(args, settings, &cropped, bg, debug)?;
+    if args.dry_run {
+        let (w, h) = rgba.dimensions();
+        let (new_w, new_h) = padded_dimensions(cropped.dimensions(), &padding)?;
+        let [r, g, b, a] = bg.0;
+        println!(
+            "{}: {w}x{h} -> {new_w}x{new_h}, background #{r:02x}{g:02x}{b:02x}{a:02x}, \
+             crop {}/{} ({} -> {}), would write {}",
+            input.display(),
+            report.reason,
+            report.top_reason,
+            report.original_height,
+            report.new_height,
+            output.display()
+        );
+        return Ok(());
+    }
+    let opts = PadOptions {
+        padding,
+        background: bg,
+        crop: false,
+        crop_confidence: args.crop_confidence,
+        bg_tolerance: args.bg_tolerance,
+        gravity: args.gravity,
+        filter: args.filter.into(),
+        radius: args.radius,
+        radius_fill: args.radius_fill,
+        shadow: settings.shadow,
+        border: settings.border,
+        backdrop: settings.backdrop.clone(),
+        watermark: settings.watermark.clone(),
+        // JPEG can't store alpha, so flatten even without --flatten
+        flatten: match settings.flatten {
+            Some(Some(color)) => Some(color),
+            Some(None) => Some(flatten_color(bg)),
+            None => (format == ImageFormat::Jpeg).then(|| flatten_color(bg)),
+        },
+    };
+
+    if let Some((frames, repeat)) = animation {
+        let frames = pad_frames(
+            frames,
+            region,
+            cropped.dimensions(),
+            &opts,
+            args.filter.into(),
+        );
+        if let Some(debug) = debug {
+            debug(&format!("padded {} animation frame(s)", frames.len()));
+        }
+        let first = frames[0].buffer().clone();
+        if args.stats {
+            write_stats(input, &output, &cropped, &first, bg, detected, &report)?;
+        }
+        writer.save_animation(frames, repeat, &output)?;
+        if let Some(compare_path) = &args.compare {
+            save_comparison(&rgba, &first, compare_path)?;
+            writer.announce(compare_path);
+        }
+        return Ok(());
+    }
 
-    let r = parse_hex_byte(&hex[0..2])?;
-    let g = parse_hex_byte(&hex[2..4])?;
-    let b = parse_hex_byte(&hex[4..6])?;
-    let a = if hex.len() == 8 {
-        parse_hex_byte(&hex[6..8])?
+    let outputs = if args.variants {
+        let flat = flatten(&pad_content(&cropped, &rgba, &opts), bg);
+        let alpha = pad_content(
+            &cropped,
+            &rgba,
+            &PadOptions {
+                background: Rgba([0, 0, 0, 0]),
+                backdrop: None,
+                ..opts
+            },
+        );
+        vec![
+            (flat, suffixed_path(&output, "flat")),
+            (alpha, suffixed_path(&output, "alpha")),
+        ]
     } else {
-        255
+        vec![(pad_content(&cropped, &rgba, &opts), output.clone())]
     };
 
-    Ok(Rgba([r, g, b, a]))
+    if args.stats {
+        write_stats(
+            input,
+            &output,
+            &cropped,
+            &outputs[0].0,
+            bg,
+            detected,
+            &report,
+        )?;
+    }
+
+    let mut compare = args.compare.as_deref();
+    for (canvas, path) in outputs {
+        // JPEG has no alpha channel, so blend transparency into the background first
+        // (and whatever is still see-through, e.g. with --bg transparent, into white)
+        let canvas = if format == ImageFormat::Jpeg {
+            let opaque = flatten(&flatten(&canvas, bg), Rgba([255, 255, 255, 255]));
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(opaque).to_rgb8())
+        } else {
+            DynamicImage::ImageRgba8(canvas)
+        };
+        match settings.split {
+            Some(tile) => save_tiles(writer, &canvas, &path, tile, icc.as_deref())?,
+            None => writer.save(&canvas, &path, icc.as_deref())?,
+        }
+        if args.checker {
+            let preview =
+                DynamicImage::ImageRgba8(checkerboard(&canvas.to_rgba8(), args.checker_size));
+            let preview = if format == ImageFormat::Jpeg {
+                DynamicImage::ImageRgb8(preview.to_rgb8())
+            } else {
+                preview
+            };
+            writer.save(&preview, &suffixed_path(&path, "preview"), icc.as_deref())?;
+        }
+        if let Some(compare_path) = compare.take() {
+            save_comparison(&rgba, &canvas.to_rgba8(), compare_path)?;
+            writer.announce(compare_path);
+        }
+    }
+    Ok(())
 }
 
-fn parse_hex_byte(s: &str) -> Result<u8> {
-    u8::from_str_radix(s, 16).map_err(|_| {
-        Box::new(PadifyError(format!(
-            "invalid color component '{s}', expected hex"
-        ))) as Box<dyn std::error::Error>
-    })
+/// The color `--flatten` composites onto by default: the background if it is opaque, or white.
+fn flatten_color(bg: Rgba<u8>) -> Rgba<u8> {
+    if bg[3] == 255 {
+        bg
+    } else {
+        Rgba([255, 255, 255, 255])
+    }
 }
 
-struct CropReport {
-    original_height: u32,
-    new_height: u32,
-    reason: &'static str,
+/// The `--bbox` line for `input` whose content sits at `(x, y, w, h)`: plain text, or a JSON
+/// object with `json`.
+fn bbox_line(input: &Path, (x, y, w, h): (u32, u32, u32, u32), json: bool) -> String {
+    if json {
+        format!(
+            "{{\"input\": {}, \"x\": {x}, \"y\": {y}, \"width\": {w}, \"height\": {h}}}",
+            json_string(&input.to_string_lossy())
+        )
+    } else {
+        format!("{}: x {x}, y {y}, {w}x{h}", input.display())
+    }
 }
 
-struct CropResult {
-    image: RgbaImage,
-    report: CropReport,
+/// Writes the `--stats` sidecar for `output` to `<output>.json`.
+fn write_stats(
+    input: &Path,
+    output: &Path,
+    content: &RgbaImage,
+    canvas: &RgbaImage,
+    background: Rgba<u8>,
+    detected: Option<BackgroundEstimate>,
+    crop: &CropReport,
+) -> Result<()> {
+    let stats = RunStats {
+        input,
+        output,
+        content: content.dimensions(),
+        canvas: canvas.dimensions(),
+        background,
+        detected,
+        crop,
+    };
+    // Append rather than replace the extension, so shot_pad.png and shot_pad.jpg don't share one
+    let mut path = output.as_os_str().to_owned();
+    path.push(".json");
+    std::fs::write(path, stats.to_json())?;
+    Ok(())
 }
 
-impl CropResult {
-    fn no_crop(image: RgbaImage, reason: &'static str) -> Self {
-        let original_height = image.height();
-        Self {
-            image,
-            report: CropReport {
-                original_height,
-                new_height: original_height,
-                reason,
-            },
+/// What `--stats` records about one processed file.
+struct RunStats<'a> {
+    input: &'a Path,
+    output: &'a Path,
+    content: (u32, u32),
+    canvas: (u32, u32),
+    background: Rgba<u8>,
+    /// `None` when the background was given rather than detected
+    detected: Option<BackgroundEstimate>,
+    crop: &'a CropReport,
+}
+
+impl RunStats<'_> {
+    fn to_json(&self) -> String {
+        let (content_w, content_h) = self.content;
+        let (canvas_w, canvas_h) = self.canvas;
+        let canvas_area = canvas_w as f64 * canvas_h as f64;
+        let content_ratio = if canvas_area > 0.0 {
+            content_w as f64 * content_h as f64 / canvas_area
+        } else {
+            0.0
+        };
+        let [r, g, b, a] = self.background.0;
+        let background_confidence = self.detected.map_or("null".to_string(), |estimate| {
+            format!("{:.4}", estimate.confidence)
+        });
+        let background_source = self.detected.map_or(json_string("given"), |estimate| {
+            json_string(estimate.source)
+        });
+        let border_transparent_ratio = self.detected.map_or("null".to_string(), |estimate| {
+            format!("{:.4}", estimate.transparent_ratio)
+        });
+        format!(
+            "{{\n  \"input\": {},\n  \"output\": {},\n  \"width\": {canvas_w},\n  \
+             \"height\": {canvas_h},\n  \"content_width\": {content_w},\n  \
+             \"content_height\": {content_h},\n  \"content_ratio\": {content_ratio:.4},\n  \
+             \"background\": \"#{r:02x}{g:02x}{b:02x}{a:02x}\",\n  \
+             \"background_confidence\": {background_confidence},\n  \
+             \"background_source\": {background_source},\n  \
+             \"border_transparent_ratio\": {border_transparent_ratio},\n  \
+             \"crop_reason\": {},\n  \"crop_top_reason\": {},\n  \
+             \"crop_confidence\": {:.4},\n  \"rows_trimmed_top\": {},\n  \
+             \"original_height\": {},\n  \"cropped_height\": {},\n  \
+             \"crop_column_reason\": {},\n  \"columns_trimmed_left\": {},\n  \
+             \"original_width\": {},\n  \"cropped_width\": {}\n}}\n",
+            json_string(&self.input.to_string_lossy()),
+            json_string(&self.output.to_string_lossy()),
+            json_string(self.crop.reason),
+            json_string(self.crop.top_reason),
+            self.crop.confidence,
+            self.crop.top,
+            self.crop.original_height,
+            self.crop.new_height,
+            json_string(self.crop.column_reason),
+            self.crop.left,
+            self.crop.original_width,
+            self.crop.new_width,
+        )
+    }
+}
+
+/// The `--stats` line on stderr: the background used for `input` and, when it was
+/// detected, how the detection arrived at it.
+fn background_summary(input: &Path, bg: Rgba<u8>, detected: Option<BackgroundEstimate>) -> String {
+    let [r, g, b, a] = bg.0;
+    let how = match detected {
+        Some(estimate) => format!(
+            "{}, confidence {:.2}, transparent border {:.2}",
+            estimate.source, estimate.confidence, estimate.transparent_ratio
+        ),
+        None => "given".to_string(),
+    };
+    format!(
+        "padify: {}: background #{r:02x}{g:02x}{b:02x}{a:02x} ({how})",
+        input.display()
+    )
+}
+
+fn json_string(value: &str) -> String {
+    let mut output = String::from("\"");
+    for character in value.chars() {
+        match character {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            control if control.is_control() => {
+                output.push_str(&format!("\\u{:04x}", control as u32))
+            }
+            other => output.push(other),
         }
     }
+    output.push('"');
+    output
+}
 
-    fn cropped(image: RgbaImage, original_height: u32, reason: &'static str) -> Self {
-        let new_height = image.height();
-        Self {
-            image,
-            report: CropReport {
-                original_height,
-                new_height,
-                reason,
-            },
+/// Writes `original` and `padded` side by side with a gray divider, for eyeballing a run.
+fn save_comparison(original: &RgbaImage, padded: &RgbaImage, path: &Path) -> Result<()> {
+    let comparison = side_by_side(original, padded, 8, Rgba([128, 128, 128, 255]));
+    let comparison = if ImageFormat::from_path(path)? == ImageFormat::Jpeg {
+        let opaque = flatten(&comparison, Rgba([255, 255, 255, 255]));
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(opaque).to_rgb8())
+    } else {
+        DynamicImage::ImageRgba8(comparison)
+    };
+    comparison.save(path)?;
+    Ok(())
+}
+
+/// Resolves the padding for `content` from the padding flags, then applies --smart,
+/// decorations, --square, the minimum size, --round-to and --snap, in that order. The
+/// --smart, --min-*, --round-to and --snap adjustments are reported to `debug`.
+fn plan_padding(
+    args: &Args,
+    settings: &Settings,
+    content: &RgbaImage,
+    bg: Rgba<u8>,
+    debug: Option<&dyn Fn(&str)>,
+) -> Result<Padding> {
+    // --canvas and --fit pad the already scaled or cropped content to their size exactly
+    // like --to-size
+    let target = settings.to_size.or(settings.canvas).or(settings.fit);
+    let mut padding = match target {
+        Some(target) => padding_to_size(content.dimensions(), target)?,
+        None => resolve_padding(args, content.dimensions())?,
+    };
+    if args.smart {
+        padding = smart_pad(content, bg, padding);
+        if let Some(debug) = debug {
+            debug(&format!(
+                "smart padding top {}, right {}, bottom {}, left {}",
+                padding.top, padding.right, padding.bottom, padding.left
+            ));
+        }
+    }
+    padding = reserve_decorations(padding, settings.border.as_ref(), settings.shadow.as_ref());
+    if args.square {
+        padding = expand_to_aspect(content.dimensions(), padding, (1, 1))?;
+    }
+    if args.min_width.is_some() || args.min_height.is_some() {
+        let before = padded_dimensions(content.dimensions(), &padding)?;
+        let min = (args.min_width.unwrap_or(0), args.min_height.unwrap_or(0));
+        padding = expand_to_min(content.dimensions(), padding, min)?;
+        if let Some(debug) = debug {
+            let after = padded_dimensions(content.dimensions(), &padding)?;
+            debug(&format!(
+                "minimum size added {}x{} ({}x{} -> {}x{})",
+                after.0 - before.0,
+                after.1 - before.1,
+                before.0,
+                before.1,
+                after.0,
+                after.1
+            ));
         }
     }
+    // Last to change the size; --snap below only moves the content
+    if let Some(step) = args.round_to {
+        padding = round_up_to_multiple(content.dimensions(), padding, step)?;
+        if let Some(debug) = debug {
+            let (w, h) = padded_dimensions(content.dimensions(), &padding)?;
+            debug(&format!(
+                "rounded output up to {w}x{h} (multiples of {step})"
+            ));
+        }
+    }
+    if let Some(step) = settings.snap {
+        let min = reserve_decorations(
+            Padding::default(),
+            settings.border.as_ref(),
+            settings.shadow.as_ref(),
+        );
+        padding = snap_padding(padding, step, min);
+        if let Some(debug) = debug {
+            debug(&format!(
+                "snapped content to ({}, {}) on a {step:.2}px grid",
+                padding.left, padding.top
+            ));
+        }
+    }
+    let (new_w, new_h) = padded_dimensions(content.dimensions(), &padding)?;
+    if let Some((target_w, target_h)) = target
+        && (new_w, new_h) != (target_w, target_h)
+    {
+        return Err(Box::new(PadifyError(format!(
+            "border and shadow need a {new_w}x{new_h} canvas, more than the target size {target_w}x{target_h}"
+        ))));
+    }
+    Ok(padding)
 }
 
-fn auto_crop_bottom_partial(image: &RgbaImage, bg: Rgba<u8>) -> CropResult {
-    let (w, h) = image.dimensions();
+/// Scales `content` up or down to the largest size with its aspect ratio that fits in
+/// `canvas`, leaving room on every side for the border and shadow. The new size is
+/// reported to `debug`.
+fn fit_canvas(
+    args: &Args,
+    settings: &Settings,
+    content: &RgbaImage,
+    (canvas_w, canvas_h): (u32, u32),
+    debug: Option<&dyn Fn(&str)>,
+) -> Result<RgbaImage> {
+    let reserve = reserve_decorations(
+        Padding::default(),
+        settings.border.as_ref(),
+        settings.shadow.as_ref(),
+    );
+    let room_w = canvas_w.saturating_sub(2 * reserve.left.max(reserve.right));
+    let room_h = canvas_h.saturating_sub(2 * reserve.top.max(reserve.bottom));
+    if room_w == 0 || room_h == 0 {
+        return Err(Box::new(PadifyError(format!(
+            "border and shadow leave no room for the content on a {canvas_w}x{canvas_h} canvas"
+        ))));
+    }
+    let (w, h) = content.dimensions();
     if w == 0 || h == 0 {
-        return CropResult::no_crop(image.clone(), "empty");
-    }
-
-    let stride_x = std::cmp::max(1, w / 400) as usize;
-    let diff_threshold = 18u16;
-    let major_threshold = 0.02f32;
-    let minor_threshold = 0.005f32;
-
-    let mut ratios = Vec::with_capacity(h as usize);
-    for y in 0..h {
-        let mut samples = 0u32;
-        let mut non_bg = 0u32;
-        for x in (0..w).step_by(stride_x) {
-            samples += 1;
-            let pixel = image.get_pixel(x, y);
-            if !is_background(*pixel, bg, diff_threshold) {
-                non_bg += 1;
-            }
-        }
-        let ratio = if samples == 0 {
-            0.0
-        } else {
-            non_bg as f32 / samples as f32
-        };
-        ratios.push(ratio);
+        return Ok(content.clone());
+    }
+    let scale = (room_w as f64 / w as f64).min(room_h as f64 / h as f64);
+    let new_w = ((w as f64 * scale).round() as u32).clamp(1, room_w);
+    let new_h = ((h as f64 * scale).round() as u32).clamp(1, room_h);
+    if let Some(debug) = debug {
+        debug(&format!(
+            "scaled content {w}x{h} -> {new_w}x{new_h} to fit the canvas"
+        ));
     }
+    if (new_w, new_h) == (w, h) {
+        return Ok(content.clone());
+    }
+    Ok(image::imageops::resize(
+        content,
+        new_w,
+        new_h,
+        args.filter.into(),
+    ))
+}
 
-    let major_rows: Vec<bool> = ratios.iter().map(|&r| r > major_threshold).collect();
-    let minor_rows: Vec<bool> = ratios.iter().map(|&r| r > minor_threshold).collect();
+/// The part (x, y, w, h) of `content` that fits unscaled on a `canvas` for --fit, leaving
+/// room on every side for the border and shadow. Larger content is cut down to the window
+/// --gravity anchors (the middle by default); content that fits is kept whole.
+fn fit_window(
+    args: &Args,
+    settings: &Settings,
+    (w, h): (u32, u32),
+    (canvas_w, canvas_h): (u32, u32),
+) -> Result<(u32, u32, u32, u32)> {
+    let reserve = reserve_decorations(
+        Padding::default(),
+        settings.border.as_ref(),
+        settings.shadow.as_ref(),
+    );
+    let room_w = canvas_w.saturating_sub(2 * reserve.left.max(reserve.right));
+    let room_h = canvas_h.saturating_sub(2 * reserve.top.max(reserve.bottom));
+    if room_w == 0 || room_h == 0 {
+        return Err(Box::new(PadifyError(format!(
+            "border and shadow leave no room for the content on a {canvas_w}x{canvas_h} canvas"
+        ))));
+    }
+    let window = (w.min(room_w), h.min(room_h));
+    let (x, y) = gravity_offset((w, h), window, args.gravity.unwrap_or(Gravity::Center));
+    Ok((x, y, window.0, window.1))
+}
 
-    let mut clusters: Vec<(u32, u32)> = Vec::new();
-    let mut in_cluster = false;
-    let mut start = 0u32;
-    for (i, &has_content) in major_rows.iter().enumerate() {
-        if has_content && !in_cluster {
-            start = i as u32;
-            in_cluster = true;
-        } else if !has_content && in_cluster {
-            let end = i.saturating_sub(1) as u32;
-            clusters.push((start, end));
-            in_cluster = false;
+/// Downscales `content`, keeping its aspect ratio, to the largest size whose padded result
+/// fits within --max-width/--max-height. The padding is planned again for every candidate
+/// size, so margins that scale with the content (--pad-percent, --coverage, auto) shrink with it.
+/// A downscale is reported to `debug`.
+fn fit_within_max(
+    args: &Args,
+    settings: &Settings,
+    content: &RgbaImage,
+    bg: Rgba<u8>,
+    debug: Option<&dyn Fn(&str)>,
+) -> Result<RgbaImage> {
+    let (max_w, max_h) = (
+        args.max_width.unwrap_or(u32::MAX),
+        args.max_height.unwrap_or(u32::MAX),
+    );
+    let (orig_w, orig_h) = content.dimensions();
+    let long_side = orig_w.max(orig_h);
+    let size_for = |long: u32| {
+        let scale =
+            |side: u32| ((u64::from(side) * u64::from(long)) / u64::from(long_side)).max(1) as u32;
+        (scale(orig_w), scale(orig_h))
+    };
+    let fits = |(w, h): (u32, u32)| -> Result<bool> {
+        // Padding only looks at pixels for --smart, where a rough preview is enough
+        let preview = image::imageops::resize(content, w, h, FilterType::Nearest);
+        let padding = plan_padding(args, settings, &preview, bg, None)?;
+        let (padded_w, padded_h) = padded_dimensions((w, h), &padding)?;
+        Ok(padded_w <= max_w && padded_h <= max_h)
+    };
+    if long_side == 0 || fits((orig_w, orig_h))? {
+        return Ok(content.clone());
+    }
+    if !fits(size_for(1))? {
+        return Err(Box::new(PadifyError(
+            "padding alone leaves no room for the content within the maximum size".into(),
+        )));
+    }
+    // The padded size never grows as the content shrinks, so the sizes that fit form a prefix
+    let (mut lo, mut hi) = (1, long_side);
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if fits(size_for(mid))? {
+            lo = mid;
+        } else {
+            hi = mid;
         }
     }
-    if in_cluster {
-        clusters.push((start, h.saturating_sub(1)));
+    let (w, h) = size_for(lo);
+    if let Some(debug) = debug {
+        debug(&format!(
+            "scaled content {orig_w}x{orig_h} -> {w}x{h} to fit the maximum size"
+        ));
     }
+    Ok(image::imageops::resize(content, w, h, args.filter.into()))
+}
 
-    if clusters.is_empty() {
-        return CropResult::no_crop(image.clone(), "no_clusters");
-    }
+/// Saves outputs, optionally collapsing byte-identical encodings into links to the first copy.
+/// Shared by the worker threads of a batch.
+struct OutputWriter {
+    dedupe: bool,
+    /// Encoder to use instead of the one implied by each path's extension
+    format: Option<ImageFormat>,
+    quality: Option<u8>,
+    /// Kept clear of the output paths printed to stdout
+    progress: ProgressBar,
+    shared: Mutex<SharedOutput>,
+}
 
-    let bottom_margin_major = major_rows
-        .iter()
-        .rev()
-        .take_while(|&&has_content| !has_content)
-        .count() as u32;
+/// The parts of an `OutputWriter` that workers update, behind its lock.
+struct SharedOutput {
+    seen: HashMap<[u8; 32], PathBuf>,
+    duplicates: usize,
+    /// Whether the "--quality is ignored" warning has been printed already
+    warned_quality: bool,
+}
 
-    let mut heights: Vec<u32> = clusters
-        .iter()
-        .take(clusters.len().saturating_sub(1))
-        .map(|(s, e)| e.saturating_sub(*s) + 1)
-        .filter(|h| *h >= 4)
-        .collect();
+impl OutputWriter {
+    fn new(
+        dedupe: bool,
+        format: Option<ImageFormat>,
+        quality: Option<u8>,
+        progress: ProgressBar,
+    ) -> Self {
+        Self {
+            dedupe,
+            format,
+            quality,
+            progress,
+            shared: Mutex::new(SharedOutput {
+                seen: HashMap::new(),
+                duplicates: 0,
+                warned_quality: false,
+            }),
+        }
+    }
 
-    if heights.is_empty() {
-        heights = clusters
-            .iter()
-            .map(|(s, e)| e.saturating_sub(*s) + 1)
-            .collect();
+    fn lock(&self) -> MutexGuard<'_, SharedOutput> {
+        // A worker that panicked mid-update leaves nothing worth refusing to read
+        self.shared.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Prints `message` to stderr without tearing the progress bar.
+    fn note(&self, message: &str) {
+        self.progress.suspend(|| eprintln!("{message}"));
     }
 
-    let median = median_u32(&mut heights).unwrap_or(0.0);
+    /// Prints a written output's path to stdout, around the progress bar.
+    fn announce(&self, path: &Path) {
+        self.progress.suspend(|| println!("{}", path.display()));
+    }
 
-    if clusters.len() >= 2 && bottom_margin_major <= 2 && median > 0.0 {
-        let (last_start, last_end) = *clusters.last().unwrap();
-        let last_height = last_end.saturating_sub(last_start) + 1;
-        if (last_height as f32) < median * 0.7 {
-            if last_start > 0 {
-                let cropped = image::imageops::crop_imm(image, 0, 0, w, last_start).to_image();
-                return CropResult::cropped(cropped, h, "partial_line");
+    /// Encodes `image` to `path`, embedding the `icc` color profile where the format allows.
+    fn save(&self, image: &DynamicImage, path: &Path, icc: Option<&[u8]>) -> Result<()> {
+        let format = match self.format {
+            Some(format) => format,
+            None => ImageFormat::from_path(path)?,
+        };
+        check_writable(format)?;
+        check_format_limits(format, image.dimensions())?;
+        if self.quality.is_some() && !matches!(format, ImageFormat::Jpeg | ImageFormat::WebP) {
+            let mut shared = self.lock();
+            if !shared.warned_quality {
+                self.note(&format!(
+                    "padify: --quality only applies to JPEG and WebP output; ignoring it for {}",
+                    format.extensions_str()[0]
+                ));
+                shared.warned_quality = true;
             }
         }
+        if !self.dedupe {
+            remove_stale_link(path)?;
+            let mut file = BufWriter::new(File::create(path)?);
+            encode(image, format, self.quality, icc, &mut file)?;
+            file.flush()?;
+            self.announce(path);
+            return Ok(());
+        }
+
+        let mut encoded = Cursor::new(Vec::new());
+        encode(image, format, self.quality, icc, &mut encoded)?;
+        self.store(&encoded.into_inner(), path)
+    }
+
+    /// Encodes `frames` to `path` as an animated GIF that plays `repeat` times.
+    fn save_animation(&self, frames: Vec<Frame>, repeat: Repeat, path: &Path) -> Result<()> {
+        check_writable(ImageFormat::Gif)?;
+        if let Some(frame) = frames.first() {
+            check_format_limits(ImageFormat::Gif, frame.buffer().dimensions())?;
+        }
+        let mut encoded = Vec::new();
+        {
+            // Speed 10 trades a little palette quality for much faster quantization
+            let mut encoder = GifEncoder::new_with_speed(&mut encoded, 10);
+            encoder.set_repeat(repeat)?;
+            encoder.encode_frames(frames)?;
+        }
+        if self.dedupe {
+            return self.store(&encoded, path);
+        }
+        remove_stale_link(path)?;
+        std::fs::write(path, &encoded)?;
+        self.announce(path);
+        Ok(())
     }
 
-    let last_major = major_rows.iter().rposition(|&v| v);
-    let last_minor = minor_rows.iter().rposition(|&v| v);
-    if let (Some(last_major), Some(last_minor)) = (last_major, last_minor) {
-        if last_minor > last_major {
-            let mut start_minor = last_minor;
-            while start_minor > 0 && minor_rows[start_minor - 1] {
-                start_minor -= 1;
+    /// Writes `encoded` to `path`, or links `path` to an earlier identical output. The lock
+    /// is held throughout, so two workers never both write the same content as the original.
+    fn store(&self, encoded: &[u8], path: &Path) -> Result<()> {
+        let digest: [u8; 32] = Sha256::digest(encoded).into();
+        let mut shared = self.lock();
+        match shared.seen.get(&digest) {
+            Some(original) => {
+                link_duplicate(original, path)?;
+                shared.duplicates += 1;
             }
-            let block_height = (last_minor - start_minor + 1) as u32;
-            let gap = start_minor.saturating_sub(last_major + 1) as u32;
-            let line_height = if median > 0.0 {
-                median
-            } else {
-                clamp_u32(h / 30, 12, 28) as f32
-            };
-            let thin_block = (block_height as f32) < line_height * 0.35;
-            let min_gap = std::cmp::max(2, (line_height * 0.2).round() as u32);
-            let gap_ok = gap >= min_gap || (thin_block && gap >= 1);
-            if gap_ok && (block_height as f32) < line_height * 0.6 {
-                let cropped =
-                    image::imageops::crop_imm(image, 0, 0, w, start_minor as u32).to_image();
-                return CropResult::cropped(cropped, h, "cursor_residue");
+            None => {
+                remove_stale_link(path)?;
+                std::fs::write(path, encoded)?;
+                shared.seen.insert(digest, path.to_path_buf());
             }
         }
+        self.announce(path);
+        Ok(())
     }
+}
 
-    CropResult::no_crop(image.clone(), "clean")
+/// Encodes `image` as `format`, honoring `quality` where the encoder supports it. WebP is
+/// lossless unless a `quality` is given.
+fn encode<W: Write + Seek>(
+    image: &DynamicImage,
+    format: ImageFormat,
+    quality: Option<u8>,
+    icc: Option<&[u8]>,
+    writer: &mut W,
+) -> Result<()> {
+    match format {
+        ImageFormat::Jpeg => {
+            // 75 is what the image crate's own JPEG output uses
+            let encoder = JpegEncoder::new_with_quality(writer, quality.unwrap_or(75));
+            image.write_with_encoder(with_icc(encoder, icc))?
+        }
+        ImageFormat::Png => image.write_with_encoder(with_icc(PngEncoder::new(writer), icc))?,
+        #[cfg(feature = "webp")]
+        ImageFormat::WebP => match quality {
+            Some(quality) => encode_lossy_webp(image, quality, writer)?,
+            None => image.write_with_encoder(with_icc(WebPEncoder::new_lossless(writer), icc))?,
+        },
+        ImageFormat::Tiff => image.write_with_encoder(with_icc(TiffEncoder::new(writer), icc))?,
+        _ => image.write_to(writer, format)?,
+    }
+    Ok(())
 }
 
-fn is_background(pixel: Rgba<u8>, bg: Rgba<u8>, threshold: u16) -> bool {
-    let dr = (pixel[0] as i16 - bg[0] as i16).abs() as u16;
-    let dg = (pixel[1] as i16 - bg[1] as i16).abs() as u16;
-    let db = (pixel[2] as i16 - bg[2] as i16).abs() as u16;
-    let da = (pixel[3] as i16 - bg[3] as i16).abs() as u16;
-    dr + dg + db + da <= threshold
+/// Encodes `image` as lossy WebP through libwebp, which the `image` crate's encoder can't
+/// produce. Transparency is kept, though compressed too; no ICC profile is embedded.
+#[cfg(feature = "webp")]
+fn encode_lossy_webp<W: Write>(image: &DynamicImage, quality: u8, writer: &mut W) -> Result<()> {
+    let rgba = image.to_rgba8();
+    let encoded = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height())
+        .encode_simple(false, quality.into())
+        .map_err(|e| PadifyError(format!("WebP encoding failed: {e:?}")))?;
+    writer.write_all(&encoded)?;
+    Ok(())
 }
 
-fn median_u32(values: &mut [u32]) -> Option<f32> {
-    if values.is_empty() {
-        return None;
-    }
-    values.sort_unstable();
-    let mid = values.len() / 2;
-    if values.len() % 2 == 1 {
-        Some(values[mid] as f32)
-    } else {
-        Some((values[mid - 1] as f32 + values[mid] as f32) / 2.0)
+/// Hands `icc` to `encoder`; encoders that can't embed a profile write the image without.
+fn with_icc<E: ImageEncoder>(mut encoder: E, icc: Option<&[u8]>) -> E {
+    if let Some(icc) = icc {
+        let _ = encoder.set_icc_profile(icc.to_vec());
     }
+    encoder
 }
 
-#[derive(Default, Clone, Copy)]
-struct Bucket {
-    count: u32,
-    sum_r: u64,
-    sum_g: u64,
-    sum_b: u64,
-    sum_a: u64,
+/// Decodes `input` upright, applying its EXIF orientation, and returns it with its
+/// embedded ICC color profile, if any.
+fn load_image(input: &Path) -> Result<(DynamicImage, Option<Vec<u8>>)> {
+    let mut decoder = image::ImageReader::open(input)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    // Unreadable metadata shouldn't cost the image itself
+    let icc = decoder.icc_profile().ok().flatten();
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok((image, icc))
 }
 
-fn deduce_background(image: &RgbaImage) -> Rgba<u8> {
-    let (w, h) = image.dimensions();
-    if w == 0 || h == 0 {
-        return Rgba([0, 0, 0, 0]);
+/// Decodes every frame of `input` and its loop count when it is an animated GIF; `None`
+/// for still images and single-frame GIFs.
+fn load_animation(input: &Path) -> Result<Option<(Vec<Frame>, Repeat)>> {
+    let reader = image::ImageReader::open(input)?.with_guessed_format()?;
+    if reader.format() != Some(ImageFormat::Gif) {
+        return Ok(None);
     }
+    let frames = GifDecoder::new(BufReader::new(File::open(input)?))?
+        .into_frames()
+        .collect_frames()?;
+    if frames.len() < 2 {
+        return Ok(None);
+    }
+    // The image crate's decoder doesn't expose the loop count, so read it with gif directly
+    let mut decoder = gif::DecodeOptions::new()
+        .read_info(BufReader::new(File::open(input)?))
+        .map_err(|e| PadifyError(format!("failed to read {}: {e}", input.display())))?;
+    decoder
+        .next_frame_info()
+        .map_err(|e| PadifyError(format!("failed to read {}: {e}", input.display())))?;
+    let repeat = match decoder.repeat() {
+        gif::Repeat::Infinite => Repeat::Infinite,
+        gif::Repeat::Finite(count) => Repeat::Finite(count),
+    };
+    Ok(Some((frames, repeat)))
+}
+
+/// Pads every frame like the first: cuts `region` (x, y, w, h) out of it, scales that to
+/// `size` if the content was scaled, and pads it with `opts`, keeping each frame's delay.
+fn pad_frames(
+    frames: Vec<Frame>,
+    (x, y, w, h): (u32, u32, u32, u32),
+    size: (u32, u32),
+    opts: &PadOptions,
+    filter: FilterType,
+) -> Vec<Frame> {
+    frames
+        .into_iter()
+        .map(|frame| {
+            let delay = frame.delay();
+            let source = frame.into_buffer();
+            let mut content = image::imageops::crop_imm(&source, x, y, w, h).to_image();
+            if content.dimensions() != size {
+                content = image::imageops::resize(&content, size.0, size.1, filter);
+            }
+            Frame::from_parts(pad_content(&content, &source, opts), 0, 0, delay)
+        })
+        .collect()
+}
 
-    let stride_x = std::cmp::max(1, w / 200) as usize;
-    let stride_y = std::cmp::max(1, h / 200) as usize;
-    let band = clamp_u32(std::cmp::min(w, h) / 20, 8, 64);
+/// Rejects images larger than `format` can store, before the encoder fails with a vaguer error.
+fn check_format_limits(format: ImageFormat, (w, h): (u32, u32)) -> Result<()> {
+    let max_side = match format {
+        ImageFormat::Jpeg => 65_535,
+        ImageFormat::WebP => 16_383,
+        ImageFormat::Gif | ImageFormat::Ico => 65_535,
+        _ => return Ok(()),
+    };
+    if w > max_side || h > max_side {
+        return Err(Box::new(PadifyError(format!(
+            "{w}x{h} is too large for {format:?} output (at most {max_side} pixels per side)"
+        ))));
+    }
+    Ok(())
+}
 
-    let border = dominant_sample(image, stride_x, stride_y, |x, y| {
-        x < band || x >= w.saturating_sub(band) || y < band || y >= h.saturating_sub(band)
-    });
+/// Parses a `--format` name such as `png`, `jpg` or `webp` into a format this build can write.
+fn parse_format(input: &str) -> Result<ImageFormat> {
+    let name = input.trim().trim_start_matches('.');
+    let format = ImageFormat::from_extension(name)
+        .ok_or_else(|| PadifyError(format!("unknown output format '{input}'")))?;
+    check_writable(format)?;
+    Ok(format)
+}
 
-    if let Some(color) = border.color_if_confident(0.2) {
-        return color;
+/// Rejects formats this build of padify can't encode, naming the cargo feature for WebP.
+fn check_writable(format: ImageFormat) -> Result<()> {
+    if format.writing_enabled() {
+        return Ok(());
     }
+    let name = format.extensions_str()[0];
+    let hint = if format == ImageFormat::WebP {
+        "; rebuild with `--features webp` to enable it"
+    } else {
+        ""
+    };
+    Err(Box::new(PadifyError(format!(
+        "output format '{name}' is not supported by this build of padify{hint}"
+    ))))
+}
 
-    if border.transparent_ratio() >= 0.6 {
-        return Rgba([0, 0, 0, 0]);
+/// Removes a link left at `path` by an earlier `--dedupe` run, so writing there replaces
+/// the link instead of overwriting the output it points to.
+fn remove_stale_link(path: &Path) -> Result<()> {
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+        std::fs::remove_file(path)?;
     }
+    Ok(())
+}
 
-    let overall = dominant_sample(image, stride_x, stride_y, |_x, _y| true);
-    overall
-        .color_if_confident(0.1)
-        .unwrap_or_else(|| Rgba([0, 0, 0, 0]))
+#[cfg(unix)]
+fn link_duplicate(original: &Path, duplicate: &Path) -> Result<()> {
+    let target = if original.parent() == duplicate.parent() {
+        PathBuf::from(original.file_name().unwrap_or(original.as_os_str()))
+    } else {
+        std::fs::canonicalize(original)?
+    };
+    if std::fs::symlink_metadata(duplicate).is_ok() {
+        std::fs::remove_file(duplicate)?;
+    }
+    std::os::unix::fs::symlink(target, duplicate)?;
+    Ok(())
 }
 
-struct SampleResult {
-    total: u32,
-    transparent: u32,
-    best: Option<Bucket>,
+#[cfg(not(unix))]
+fn link_duplicate(original: &Path, duplicate: &Path) -> Result<()> {
+    std::fs::copy(original, duplicate)?;
+    Ok(())
 }
 
-impl SampleResult {
-    fn color_if_confident(&self, threshold: f32) -> Option<Rgba<u8>> {
-        let non_transparent = self.total.saturating_sub(self.transparent);
-        if non_transparent == 0 {
-            return None;
-        }
+fn save_tiles(
+    writer: &OutputWriter,
+    canvas: &DynamicImage,
+    output: &Path,
+    (tile_w, tile_h): (u32, u32),
+    icc: Option<&[u8]>,
+) -> Result<()> {
+    let (w, h) = (canvas.width(), canvas.height());
+    let cols = w.div_ceil(tile_w);
+    let rows = h.div_ceil(tile_h);
+    if rows <= 1 && cols <= 1 {
+        return writer.save(canvas, output, icc);
+    }
 
-        let bucket = self.best?;
-        let ratio = bucket.count as f32 / non_transparent as f32;
-        if ratio < threshold {
-            return None;
+    writer.note(&format!(
+        "padify: split {w}x{h} into {rows} row(s) x {cols} column(s)"
+    ));
+    for row in 0..rows {
+        for col in 0..cols {
+            let x = col * tile_w;
+            let y = row * tile_h;
+            let tile = canvas.crop_imm(x, y, tile_w.min(w - x), tile_h.min(h - y));
+            let path = tile_path(output, row, col);
+            writer.save(&tile, &path, icc)?;
         }
+    }
+    Ok(())
+}
 
-        let count = bucket.count as u64;
-        Some(Rgba([
-            (bucket.sum_r / count) as u8,
-            (bucket.sum_g / count) as u8,
-            (bucket.sum_b / count) as u8,
-            (bucket.sum_a / count) as u8,
-        ]))
+fn tile_path(output: &Path, row: u32, col: u32) -> PathBuf {
+    suffixed_path(output, &format!("r{row}c{col}"))
+}
+
+/// The first file a run would write for `output` that already exists, if any.
+fn existing_output(output: &Path, args: &Args) -> Option<PathBuf> {
+    let mut planned = if args.variants {
+        vec![
+            suffixed_path(output, "flat"),
+            suffixed_path(output, "alpha"),
+        ]
+    } else {
+        vec![output.to_path_buf()]
+    };
+    if args.checker {
+        let previews: Vec<PathBuf> = planned
+            .iter()
+            .map(|path| suffixed_path(path, "preview"))
+            .collect();
+        planned.extend(previews);
+    }
+    if args.split.is_some() {
+        // A canvas that fits in one tile is saved without the tile suffix
+        let tiles: Vec<PathBuf> = planned.iter().map(|path| tile_path(path, 0, 0)).collect();
+        planned.extend(tiles);
     }
+    // symlink_metadata also catches links left by --dedupe, even dangling ones
+    planned
+        .into_iter()
+        .find(|path| std::fs::symlink_metadata(path).is_ok())
+}
+
+/// Returns `<stem>_<suffix>.<ext>` next to `path`.
+fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "output".to_string());
+    let file_name = match path.extension() {
+        Some(ext) => format!("{stem}_{suffix}.{}", ext.to_string_lossy()),
+        None => format!("{stem}_{suffix}"),
+    };
+    path.with_file_name(file_name)
+}
+
+/// Parses a `PX:HEX` border such as `4:#ffffff`.
+fn parse_border(input: &str) -> Result<(u32, Rgba<u8>)> {
+    let invalid = || {
+        PadifyError(format!(
+            "invalid border '{input}', expected PX:HEX (e.g. 4:#ffffff)"
+        ))
+    };
+    let (width, color) = input.trim().split_once(':').ok_or_else(invalid)?;
+    let width: u32 = width.trim().parse().map_err(|_| invalid())?;
+    Ok((width, parse_color(color)?))
+}
+
+/// Parses an `X,Y` pixel offset such as `0,8` or `-4,4`.
+fn parse_offset(input: &str) -> Result<(i32, i32)> {
+    let invalid = || PadifyError(format!("invalid offset '{input}', expected X,Y (e.g. 0,8)"));
+    let (x, y) = input.trim().split_once(',').ok_or_else(invalid)?;
+    let x: i32 = x.trim().parse().map_err(|_| invalid())?;
+    let y: i32 = y.trim().parse().map_err(|_| invalid())?;
+    Ok((x, y))
+}
+
+/// Where `--resize` scales the input to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resize {
+    Exact(u32, u32),
+    Percent(f64),
+}
 
-    fn transparent_ratio(&self) -> f32 {
-        if self.total == 0 {
-            return 1.0;
+impl Resize {
+    /// The size an image of `(w, h)` is scaled to; percentages never go below one pixel.
+    fn dimensions(self, (w, h): (u32, u32)) -> (u32, u32) {
+        match self {
+            Resize::Exact(w, h) => (w, h),
+            Resize::Percent(percent) => {
+                let scale = |side: u32| ((side as f64 * percent / 100.0).round() as u32).max(1);
+                (scale(w), scale(h))
+            }
         }
-        self.transparent as f32 / self.total as f32
     }
 }
 
-fn dominant_sample<F>(
-    image: &RgbaImage,
-    stride_x: usize,
-    stride_y: usize,
-    include: F,
-) -> SampleResult
-where
-    F: Fn(u32, u32) -> bool,
-{
-    let (w, h) = image.dimensions();
-    let mut buckets: HashMap<u32, Bucket> = HashMap::new();
-    let mut total: u32 = 0;
-    let mut transparent: u32 = 0;
+/// Parses `--resize` as `WxH` or a percentage such as `50%`.
+fn parse_resize(input: &str) -> Result<Resize> {
+    let Some(percent) = input.trim().strip_suffix('%') else {
+        let (w, h) = parse_size(input)?;
+        return Ok(Resize::Exact(w, h));
+    };
+    match percent.trim().parse::<f64>() {
+        Ok(percent) if percent.is_finite() && percent > 0.0 => Ok(Resize::Percent(percent)),
+        _ => Err(Box::new(PadifyError(format!(
+            "invalid resize '{input}', expected WxH or a positive percentage (e.g. 50%)"
+        )))),
+    }
+}
 
-    for y in (0..h).step_by(stride_y) {
-        for x in (0..w).step_by(stride_x) {
-            if !include(x, y) {
-                continue;
-            }
-            let pixel = image.get_pixel(x, y);
-            total = total.saturating_add(1);
-            if pixel[3] <= 5 {
-                transparent = transparent.saturating_add(1);
-                continue;
+fn parse_size(input: &str) -> Result<(u32, u32)> {
+    let invalid = || {
+        PadifyError(format!(
+            "invalid size '{input}', expected WxH (e.g. 1024x768)"
+        ))
+    };
+    let (w, h) = input.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+    let w: u32 = w.trim().parse().map_err(|_| invalid())?;
+    let h: u32 = h.trim().parse().map_err(|_| invalid())?;
+    if w == 0 || h == 0 {
+        return Err(Box::new(invalid()));
+    }
+    Ok((w, h))
+}
+
+/// Parses a length such as `0.125in`, `3mm`, `0.5cm` or `32px` (bare numbers are pixels)
+/// into pixels; physical units need `dpi`. Lengths under a pixel are rejected.
+fn parse_length(input: &str, dpi: Option<f64>) -> Result<f64> {
+    let invalid = || {
+        PadifyError(format!(
+            "invalid length '{input}', expected a number with in, cm, mm or px (e.g. 0.125in)"
+        ))
+    };
+    let trimmed = input.trim();
+    let split = trimmed
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let value: f64 = number.trim().parse().map_err(|_| invalid())?;
+    let per_inch = match unit.to_ascii_lowercase().as_str() {
+        "" | "px" => None,
+        "in" => Some(1.0),
+        "cm" => Some(2.54),
+        "mm" => Some(25.4),
+        _ => return Err(Box::new(invalid())),
+    };
+    let pixels = match per_inch {
+        None => value,
+        Some(per_inch) => {
+            let dpi = dpi.ok_or_else(|| {
+                PadifyError(format!("length '{input}' needs --dpi to convert to pixels"))
+            })?;
+            value / per_inch * dpi
+        }
+    };
+    if !(pixels.is_finite() && pixels >= 1.0) {
+        return Err(Box::new(PadifyError(format!(
+            "length '{input}' must be at least one pixel"
+        ))));
+    }
+    Ok(pixels)
+}
+
+fn resolve_padding(args: &Args, (w, h): (u32, u32)) -> Result<Padding> {
+    if let Some(coverage) = args.coverage {
+        return Ok(Padding::uniform(coverage_pad((w, h), coverage)?));
+    }
+    let auto = auto_pad(w.min(h), args.auto_ratio, args.auto_min, args.auto_max);
+    let pad = if let Some(all) = args.all {
+        all
+    } else if let Some(percent) = args.pad_percent {
+        percent_pad(w.min(h), percent)?
+    } else {
+        match (args.pad_x, args.pad_y) {
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return Err(Box::new(PadifyError(
+                        "pad-x and pad-y must be equal (or use --all/--pad)".into(),
+                    )));
+                }
+                x
             }
+            (Some(x), None) => x,
+            (None, Some(y)) => y,
+            (None, None) => auto,
+        }
+    };
+    Ok(Padding {
+        top: args.pad_top.unwrap_or(pad),
+        right: args.pad_right.unwrap_or(pad),
+        bottom: args.pad_bottom.unwrap_or(pad),
+        left: args.pad_left.unwrap_or(pad),
+    })
+}
+
+/// `<input stem>_pad.<ext>` next to `input`, with the extension of `format` when one is forced.
+fn default_output_path(input: &Path, format: Option<ImageFormat>) -> PathBuf {
+    let parent = input.parent().unwrap_or_else(|| Path::new("."));
+    let stem = input
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "output".to_string());
+    let ext = match format.and_then(|format| format.extensions_str().first()) {
+        Some(ext) => Some(std::borrow::Cow::Borrowed(*ext)),
+        None => input.extension().map(|s| s.to_string_lossy()),
+    };
+
+    let file_name = match ext {
+        Some(ext) if !ext.is_empty() => format!("{stem}_pad.{ext}"),
+        _ => format!("{stem}_pad"),
+    };
+
+    parent.join(file_name)
+}
+
+/// Makes sure `output` ends in an extension `image` can encode, falling back to the
+/// forced `format`, then the input's format (or PNG) when it has none or an unrecognized one.
+fn with_format_extension(output: PathBuf, input: &Path, format: Option<ImageFormat>) -> PathBuf {
+    if ImageFormat::from_path(&output).is_ok() {
+        return output;
+    }
+    let format = format
+        .or_else(|| ImageFormat::from_path(input).ok())
+        .or_else(|| {
+            image::ImageReader::open(input)
+                .and_then(|reader| reader.with_guessed_format())
+                .ok()
+                .and_then(|reader| reader.format())
+        })
+        .unwrap_or(ImageFormat::Png);
+    let ext = format.extensions_str().first().copied().unwrap_or("png");
+    let mut name = output.into_os_string();
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tile_size() {
+        assert_eq!(parse_size("1024x768").expect("valid size"), (1024, 768));
+        assert!(parse_size("0x10").is_err());
+        assert!(parse_size("wide").is_err());
+    }
+
+    #[test]
+    fn parses_resize_as_size_or_percentage() {
+        assert_eq!(
+            parse_resize("640x480").expect("valid size"),
+            Resize::Exact(640, 480)
+        );
+        let half = parse_resize("50%").expect("valid percentage");
+        assert_eq!(half, Resize::Percent(50.0));
+        assert_eq!(half.dimensions((2880, 1801)), (1440, 901));
+        assert_eq!(Resize::Percent(1.0).dimensions((10, 10)), (1, 1));
+        assert!(parse_resize("0x0").is_err());
+        assert!(parse_resize("0%").is_err());
+        assert!(parse_resize("-20%").is_err());
+        assert!(parse_resize("half").is_err());
+    }
+
+    #[test]
+    fn parses_border_spec() {
+        assert_eq!(
+            parse_border("4:#ff0000").expect("valid border"),
+            (4, Rgba([255, 0, 0, 255]))
+        );
+        assert!(parse_border("#ff0000").is_err());
+    }
+
+    #[test]
+    fn parses_physical_lengths() {
+        assert_eq!(parse_length("0.125in", Some(300.0)).expect("valid"), 37.5);
+        assert_eq!(parse_length("25.4mm", Some(72.0)).expect("valid"), 72.0);
+        assert_eq!(parse_length("32px", None).expect("valid"), 32.0);
+        assert_eq!(parse_length("16", None).expect("valid"), 16.0);
+        assert!(parse_length("1in", None).is_err());
+        assert!(parse_length("0.001in", Some(300.0)).is_err());
+        assert!(parse_length("2ft", Some(300.0)).is_err());
+    }
+
+    #[test]
+    fn parses_shadow_offset() {
+        assert_eq!(parse_offset("0,8").expect("valid offset"), (0, 8));
+        assert_eq!(parse_offset(" -4 , 6 ").expect("valid offset"), (-4, 6));
+        assert!(parse_offset("4x6").is_err());
+    }
+
+    #[test]
+    fn forced_format_picks_default_extension() {
+        assert_eq!(
+            parse_format("jpg").expect("known format"),
+            ImageFormat::Jpeg
+        );
+        assert!(parse_format("docx").is_err());
+        assert_eq!(
+            default_output_path(Path::new("shots/a.png"), Some(ImageFormat::Jpeg)),
+            PathBuf::from("shots/a_pad.jpg")
+        );
+        assert_eq!(
+            default_output_path(Path::new("shots/a.png"), None),
+            PathBuf::from("shots/a_pad.png")
+        );
+    }
 
-            let key = quantize_key(*pixel);
-            let entry = buckets.entry(key).or_insert_with(Bucket::default);
-            entry.count = entry.count.saturating_add(1);
-            entry.sum_r += pixel[0] as u64;
-            entry.sum_g += pixel[1] as u64;
-            entry.sum_b += pixel[2] as u64;
-            entry.sum_a += pixel[3] as u64;
+    #[test]
+    fn max_width_scales_content_and_proportional_padding() {
+        let args = Args::parse_from([
+            "padify",
+            "in.png",
+            "--max-width",
+            "120",
+            "--pad-percent",
+            "10",
+        ]);
+        let settings = Settings::from_args(&args).expect("valid settings");
+        let content = RgbaImage::new(300, 200);
+        let fitted =
+            fit_within_max(&args, &settings, &content, Rgba([0, 0, 0, 0]), None).expect("fits");
+        // 10% of the short side (70) pads each side by 7: 106 + 2 * 7 is exactly the maximum
+        assert_eq!(fitted.dimensions(), (106, 70));
+    }
+
+    #[test]
+    fn auto_padding_uses_configured_ratio_and_clamps() {
+        let args = Args::parse_from(["padify", "in.png", "--auto-ratio", "0.1", "--pad-top", "5"]);
+        let padding = resolve_padding(&args, (1000, 800)).expect("valid padding");
+        assert_eq!((padding.top, padding.left), (5, 80));
+
+        let clamped = Args::parse_from(["padify", "in.png", "--auto-max", "60"]);
+        assert_eq!(
+            resolve_padding(&clamped, (2000, 2000)).expect("valid").left,
+            60
+        );
+
+        let inverted =
+            Args::parse_from(["padify", "in.png", "--auto-min", "90", "--auto-max", "60"]);
+        assert!(Settings::from_args(&inverted).is_err());
+        let negative = Args::parse_from(["padify", "in.png", "--auto-ratio=-0.1"]);
+        assert!(Settings::from_args(&negative).is_err());
+    }
+
+    #[test]
+    fn canvas_scales_content_to_fit_exactly() {
+        let args = Args::parse_from(["padify", "in.png", "--canvas", "400x400"]);
+        let settings = Settings::from_args(&args).expect("valid settings");
+        let content = RgbaImage::new(300, 200);
+        let fitted = fit_canvas(&args, &settings, &content, (400, 400), None).expect("fits");
+        assert_eq!(fitted.dimensions(), (400, 267));
+        let padding =
+            plan_padding(&args, &settings, &fitted, Rgba([0, 0, 0, 0]), None).expect("pads");
+        assert_eq!(
+            padded_dimensions(fitted.dimensions(), &padding).expect("valid size"),
+            (400, 400)
+        );
+
+        let framed = Args::parse_from([
+            "padify",
+            "in.png",
+            "--canvas",
+            "100x50",
+            "--border",
+            "5:#ffffff",
+        ]);
+        let settings = Settings::from_args(&framed).expect("valid settings");
+        let fitted = fit_canvas(&framed, &settings, &content, (100, 50), None).expect("fits");
+        assert_eq!(fitted.dimensions(), (60, 40));
+        assert!(
+            Args::try_parse_from(["padify", "in.png", "--canvas", "4x4", "--pad", "3"]).is_err()
+        );
+        assert!(parse_size("400by300").is_err());
+    }
+
+    #[test]
+    fn fit_crops_oversized_content_by_gravity() {
+        let args = Args::parse_from(["padify", "in.png", "--fit", "200x300"]);
+        let settings = Settings::from_args(&args).expect("valid settings");
+        assert_eq!(settings.fit, Some((200, 300)));
+        // Wider than the canvas: the middle 200 columns are kept, the height is untouched
+        let window = fit_window(&args, &settings, (300, 100), (200, 300)).expect("fits");
+        assert_eq!(window, (50, 0, 200, 100));
+        let content = RgbaImage::new(200, 100);
+        let padding =
+            plan_padding(&args, &settings, &content, Rgba([0, 0, 0, 0]), None).expect("pads");
+        assert_eq!((padding.left, padding.top, padding.bottom), (0, 100, 100));
+
+        let south_east = Args::parse_from([
+            "padify",
+            "in.png",
+            "--fit",
+            "200x300",
+            "--gravity",
+            "southeast",
+        ]);
+        let window = fit_window(&south_east, &settings, (300, 400), (200, 300)).expect("fits");
+        assert_eq!(window, (100, 100, 200, 300));
+        let exact = fit_window(&args, &settings, (200, 300), (200, 300)).expect("fits");
+        assert_eq!(exact, (0, 0, 200, 300));
+        assert!(
+            Args::try_parse_from(["padify", "in.png", "--fit", "2x2", "--canvas", "4x4"]).is_err()
+        );
+        assert!(
+            Settings::from_args(&Args::parse_from(["padify", "in.png", "--fit", "0x10"])).is_err()
+        );
+    }
+
+    #[test]
+    fn loads_upright_and_keeps_color_profile() {
+        // Big-endian EXIF with a single Orientation entry: 6, rotate 90° clockwise
+        let exif = [
+            b'M', b'M', 0, 42, 0, 0, 0, 8, 0, 1, 0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0,
+            0,
+        ];
+        let icc = b"fake icc profile".to_vec();
+        let mut png = Vec::new();
+        {
+            let mut encoder = PngEncoder::new(&mut png);
+            encoder
+                .set_exif_metadata(exif.to_vec())
+                .expect("png takes exif");
+            encoder.set_icc_profile(icc.clone()).expect("png takes icc");
+            let pixels = RgbaImage::from_fn(3, 2, |x, _| Rgba([x as u8 * 100, 0, 0, 255]));
+            encoder
+                .write_image(&pixels, 3, 2, image::ExtendedColorType::Rgba8)
+                .expect("encodes");
         }
+        let path = std::env::temp_dir().join(format!("padify-orient-{}.png", std::process::id()));
+        std::fs::write(&path, &png).expect("writes");
+        let loaded = load_image(&path);
+        std::fs::remove_file(&path).expect("removes");
+        let (image, loaded_icc) = loaded.expect("loads");
+        assert_eq!(image.dimensions(), (2, 3));
+        assert_eq!(loaded_icc.as_deref(), Some(&icc[..]));
+
+        let mut out = Cursor::new(Vec::new());
+        encode(&image, ImageFormat::Png, None, Some(&icc), &mut out).expect("encodes");
+        let mut decoder =
+            image::codecs::png::PngDecoder::new(Cursor::new(out.into_inner())).expect("decodes");
+        assert_eq!(decoder.icc_profile().expect("reads icc"), Some(icc));
     }
 
-    let mut best: Option<Bucket> = None;
-    let mut best_count = 0u32;
-    for bucket in buckets.values() {
-        if bucket.count > best_count {
-            best_count = bucket.count;
-            best = Some(*bucket);
+    #[test]
+    fn pads_every_animation_frame_and_keeps_timing() {
+        let frame = |shade: u8, millis: u32| {
+            let mut buffer = RgbaImage::from_pixel(6, 4, Rgba([255, 255, 255, 255]));
+            buffer.put_pixel(2, 1, Rgba([shade, 0, 0, 255]));
+            let delay = image::Delay::from_numer_denom_ms(millis, 1);
+            Frame::from_parts(buffer, 0, 0, delay)
+        };
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            encoder.set_repeat(Repeat::Finite(3)).expect("sets repeat");
+            encoder
+                .encode_frames([frame(0, 100), frame(200, 250)])
+                .expect("encodes");
+        }
+        let path = std::env::temp_dir().join(format!("padify-anim-{}.gif", std::process::id()));
+        std::fs::write(&path, &gif).expect("writes");
+        let loaded = load_animation(&path);
+        std::fs::remove_file(&path).expect("removes");
+        let (frames, repeat) = loaded.expect("loads").expect("is animated");
+        assert!(matches!(repeat, Repeat::Finite(3)));
+
+        let opts = PadOptions {
+            padding: Padding::uniform(2),
+            background: Rgba([255, 255, 255, 255]),
+            ..PadOptions::default()
+        };
+        let padded = pad_frames(frames, (2, 1, 3, 2), (3, 2), &opts, FilterType::Nearest);
+        assert_eq!(padded.len(), 2);
+        for (frame, millis) in padded.iter().zip([100, 250]) {
+            assert_eq!(frame.buffer().dimensions(), (7, 6));
+            assert_eq!(frame.delay(), image::Delay::from_numer_denom_ms(millis, 1));
         }
+        assert_eq!(padded[1].buffer().get_pixel(2, 2)[0], 200);
     }
 
-    SampleResult {
-        total,
-        transparent,
-        best,
+    #[test]
+    fn quality_shrinks_jpeg_output() {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x ^ y) * 4) as u8])
+        }));
+        let encoded_len = |quality| {
+            let mut out = Cursor::new(Vec::new());
+            encode(&image, ImageFormat::Jpeg, quality, None, &mut out).expect("encodes");
+            out.into_inner().len()
+        };
+        assert!(encoded_len(Some(10)) < encoded_len(Some(95)));
     }
-}
 
-fn clamp_u32(value: u32, min: u32, max: u32) -> u32 {
-    if value < min {
-        min
-    } else if value > max {
-        max
-    } else {
-        value
+    #[cfg(feature = "webp")]
+    #[test]
+    fn webp_is_lossless_unless_quality_is_given() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            Rgba([
+                (x * 4) as u8,
+                (y * 4) as u8,
+                ((x ^ y) * 4) as u8,
+                (x * 3) as u8,
+            ])
+        }));
+        let encoded = |quality| {
+            let mut out = Cursor::new(Vec::new());
+            encode(&image, ImageFormat::WebP, quality, None, &mut out).expect("encodes");
+            out.into_inner()
+        };
+        let lossless = image::load_from_memory(&encoded(None)).expect("decodes");
+        assert_eq!(lossless.to_rgba8(), image.to_rgba8());
+        let lossy = image::load_from_memory(&encoded(Some(20))).expect("decodes");
+        assert_eq!(lossy.dimensions(), (64, 64));
+        assert!(encoded(Some(20)).len() < encoded(None).len());
     }
-}
 
-fn quantize_key(pixel: Rgba<u8>) -> u32 {
-    let r = (pixel[0] >> 3) as u32;
-    let g = (pixel[1] >> 3) as u32;
-    let b = (pixel[2] >> 3) as u32;
-    let a = (pixel[3] >> 3) as u32;
-    (r << 15) | (g << 10) | (b << 5) | a
+    #[test]
+    fn finds_existing_outputs_including_variants() {
+        let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let args = Args::parse_from(["padify", "in.png"]);
+        assert_eq!(existing_output(&manifest, &args), Some(manifest.clone()));
+        assert_eq!(
+            existing_output(Path::new("definitely/missing.png"), &args),
+            None
+        );
+
+        let variants = Args::parse_from(["padify", "in.png", "--variants"]);
+        assert_eq!(existing_output(&manifest, &variants), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writing_over_a_dedupe_link_leaves_its_target_alone() {
+        let dir = std::env::temp_dir().join(format!("padify-relink-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("creates dir");
+        let (original, duplicate) = (dir.join("a_pad.png"), dir.join("b_pad.png"));
+        std::fs::write(&original, b"a").expect("writes");
+        link_duplicate(&original, &duplicate).expect("links");
+        remove_stale_link(&duplicate).expect("unlinks");
+        std::fs::write(&duplicate, b"b").expect("writes");
+        let contents = (std::fs::read(&original), std::fs::read(&duplicate));
+        std::fs::remove_dir_all(&dir).expect("cleans up");
+        assert_eq!(contents.0.expect("reads"), b"a");
+        assert_eq!(contents.1.expect("reads"), b"b");
+    }
+
+    #[test]
+    fn stats_report_content_ratio_and_confidence() {
+        let crop = CropResult::no_crop(RgbaImage::new(50, 40), "disabled").report;
+        let stats = RunStats {
+            input: Path::new("shots/a \"b\".png"),
+            output: Path::new("shots/a_pad.png"),
+            content: (50, 40),
+            canvas: (100, 80),
+            background: Rgba([11, 15, 20, 255]),
+            detected: Some(BackgroundEstimate {
+                color: Rgba([11, 15, 20, 255]),
+                confidence: 0.5,
+                source: "overall",
+                transparent_ratio: 0.25,
+            }),
+            crop: &crop,
+        };
+        let json = stats.to_json();
+        assert!(json.contains(r#""input": "shots/a \"b\".png""#));
+        assert!(json.contains(r#""content_ratio": 0.2500"#));
+        assert!(json.contains(r##""background": "#0b0f14ff""##));
+        assert!(json.contains(r#""background_confidence": 0.5000"#));
+        assert!(json.contains(r#""background_source": "overall""#));
+        assert!(json.contains(r#""border_transparent_ratio": 0.2500"#));
+        assert!(json.contains(r#""crop_reason": "disabled""#));
+        assert!(json.contains(r#""crop_column_reason": "not_checked""#));
+        assert!(json.contains(r#""cropped_width": 50"#));
+
+        assert_eq!(
+            background_summary(stats.input, stats.background, stats.detected),
+            "padify: shots/a \"b\".png: background #0b0f14ff \
+             (overall, confidence 0.50, transparent border 0.25)"
+        );
+
+        let given = RunStats {
+            detected: None,
+            ..stats
+        };
+        assert!(given.to_json().contains(r#""background_confidence": null"#));
+        assert!(given.to_json().contains(r#""background_source": "given""#));
+    }
+
+    #[test]
+    fn bbox_prints_text_or_json() {
+        let input = Path::new("shots/a.png");
+        assert_eq!(
+            bbox_line(input, (12, 40, 300, 200), false),
+            "shots/a.png: x 12, y 40, 300x200"
+        );
+        assert_eq!(
+            bbox_line(input, (12, 40, 300, 200), true),
+            r#"{"input": "shots/a.png", "x": 12, "y": 40, "width": 300, "height": 200}"#
+        );
+    }
+
+    #[test]
+    fn two_paths_mean_input_and_output_unless_output_exists() {
+        let outputs = |paths: &[PathBuf], output: Option<&Path>| -> Vec<_> {
+            resolve_jobs(paths, output, &[], None, None)
+                .expect("jobs")
+                .into_iter()
+                .map(|job| (job.input, job.output))
+                .collect()
+        };
+        let input = PathBuf::from("in.png");
+        let missing = PathBuf::from("definitely/missing/out.png");
+        assert_eq!(
+            outputs(&[input.clone(), missing.clone()], None),
+            [(input.clone(), Some(missing.clone()))]
+        );
+
+        let existing = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        assert_eq!(
+            outputs(&[input.clone(), existing.clone()], None),
+            [(input.clone(), None), (existing.clone(), None)]
+        );
+        assert_eq!(
+            outputs(std::slice::from_ref(&input), Some(&existing)),
+            [(input.clone(), Some(existing))]
+        );
+        assert!(
+            resolve_jobs(
+                &[input, missing],
+                Some(Path::new("out.png")),
+                &[],
+                None,
+                None
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn manifest_skips_comments_and_resolves_against_its_directory() {
+        let dir = std::env::temp_dir().join(format!("padify-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("creates dir");
+        let list = dir.join("inputs.txt");
+        std::fs::write(
+            &list,
+            "# screenshots for the docs\nshots/a.png\n\n  b.jpg  \n/abs/c.png\n",
+        )
+        .expect("writes manifest");
+        let paths = read_manifest(&list).expect("reads manifest");
+        assert_eq!(
+            paths,
+            [
+                dir.join("shots/a.png"),
+                dir.join("b.jpg"),
+                PathBuf::from("/abs/c.png")
+            ]
+        );
+        std::fs::remove_dir_all(&dir).expect("removes dir");
+        assert!(read_manifest(&list).is_err());
+
+        // Two listed files are two inputs, never an input and its output
+        let jobs = batch_jobs(&paths[..2], &[], None, None);
+        assert!(jobs.iter().all(|job| job.output.is_none()));
+    }
+
+    #[test]
+    fn matches_extensions_case_insensitively() {
+        let extensions = vec!["png".to_string(), ".JPG".to_string()];
+        assert!(has_extension(Path::new("shots/a.PNG"), &extensions));
+        assert!(has_extension(Path::new("shots/b.jpg"), &extensions));
+        assert!(!has_extension(Path::new("shots/c.webp"), &extensions));
+        assert!(!has_extension(Path::new("shots/README"), &extensions));
+        assert!(is_padded_output(Path::new("shots/a_pad.png")));
+    }
 }
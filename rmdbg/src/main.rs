@@ -1,101 +1,1153 @@
 use std::fs::{self, File};
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::path::Path;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
 use clap::{App, Arg};
 use regex::Regex;
 use walkdir::WalkDir;
 
-fn process_file(path: &Path) -> std::io::Result<()> {
-    // Retrieve the original file's metadata and permissions
-    let metadata = fs::metadata(&path)?;
-    let permissions = metadata.permissions();
+/// Rules reported for detected debug statements, as `(id, description)`.
+const RULES: [(&str, &str); 5] = [
+    (
+        "inline-import-set-trace",
+        "Single-line 'import pdb; pdb.set_trace()' breakpoint",
+    ),
+    ("set-trace", "Standalone 'pdb.set_trace()' call"),
+    (
+        "chained-set-trace",
+        "'pdb.set_trace()' chained after other statements with ';'",
+    ),
+    (
+        "import-set-trace",
+        "'import pdb' followed by a matching 'pdb.set_trace()' call",
+    ),
+    (
+        "filter-candidate",
+        "Line mentioning pdb, ipdb or breakpoint that --filter-cmd chose to remove",
+    ),
+];
 
-    let file = File::open(&path)?;
-    let reader = BufReader::new(file);
+/// A debug statement spanning lines `start..end` (0-based, end exclusive).
+struct Removal {
+    start: usize,
+    end: usize,
+    rule: &'static str,
+    /// Line left in place of the removed ones, when real code shares the line with the call
+    replacement: Option<String>,
+}
 
-    let temp_path = path.with_extension("tmp");
-    let temp_file = File::create(&temp_path)?;
-    let mut writer = BufWriter::new(temp_file);
+struct Patterns {
+    import: Regex,
+    set_trace: Regex,
+    single_line: Regex,
+}
 
-    // Set the permissions of the temp file to match the original
-    fs::set_permissions(&temp_path, permissions.clone())?;
+impl Patterns {
+    fn new() -> Self {
+        // Regular expressions for pattern matching
+        Patterns {
+            // Matches 'import pdb' or 'import ipdb' with optional whitespace and captures indentation and module name
+            import: Regex::new(r"^(\s*)import\s+(pdb|ipdb)\s*$").unwrap(),
 
-    // Regular expressions for pattern matching
-    // Matches 'import pdb' or 'import ipdb' with optional whitespace and captures indentation and module name
-    let re_import = Regex::new(r"^(\s*)import\s+(pdb|ipdb)\s*$").unwrap();
+            // Matches lines containing 'pdb.set_trace()' or 'ipdb.set_trace()' with optional whitespace
+            set_trace: Regex::new(r"^\s*(pdb|ipdb)\.set_trace\(\)\s*$").unwrap(),
 
-    // Matches lines containing 'pdb.set_trace()' or 'ipdb.set_trace()' with optional whitespace
-    let re_set_trace = Regex::new(r"^\s*(pdb|ipdb)\.set_trace\(\)\s*$").unwrap();
+            // Matches single-line 'import pdb; pdb.set_trace()' or 'import ipdb; ipdb.set_trace()'
+            single_line: Regex::new(r"^\s*import\s+(pdb|ipdb);\s*(pdb|ipdb)\.set_trace\(\)\s*$")
+                .unwrap(),
+        }
+    }
+}
 
-    // Matches single-line 'import pdb; pdb.set_trace()' or 'import ipdb; ipdb.set_trace()'
-    let re_single_line =
-        Regex::new(r"^\s*import\s+(pdb|ipdb);\s*(pdb|ipdb)\.set_trace\(\)\s*$").unwrap();
+/// Iterates over the debug statements in `lines` in order, without scanning past the one returned.
+struct DebugStatements<'a> {
+    lines: &'a [String],
+    patterns: Patterns,
+    i: usize,
+}
 
-    let mut buffer = Vec::new();
+impl Iterator for DebugStatements<'_> {
+    type Item = Removal;
 
-    let lines: Vec<String> = reader.lines().collect::<Result<_, _>>()?;
-    let mut i = 0;
+    fn next(&mut self) -> Option<Removal> {
+        let lines = self.lines;
+        let patterns = &self.patterns;
+
+        while self.i < lines.len() {
+            let i = self.i;
+            let line = &lines[i];
+
+            // Check for the single-line pattern
+            if patterns.single_line.is_match(line) {
+                self.i += 1;
+                return Some(Removal {
+                    start: i,
+                    end: i + 1,
+                    rule: "inline-import-set-trace",
+                    replacement: None,
+                });
+            }
+
+            // Check for standalone 'pdb.set_trace()' or 'ipdb.set_trace()' line
+            if patterns.set_trace.is_match(line) {
+                self.i += 1;
+                return Some(Removal {
+                    start: i,
+                    end: i + 1,
+                    rule: "set-trace",
+                    replacement: None,
+                });
+            }
+
+            // Check for 'pdb.set_trace()' chained after other statements on the same line
+            if let Some(kept) = strip_chained_set_trace(line, &patterns.set_trace) {
+                self.i += 1;
+                return Some(Removal {
+                    start: i,
+                    end: i + 1,
+                    rule: "chained-set-trace",
+                    replacement: Some(kept),
+                });
+            }
+
+            // Check for the multiline pattern start
+            if let Some(caps) = patterns.import.captures(line) {
+                let indent = caps.get(1).unwrap().as_str();
+                let module_name = caps.get(2).unwrap().as_str();
+                self.i += 1;
+
+                // Skip over any whitespace-only lines
+                while self.i < lines.len() && lines[self.i].trim().is_empty() {
+                    self.i += 1;
+                }
+
+                // Check for '<module_name>.set_trace()' with the same indentation
+                if self.i < lines.len() {
+                    let next_line = &lines[self.i];
+                    let expected_set_trace = format!("{}{}.set_trace()", indent, module_name);
+
+                    if next_line.trim() == expected_set_trace.trim() {
+                        // Remove the import, the blank lines and the current line
+                        self.i += 1;
+                        return Some(Removal {
+                            start: i,
+                            end: self.i,
+                            rule: "import-set-trace",
+                            replacement: None,
+                        });
+                    }
+                }
+
+                // Pattern did not match; keep the import and the blank lines
+            } else {
+                self.i += 1;
+            }
+        }
+
+        None
+    }
+}
+
+/// Byte offsets of the top-level ';' separators in a line of Python, or `None` when the
+/// line has a comment or leaves a string or bracket open.
+fn top_level_semicolons(line: &str) -> Option<Vec<usize>> {
+    let mut positions = Vec::new();
+    let mut depth = 0i32;
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '\'' | '"' => quote = Some(c),
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '#' => return None,
+            ';' if depth == 0 => positions.push(idx),
+            _ => {}
+        }
+    }
+    if quote.is_none() && depth == 0 {
+        Some(positions)
+    } else {
+        None
+    }
+}
+
+/// For a line like `x = compute(); pdb.set_trace()`, returns the code before the call.
+fn strip_chained_set_trace(line: &str, set_trace: &Regex) -> Option<String> {
+    let semicolons = top_level_semicolons(line)?;
+    let last = *semicolons.last()?;
+    if !set_trace.is_match(&line[last + 1..]) {
+        return None;
+    }
+    let kept = line[..last].trim_end();
+    if kept.trim().is_empty() {
+        return None;
+    }
+    Some(kept.to_string())
+}
+
+fn debug_statements(lines: &[String]) -> DebugStatements<'_> {
+    DebugStatements {
+        lines,
+        patterns: Patterns::new(),
+        i: 0,
+    }
+}
 
+fn find_removals(lines: &[String]) -> Vec<Removal> {
+    debug_statements(lines).collect()
+}
+
+/// Adds every other line that mentions a debugger to `removals`, for `--filter-cmd` to judge.
+fn with_filter_candidates(lines: &[String], removals: Vec<Removal>) -> Vec<Removal> {
+    let mention = Regex::new(r"\b(pdb|ipdb|breakpoint|set_trace)\b").unwrap();
+    let mut merged = Vec::with_capacity(removals.len());
+    let mut removals = removals.into_iter().peekable();
+    let mut i = 0;
     while i < lines.len() {
-        let line = &lines[i];
+        if let Some(removal) = removals.next_if(|r| r.start <= i) {
+            i = removal.end;
+            merged.push(removal);
+            continue;
+        }
+        if mention.is_match(&lines[i]) {
+            merged.push(Removal {
+                start: i,
+                end: i + 1,
+                rule: "filter-candidate",
+                replacement: None,
+            });
+        }
+        i += 1;
+    }
+    merged
+}
+
+/// Which of the detected statements to act on, narrowed by `--added-since` and `--filter-cmd`.
+#[derive(Default)]
+struct Selection {
+    /// Git revision; only statements on lines added since it are selected
+    added_since: Option<String>,
+    /// Shell command that accepts (exit 0) or rejects (exit 1) each candidate
+    filter_cmd: Option<String>,
+}
 
-        // Check for the single-line pattern
-        if re_single_line.is_match(line) {
+impl Selection {
+    /// The first `limit` selected statements in `lines`, read from `path`.
+    fn removals(&self, path: &Path, lines: &[String], limit: usize) -> io::Result<Vec<Removal>> {
+        let filtered = self.filter_cmd.is_some();
+        let candidates = code_regions(path, lines).into_iter().flat_map(|region| {
+            let code = &lines[region.clone()];
+            let found: Box<dyn Iterator<Item = Removal>> = if filtered {
+                Box::new(with_filter_candidates(code, find_removals(code)).into_iter())
+            } else {
+                // Stays lazy so that `limit` cuts the scan short
+                Box::new(debug_statements(code))
+            };
+            found.map(move |removal| Removal {
+                start: removal.start + region.start,
+                end: removal.end + region.start,
+                ..removal
+            })
+        });
+        let added = match &self.added_since {
+            Some(rev) => Some(added_lines(path, rev, lines.len())?),
+            None => None,
+        };
+        let mut selected = Vec::new();
+        for removal in candidates {
+            if selected.len() >= limit {
+                break;
+            }
+            if added
+                .as_ref()
+                .is_some_and(|added| !is_added(&removal, lines, added))
+            {
+                continue;
+            }
+            if let Some(command) = &self.filter_cmd {
+                if !filter_accepts(command, path, lines, &removal)? {
+                    continue;
+                }
+            }
+            selected.push(removal);
+        }
+        Ok(selected)
+    }
+}
+
+/// Documentation formats whose Python code blocks are cleaned, leaving the prose alone.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum DocFormat {
+    Markdown,
+    ReStructuredText,
+}
+
+fn doc_format(path: &Path) -> Option<DocFormat> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("md" | "markdown") => Some(DocFormat::Markdown),
+        Some("rst") => Some(DocFormat::ReStructuredText),
+        _ => None,
+    }
+}
+
+/// The line ranges of `path` that hold Python code: the whole file, or for documentation
+/// the bodies of the code blocks tagged as Python (fence and directive lines excluded).
+fn code_regions(path: &Path, lines: &[String]) -> Vec<Range<usize>> {
+    match doc_format(path) {
+        None => std::iter::once(0..lines.len()).collect(),
+        Some(DocFormat::Markdown) => markdown_python_blocks(lines),
+        Some(DocFormat::ReStructuredText) => rst_python_blocks(lines),
+    }
+}
+
+fn is_python_tag(tag: &str) -> bool {
+    matches!(
+        tag.to_ascii_lowercase().as_str(),
+        "python" | "py" | "python3"
+    )
+}
+
+/// Bodies of ```` ```python ```` / `~~~ py` fenced blocks. A block ends at a fence of the
+/// same character that is at least as long, or at the end of the file.
+fn markdown_python_blocks(lines: &[String]) -> Vec<Range<usize>> {
+    let fence = Regex::new(r"^\s*(`{3,}|~{3,})\s*([^\s`{]*)").unwrap();
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let Some(caps) = fence.captures(&lines[i]) else {
             i += 1;
             continue;
+        };
+        let marker = caps[1].to_string();
+        let python = is_python_tag(&caps[2]);
+        let start = i + 1;
+        let mut end = start;
+        while end < lines.len() {
+            let line = lines[end].trim();
+            let closes = line.starts_with(&marker)
+                && line
+                    .chars()
+                    .all(|c| c == marker.chars().next().unwrap_or('`'));
+            if closes {
+                break;
+            }
+            end += 1;
+        }
+        if python {
+            blocks.push(start..end);
         }
+        i = end + 1;
+    }
+    blocks
+}
 
-        // Check for standalone 'pdb.set_trace()' or 'ipdb.set_trace()' line
-        if re_set_trace.is_match(line) {
+/// Bodies of `.. code-block:: python` (or `code`/`sourcecode`) directives, after any
+/// `:option:` lines, up to the first non-blank line that isn't indented past the directive.
+fn rst_python_blocks(lines: &[String]) -> Vec<Range<usize>> {
+    let directive =
+        Regex::new(r"^(\s*)\.\.\s+(?:code-block|code|sourcecode)::\s*(\S*)\s*$").unwrap();
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let Some(caps) = directive.captures(&lines[i]) else {
             i += 1;
             continue;
+        };
+        let indent = caps[1].len();
+        let python = is_python_tag(&caps[2]);
+        let inside = |line: &str| line.trim().is_empty() || indent_of(line).len() > indent;
+        let mut start = i + 1;
+        while start < lines.len()
+            && lines[start].trim().starts_with(':')
+            && indent_of(&lines[start]).len() > indent
+        {
+            start += 1;
+        }
+        let mut end = start;
+        while end < lines.len() && inside(&lines[end]) {
+            end += 1;
+        }
+        if python {
+            blocks.push(start..end);
         }
+        i = end.max(i + 1);
+    }
+    blocks
+}
 
-        // Check for the multiline pattern start
-        if let Some(caps) = re_import.captures(line) {
-            buffer.push(line.clone());
-            let indent = caps.get(1).unwrap().as_str().to_string();
-            let module_name = caps.get(2).unwrap().as_str().to_string();
-            i += 1;
+/// Lines of context around a candidate sent to `--filter-cmd`
+const FILTER_CONTEXT: usize = 3;
+
+/// Asks `command` whether to remove `removal`: exit status 0 removes it, 1 keeps it.
+///
+/// The command runs through the shell with the candidate on stdin in `grep -n` style
+/// (`LINE:text` for candidate lines, `LINE-text` for context) and RMDBG_PATH,
+/// RMDBG_START, RMDBG_END (1-based, inclusive) and RMDBG_RULE in its environment.
+fn filter_accepts(
+    command: &str,
+    path: &Path,
+    lines: &[String],
+    removal: &Removal,
+) -> io::Result<bool> {
+    let from = removal.start.saturating_sub(FILTER_CONTEXT);
+    let to = (removal.end + FILTER_CONTEXT).min(lines.len());
+    let mut input = String::new();
+    for (i, line) in lines.iter().enumerate().take(to).skip(from) {
+        let separator = if (removal.start..removal.end).contains(&i) {
+            ':'
+        } else {
+            '-'
+        };
+        input.push_str(&format!("{}{}{}\n", i + 1, separator, line));
+    }
+
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let mut child = shell
+        .arg(command)
+        .env("RMDBG_PATH", path)
+        .env("RMDBG_START", (removal.start + 1).to_string())
+        .env("RMDBG_END", removal.end.to_string())
+        .env("RMDBG_RULE", removal.rule)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A filter that decides without reading its input closes the pipe early
+        match stdin.write_all(input.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+            _ => {}
+        }
+    }
+    let status = child.wait()?;
+    match status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => Err(io::Error::other(format!(
+            "--filter-cmd failed ({}) on line {}, expected exit status 0 (remove) or 1 (keep)",
+            status,
+            removal.start + 1
+        ))),
+    }
+}
+
+fn read_lines(path: &Path) -> std::io::Result<Vec<String>> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    reader.lines().collect()
+}
+
+/// Settings that affect how a file is rewritten.
+struct Options {
+    annotate: bool,
+    /// Python interpreter used to check that cleaned `.py` files still parse
+    verify_with: Option<String>,
+    selection: Selection,
+}
+
+/// The code of a Python line without its trailing comment, ignoring `#` inside strings.
+fn code_part(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '\'' | '"' => quote = Some(c),
+            '#' => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn indent_of(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+const ANNOTATION_PREFIX: &str = "# rmdbg: removed ";
+
+/// Inserts `annotation` after any shebang/encoding lines, replacing an earlier rmdbg annotation.
+fn annotate<'a>(lines: &mut Vec<&'a str>, annotation: &'a str) {
+    let re_coding = Regex::new(r"^[ \t\f]*#.*?coding[:=][ \t]*[-_.a-zA-Z0-9]+").unwrap();
+    let mut position = 0;
+    if lines.first().is_some_and(|line| line.starts_with("#!")) {
+        position = 1;
+    }
+    // PEP 263 only honors an encoding declaration on the first two lines
+    if position < 2
+        && lines
+            .get(position)
+            .is_some_and(|line| re_coding.is_match(line))
+    {
+        position += 1;
+    }
+    if lines
+        .get(position)
+        .is_some_and(|line| line.starts_with(ANNOTATION_PREFIX))
+    {
+        lines.remove(position);
+    }
+    lines.insert(position, annotation);
+}
+
+/// Formats today's UTC date as YYYY-MM-DD.
+fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// An `import pdb` line waiting to see whether its `set_trace()` call follows.
+struct PendingImport {
+    line: String,
+    expected: String,
+    /// Whitespace-only lines seen since the import
+    blanks: Vec<String>,
+}
+
+/// A removal that empties a block unless a statement indented past the header follows.
+struct PendingGuard {
+    /// Index of the removal among those the cleaner has seen
+    removal: usize,
+    header_indent: usize,
+    pass: String,
+    /// Comments and blank lines seen since the removal
+    held: Vec<String>,
+}
 
-            // Buffer any whitespace-only lines
-            while i < lines.len() && lines[i].trim().is_empty() {
-                buffer.push(lines[i].clone());
-                i += 1;
+/// Cleans Python source line by line, writing kept lines straight to `out`.
+///
+/// `line` finds the debug statements itself, the way `find_removals` does; `clean_lines`
+/// instead feeds it removals picked up front through `keep` and `remove`. Either way a
+/// removal that would leave a block (`def`, `class`, `if`, ...) without statements leaves
+/// a `pass` at the removed line's indentation: when the last kept statement before it is a
+/// header ending in `:` and the next statement is dedented to the header's level (or the
+/// input ends). Lines after decorators are just removed, since a decorator followed by
+/// `def` or `class` is valid again once the stray call is gone.
+///
+/// Lines are only held while an `import pdb` waits for its `set_trace()` or a removal
+/// waits for the statement that decides whether it needs a `pass`.
+struct StreamCleaner<W: Write> {
+    out: W,
+    patterns: Patterns,
+    removed: usize,
+    /// The `pass` lines written, with the index of the removal each one stands in for
+    passes: Vec<(usize, String)>,
+    /// Indentation of the last kept statement, when it is a header ending in `:`
+    header_indent: Option<usize>,
+    import: Option<PendingImport>,
+    guard: Option<PendingGuard>,
+}
+
+impl<W: Write> StreamCleaner<W> {
+    fn new(out: W) -> Self {
+        StreamCleaner {
+            out,
+            patterns: Patterns::new(),
+            removed: 0,
+            passes: Vec::new(),
+            header_indent: None,
+            import: None,
+            guard: None,
+        }
+    }
+
+    fn line(&mut self, line: &str) -> io::Result<()> {
+        if let Some(mut import) = self.import.take() {
+            if line.trim().is_empty() {
+                import.blanks.push(line.to_string());
+                self.import = Some(import);
+                return Ok(());
+            }
+            if line.trim() == import.expected.trim() {
+                return self.remove(None, line);
             }
+            // Pattern did not match; keep the import and the blank lines
+            self.keep(&import.line)?;
+            for blank in &import.blanks {
+                self.keep(blank)?;
+            }
+        }
 
-            // Check for '<module_name>.set_trace()' with the same indentation
-            if i < lines.len() {
-                let next_line = &lines[i];
-                let expected_set_trace = format!("{}{}.set_trace()", indent, module_name);
+        let patterns = &self.patterns;
+        if patterns.single_line.is_match(line) || patterns.set_trace.is_match(line) {
+            return self.remove(None, line);
+        }
+        if let Some(kept) = strip_chained_set_trace(line, &patterns.set_trace) {
+            return self.remove(Some(&kept), line);
+        }
+        if let Some(caps) = patterns.import.captures(line) {
+            let expected = format!("{}{}.set_trace()", &caps[1], &caps[2]);
+            self.import = Some(PendingImport {
+                line: line.to_string(),
+                expected,
+                blanks: Vec::new(),
+            });
+            return Ok(());
+        }
+        self.keep(line)
+    }
 
-                if next_line.trim() == expected_set_trace.trim() {
-                    // Skip the buffered lines and the current line
-                    buffer.clear();
+    /// Drops a debug statement whose last line is `last`, leaving `replacement` if any.
+    fn remove(&mut self, replacement: Option<&str>, last: &str) -> io::Result<()> {
+        self.removed += 1;
+        if let Some(replacement) = replacement {
+            return self.keep(replacement);
+        }
+        // A removal after one already waiting is settled by the same statement, and the
+        // first one's `pass` (if any) stops it from emptying the block
+        if self.guard.is_some() {
+            return Ok(());
+        }
+        if let Some(header_indent) = self.header_indent {
+            self.guard = Some(PendingGuard {
+                removal: self.removed - 1,
+                header_indent,
+                pass: format!("{}pass", indent_of(last)),
+                held: Vec::new(),
+            });
+        }
+        Ok(())
+    }
+
+    fn keep(&mut self, line: &str) -> io::Result<()> {
+        let code = code_part(line);
+        let is_statement = !code.trim().is_empty();
+        if let Some(guard) = &mut self.guard {
+            if !is_statement {
+                guard.held.push(line.to_string());
+                return Ok(());
+            }
+            self.settle(Some(line))?;
+        }
+        if is_statement {
+            self.header_indent = code
+                .trim_end()
+                .ends_with(':')
+                .then(|| indent_of(line).len());
+        }
+        self.write(line)
+    }
+
+    /// Writes out the waiting removal's `pass` and held lines, now that `next` is known.
+    fn settle(&mut self, next: Option<&str>) -> io::Result<()> {
+        let Some(guard) = self.guard.take() else {
+            return Ok(());
+        };
+        if next.is_none_or(|next| indent_of(next).len() <= guard.header_indent) {
+            self.header_indent = None;
+            self.write(&guard.pass)?;
+            self.passes.push((guard.removal, guard.pass));
+        }
+        for line in &guard.held {
+            self.write(line)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        self.out.write_all(line.as_bytes())?;
+        self.out.write_all(b"\n")
+    }
+
+    /// Flushes whatever is still held.
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(import) = self.import.take() {
+            self.keep(&import.line)?;
+            for blank in &import.blanks {
+                self.keep(blank)?;
+            }
+        }
+        self.settle(None)?;
+        self.out.flush()
+    }
+}
+
+/// Streams `reader` through a `StreamCleaner` into `writer`, reusing one line buffer.
+fn clean_stream(mut reader: impl BufRead, writer: impl Write) -> io::Result<usize> {
+    let mut cleaner = StreamCleaner::new(writer);
+    let mut buffer = String::new();
+    loop {
+        buffer.clear();
+        if reader.read_line(&mut buffer)? == 0 {
+            break;
+        }
+        // Strip the terminator the way `BufRead::lines` does
+        let mut line = buffer.strip_suffix('\n').unwrap_or(&buffer);
+        if buffer.ends_with('\n') {
+            line = line.strip_suffix('\r').unwrap_or(line);
+        }
+        cleaner.line(line)?;
+    }
+    cleaner.finish()?;
+    Ok(cleaner.removed)
+}
+
+/// Returns the lines that remain once `removals` are dropped from `lines`, running each code
+/// region through its own `StreamCleaner` and keeping the lines outside them as they are.
+/// Removals that would empty a block get the `pass` left in their place as replacement.
+fn clean_lines(
+    lines: &[String],
+    regions: &[Range<usize>],
+    removals: &mut [Removal],
+) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut copied = 0;
+    let mut next = 0;
+    for region in regions {
+        for line in &lines[copied..region.start] {
+            out.extend_from_slice(line.as_bytes());
+            out.push(b'\n');
+        }
+        let first = next;
+        let mut cleaner = StreamCleaner::new(&mut out);
+        let mut i = region.start;
+        // Writing to a Vec can't fail
+        while i < region.end {
+            match removals.get(next).filter(|removal| removal.start == i) {
+                Some(removal) => {
+                    let last = &lines[removal.end - 1];
+                    cleaner
+                        .remove(removal.replacement.as_deref(), last)
+                        .unwrap();
+                    i = removal.end;
+                    next += 1;
+                }
+                None => {
+                    cleaner.keep(&lines[i]).unwrap();
                     i += 1;
-                    continue;
                 }
             }
+        }
+        cleaner.finish().unwrap();
+        for (removal, pass) in std::mem::take(&mut cleaner.passes) {
+            removals[first + removal].replacement = Some(pass);
+        }
+        copied = region.end;
+    }
+    for line in &lines[copied..] {
+        out.extend_from_slice(line.as_bytes());
+        out.push(b'\n');
+    }
+    // Every line came out of a String and ends in the '\n' pushed after it
+    let out = String::from_utf8(out).expect("lines are UTF-8");
+    out.split_terminator('\n').map(str::to_string).collect()
+}
+
+/// Whether `process_file` can stream `path` instead of reading all of its lines first.
+///
+/// Annotations go at the top of the file, `--added-since` and `--filter-cmd` look at
+/// whole-file line numbers, and documentation files only clean their code blocks.
+fn can_stream(path: &Path, options: &Options) -> bool {
+    !options.annotate
+        && options.selection.added_since.is_none()
+        && options.selection.filter_cmd.is_none()
+        && doc_format(path).is_none()
+}
+
+/// `process_file` for files `can_stream` accepts; leaves files without debug statements
+/// untouched.
+fn stream_file(path: &Path, options: &Options) -> std::io::Result<()> {
+    let metadata = fs::metadata(path)?;
+    let permissions = metadata.permissions();
+
+    let temp_path = path.with_extension("tmp");
+    let result = File::open(path).and_then(|file| {
+        let writer = BufWriter::new(File::create(&temp_path)?);
+        clean_stream(BufReader::new(file), writer)
+    });
+    let result = match result {
+        Ok(0) => fs::remove_file(&temp_path),
+        Ok(_) => match &options.verify_with {
+            Some(python) if has_extension(path, "py") => verify_syntax(python, &temp_path),
+            _ => Ok(()),
+        }
+        .and_then(|()| replace_file(&temp_path, path, permissions)),
+        Err(err) => Err(err),
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn process_file(path: &Path, options: &Options) -> std::io::Result<()> {
+    if can_stream(path, options) {
+        return stream_file(path, options);
+    }
+    let lines = read_lines(path)?;
+    let mut removals = options.selection.removals(path, &lines, usize::MAX)?;
+    if removals.is_empty() {
+        return Ok(());
+    }
+    let cleaned = clean_lines(&lines, &code_regions(path, &lines), &mut removals);
+
+    let mut output: Vec<&str> = cleaned.iter().map(String::as_str).collect();
+    let annotation;
+    // Markdown and reStructuredText have no comment syntax the annotation could use
+    if options.annotate && doc_format(path).is_none() {
+        annotation = format!(
+            "{}{} debug statement(s) on {}",
+            ANNOTATION_PREFIX,
+            removals.len(),
+            today()
+        );
+        annotate(&mut output, &annotation);
+    }
+
+    // Retrieve the original file's metadata and permissions
+    let metadata = fs::metadata(path)?;
+    let permissions = metadata.permissions();
+
+    let temp_path = path.with_extension("tmp");
+    let result = write_lines(&temp_path, &output)
+        .and_then(|()| match &options.verify_with {
+            Some(python) if has_extension(path, "py") => verify_syntax(python, &temp_path),
+            _ => Ok(()),
+        })
+        .and_then(|()| replace_file(&temp_path, path, permissions));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Cleans Python source read from stdin and writes the result to stdout, for use as an
+/// editor filter. With `emit_edits`, the removed line ranges are reported on stderr.
+fn filter_stdin(options: &Options, emit_edits: bool) -> std::io::Result<()> {
+    let lines: Vec<String> = io::stdin().lock().lines().collect::<io::Result<_>>()?;
+    let mut removals = options
+        .selection
+        .removals(Path::new("-"), &lines, usize::MAX)?;
+    let cleaned = clean_lines(&lines, &code_regions(Path::new("-"), &lines), &mut removals);
+
+    let mut output: Vec<&str> = cleaned.iter().map(String::as_str).collect();
+    let annotation;
+    if options.annotate && !removals.is_empty() {
+        annotation = format!(
+            "{}{} debug statement(s) on {}",
+            ANNOTATION_PREFIX,
+            removals.len(),
+            today()
+        );
+        annotate(&mut output, &annotation);
+    }
+
+    let mut writer = BufWriter::new(io::stdout().lock());
+    for line in &output {
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    if emit_edits {
+        eprintln!("{}", edits_report(&removals));
+    }
+    Ok(())
+}
+
+/// JSON list of `removals` as 1-based, inclusive line ranges of the original text, each with
+/// the line left in their place (`null` when the lines are simply dropped).
+fn edits_report(removals: &[Removal]) -> String {
+    let edits: Vec<String> = removals
+        .iter()
+        .map(|removal| {
+            format!(
+                "{{\"start\": {}, \"end\": {}, \"rule\": {}, \"replacement\": {}}}",
+                removal.start + 1,
+                removal.end,
+                json_string(removal.rule),
+                removal
+                    .replacement
+                    .as_deref()
+                    .map_or("null".to_string(), json_string)
+            )
+        })
+        .collect();
+    format!("[{}]", edits.join(", "))
+}
+
+/// Flags the lines of `path` (0-based) that are new relative to git revision `rev`,
+/// counting every line of a file git doesn't track as new.
+fn added_lines(path: &Path, rev: &str, line_count: usize) -> std::io::Result<Vec<bool>> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    let tracked = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["ls-files", "--error-unmatch", "--"])
+        .arg(name)
+        .output()?;
+    if !tracked.status.success() {
+        return Ok(vec![true; line_count]);
+    }
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args([
+            "diff",
+            "--no-color",
+            "--no-ext-diff",
+            "--unified=0",
+            rev,
+            "--",
+        ])
+        .arg(name)
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!(
+            "git diff against {} failed: {}",
+            rev,
+            stderr.trim()
+        )));
+    }
+    Ok(parse_added_lines(
+        &String::from_utf8_lossy(&output.stdout),
+        line_count,
+    ))
+}
 
-            // Pattern did not match; write buffered lines
-            for buf_line in &buffer {
-                writer.write_all(buf_line.as_bytes())?;
-                writer.write_all(b"\n")?;
+/// Reads the new-file ranges from the hunk headers of a `--unified=0` diff.
+fn parse_added_lines(diff: &str, line_count: usize) -> Vec<bool> {
+    let hunk = Regex::new(r"^@@ -\S+ \+(\d+)(?:,(\d+))? @@").unwrap();
+    let mut added = vec![false; line_count];
+    for caps in diff.lines().filter_map(|line| hunk.captures(line)) {
+        let start: usize = caps[1].parse().unwrap_or(0);
+        let count: usize = caps.get(2).map_or(1, |c| c.as_str().parse().unwrap_or(0));
+        // A zero-length hunk is a pure deletion and adds nothing
+        for i in (start.saturating_sub(1)..start.saturating_sub(1) + count)
+            .take_while(|&i| i < line_count)
+        {
+            added[i] = true;
+        }
+    }
+    added
+}
+
+/// Whether every non-blank line of `removal` was added, so pre-existing code is never touched.
+fn is_added(removal: &Removal, lines: &[String], added: &[bool]) -> bool {
+    (removal.start..removal.end).all(|i| added[i] || lines[i].trim().is_empty())
+}
+
+const PARSE_CHECK: &str = "import ast, sys; ast.parse(open(sys.argv[1], 'rb').read())";
+
+/// Fails unless `python` can parse the file at `path`.
+fn verify_syntax(python: &str, path: &Path) -> std::io::Result<()> {
+    let output = Command::new(python)
+        .args(["-c", PARSE_CHECK])
+        .arg(path)
+        .output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = stderr
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("unknown error");
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "cleaned file would not parse, left unchanged ({})",
+            reason.trim()
+        ),
+    ))
+}
+
+fn python_available(python: &str) -> bool {
+    Command::new(python)
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+fn write_lines(path: &Path, lines: &[&str]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for line in lines {
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// Moves `temp_path` over `path`, giving the result the original `permissions`.
+#[cfg(not(windows))]
+fn replace_file(
+    temp_path: &Path,
+    path: &Path,
+    permissions: fs::Permissions,
+) -> std::io::Result<()> {
+    // Set the permissions of the temp file to match the original
+    fs::set_permissions(temp_path, permissions)?;
+    fs::rename(temp_path, path)
+}
+
+/// Moves `temp_path` over `path`, giving the result the original `permissions`.
+///
+/// Windows refuses to replace a read-only file, so the flag is cleared on the
+/// original for the rename and restored on the replacement afterwards.
+#[cfg(windows)]
+fn replace_file(
+    temp_path: &Path,
+    path: &Path,
+    permissions: fs::Permissions,
+) -> std::io::Result<()> {
+    if permissions.readonly() {
+        let mut writable = permissions.clone();
+        #[allow(clippy::permissions_set_readonly_false)]
+        writable.set_readonly(false);
+        fs::set_permissions(path, writable)?;
+    }
+    if let Err(e) = fs::rename(temp_path, path) {
+        let _ = fs::set_permissions(path, permissions);
+        return Err(e);
+    }
+    fs::set_permissions(path, permissions)
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some(extension)
+}
+
+fn collect_files(targets: &[String], extension: &str) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for target in targets {
+        let target_path = Path::new(target);
+
+        if target_path.is_file() {
+            if has_extension(target_path, extension) {
+                files.push(target_path.to_path_buf());
+            }
+        } else if target_path.is_dir() {
+            for entry in WalkDir::new(target_path)
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|e| e.path().is_file() && has_extension(e.path(), extension))
+            {
+                files.push(entry.into_path());
             }
-            buffer.clear();
         } else {
-            // Write the line as it doesn't match any patterns
-            writer.write_all(line.as_bytes())?;
-            writer.write_all(b"\n")?;
-            i += 1;
+            eprintln!(
+                "The target path '{}' is neither a file nor a directory.",
+                target
+            );
         }
     }
+    files
+}
 
-    // Replace the original file with the temp file
-    fs::rename(&temp_path, &path)?;
+fn json_string(value: &str) -> String {
+    let mut output = String::from("\"");
+    for character in value.chars() {
+        match character {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            control if control.is_control() => {
+                output.push_str(&format!("\\u{:04x}", control as u32))
+            }
+            other => output.push(other),
+        }
+    }
+    output.push('"');
+    output
+}
 
-    Ok(())
+fn sarif_uri(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// Renders the findings of a scan as a SARIF 2.1.0 document.
+fn sarif_report(findings: &[(PathBuf, Vec<Removal>)]) -> String {
+    let rules: Vec<String> = RULES
+        .iter()
+        .map(|(id, description)| {
+            format!(
+                "{{\"id\":{},\"shortDescription\":{{\"text\":{}}}}}",
+                json_string(id),
+                json_string(description)
+            )
+        })
+        .collect();
+
+    let mut results = Vec::new();
+    for (path, removals) in findings {
+        for removal in removals {
+            results.push(format!(
+                concat!(
+                    "{{\"ruleId\":{},\"level\":\"warning\",",
+                    "\"message\":{{\"text\":\"Debugger statement left in source\"}},",
+                    "\"locations\":[{{\"physicalLocation\":{{",
+                    "\"artifactLocation\":{{\"uri\":{}}},",
+                    "\"region\":{{\"startLine\":{},\"endLine\":{}}}}}}}]}}"
+                ),
+                json_string(removal.rule),
+                json_string(&sarif_uri(path)),
+                removal.start + 1,
+                removal.end
+            ));
+        }
+    }
+
+    format!(
+        concat!(
+            "{{\"version\":\"2.1.0\",",
+            "\"$schema\":\"https://json.schemastore.org/sarif-2.1.0.json\",",
+            "\"runs\":[{{\"tool\":{{\"driver\":{{\"name\":\"rmdbg\",",
+            "\"informationUri\":\"https://github.com/osolmaz/tools\",",
+            "\"rules\":[{}]}}}},\"results\":[{}]}}]}}\n"
+        ),
+        rules.join(","),
+        results.join(",")
+    )
 }
 
 fn main() {
@@ -105,8 +1157,8 @@ fn main() {
         .about("Removes lines containing pdb or ipdb debugging statements from Python files.")
         .arg(
             Arg::with_name("TARGETS")
-                .help("Target files or directories")
-                .required(true)
+                .help("Target files or directories, or '-' to clean stdin to stdout")
+                .required_unless("stdin-paths")
                 .multiple(true)
                 .index(1),
         )
@@ -118,45 +1170,514 @@ fn main() {
                 .help("File extension to filter (default: py)")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("stdin-paths")
+                .long("stdin-paths")
+                .help("Also read target paths from stdin, one per line"),
+        )
+        .arg(
+            Arg::with_name("list-only")
+                .long("list-only")
+                .alias("first-only")
+                .conflicts_with("sarif")
+                .help("Print the first debug statement in each file as path:line without modifying files"),
+        )
+        .arg(
+            Arg::with_name("annotate")
+                .long("annotate")
+                .help("Add a '# rmdbg: removed N debug statement(s)' comment to the top of modified files"),
+        )
+        .arg(
+            Arg::with_name("verify-syntax")
+                .long("verify-syntax")
+                .help("Leave a .py file unchanged if the cleaned version would no longer parse"),
+        )
+        .arg(
+            Arg::with_name("python")
+                .long("python")
+                .value_name("PATH")
+                .help("Python interpreter used by --verify-syntax (default: python3)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("added-since")
+                .long("added-since")
+                .value_name("REV")
+                .help("Only touch debug statements on lines added since this git revision")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("filter-cmd")
+                .long("filter-cmd")
+                .value_name("COMMAND")
+                .help("Shell command that decides each removal: exit 0 removes, 1 keeps (see README)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("emit-edits")
+                .long("emit-edits")
+                .conflicts_with("annotate")
+                .help("With '-', print the removed line ranges as JSON to stderr"),
+        )
+        .arg(
+            Arg::with_name("sarif")
+                .long("sarif")
+                .value_name("FILE")
+                .help("Write detected statements as a SARIF report instead of modifying files")
+                .takes_value(true),
+        )
         .get_matches();
 
-    let targets: Vec<_> = matches.values_of("TARGETS").unwrap().collect();
-    let extension = matches.value_of("extension").unwrap_or("py");
+    let mut targets: Vec<String> = matches
+        .values_of("TARGETS")
+        .map(|values| values.map(str::to_string).collect())
+        .unwrap_or_default();
+    if matches.is_present("stdin-paths") {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) if !line.trim().is_empty() => targets.push(line.trim().to_string()),
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Error reading paths from stdin: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    let selection = Selection {
+        added_since: matches.value_of("added-since").map(str::to_string),
+        filter_cmd: matches.value_of("filter-cmd").map(str::to_string),
+    };
 
-    for target in targets {
-        let target_path = Path::new(target);
+    let from_stdin = targets.iter().any(|target| target == "-");
+    if from_stdin {
+        let unsupported = [
+            "stdin-paths",
+            "list-only",
+            "sarif",
+            "verify-syntax",
+            "added-since",
+        ];
+        if targets.len() > 1 {
+            eprintln!("'-' (stdin) can't be combined with other targets");
+            std::process::exit(1);
+        }
+        if let Some(flag) = unsupported.iter().find(|flag| matches.is_present(flag)) {
+            eprintln!("--{} can't be used when reading from stdin", flag);
+            std::process::exit(1);
+        }
+        let options = Options {
+            annotate: matches.is_present("annotate"),
+            verify_with: None,
+            selection,
+        };
+        if let Err(e) = filter_stdin(&options, matches.is_present("emit-edits")) {
+            eprintln!("Error processing stdin: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if matches.is_present("emit-edits") {
+        eprintln!("--emit-edits only applies when reading from stdin ('-')");
+        std::process::exit(1);
+    }
+    let extension = matches.value_of("extension").unwrap_or("py");
+    let files = collect_files(&targets, extension);
 
-        if target_path.is_file() {
-            if target_path
-                .extension()
-                .and_then(|ext| ext.to_str())
-                .map_or(false, |ext| ext == extension)
-            {
-                if let Err(e) = process_file(target_path) {
-                    eprintln!("Error processing file {}: {}", target, e);
-                }
+    if matches.is_present("list-only") {
+        for path in files {
+            let first = read_lines(&path)
+                .and_then(|lines| selection.removals(&path, &lines, 1))
+                .map(|removals| removals.into_iter().next());
+            match first {
+                Ok(Some(removal)) => println!("{}:{}", path.display(), removal.start + 1),
+                Ok(None) => {}
+                Err(e) => eprintln!("Error processing file {}: {}", path.display(), e),
             }
-        } else if target_path.is_dir() {
-            for entry in WalkDir::new(target_path)
-                .into_iter()
-                .filter_map(Result::ok)
-                .filter(|e| {
-                    e.path().is_file()
-                        && e.path()
-                            .extension()
-                            .and_then(|ext| ext.to_str())
-                            .map_or(false, |ext| ext == extension)
-                })
-            {
-                if let Err(e) = process_file(entry.path()) {
-                    eprintln!("Error processing file {}: {}", entry.path().display(), e);
+        }
+        return;
+    }
+
+    if let Some(sarif_path) = matches.value_of("sarif") {
+        let mut findings = Vec::new();
+        for path in files {
+            let found =
+                read_lines(&path).and_then(|lines| selection.removals(&path, &lines, usize::MAX));
+            match found {
+                Ok(removals) => {
+                    if !removals.is_empty() {
+                        findings.push((path, removals));
+                    }
                 }
+                Err(e) => eprintln!("Error processing file {}: {}", path.display(), e),
             }
+        }
+        if let Err(e) = fs::write(sarif_path, sarif_report(&findings)) {
+            eprintln!("Error writing SARIF report {}: {}", sarif_path, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut verify_with = None;
+    if matches.is_present("verify-syntax") {
+        let python = matches.value_of("python").unwrap_or("python3");
+        if python_available(python) {
+            verify_with = Some(python.to_string());
         } else {
             eprintln!(
-                "The target path '{}' is neither a file nor a directory.",
-                target
+                "Warning: Python interpreter '{}' not found, skipping --verify-syntax",
+                python
             );
         }
     }
+    let options = Options {
+        annotate: matches.is_present("annotate"),
+        verify_with,
+        selection,
+    };
+    for path in files {
+        if let Err(e) = process_file(&path, &options) {
+            eprintln!("Error processing file {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(source: &str) -> Vec<String> {
+        source.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn finds_all_debug_statement_forms() {
+        let source = lines("import pdb; pdb.set_trace()\nx = 1\nimport ipdb\n\nipdb.set_trace()\n  pdb.set_trace()\n");
+        let found: Vec<_> = find_removals(&source)
+            .iter()
+            .map(|r| (r.start, r.end, r.rule))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (0, 1, "inline-import-set-trace"),
+                (2, 5, "import-set-trace"),
+                (5, 6, "set-trace"),
+            ]
+        );
+    }
+
+    #[test]
+    fn strips_set_trace_chained_after_code() {
+        let source = lines("x = compute(); pdb.set_trace()\ny = 'a;b'; ipdb.set_trace()\nz = f(';'); pdb.set_trace()  # bp\n");
+        let mut removals = find_removals(&source);
+        assert_eq!(removals.len(), 2);
+        assert!(removals.iter().all(|r| r.rule == "chained-set-trace"));
+        assert_eq!(
+            clean_lines(
+                &source,
+                &code_regions(Path::new("-"), &source),
+                &mut removals
+            ),
+            [
+                "x = compute()",
+                "y = 'a;b'",
+                "z = f(';'); pdb.set_trace()  # bp"
+            ]
+        );
+    }
+
+    #[test]
+    fn added_since_only_touches_new_lines() {
+        let diff = "diff --git a/app.py b/app.py\n@@ -1,0 +2 @@\n+pdb.set_trace()\n@@ -4 +5,0 @@\n-gone\n@@ -6,2 +6,2 @@\n";
+        let added = parse_added_lines(diff, 8);
+        assert_eq!(added, [false, true, false, false, false, true, true, false]);
+
+        let source = lines("import pdb\npdb.set_trace()\nx = 1\npdb.set_trace()\n\nimport ipdb\nipdb.set_trace()\ny = 2\n");
+        let kept: Vec<_> = find_removals(&source)
+            .into_iter()
+            .filter(|removal| is_added(removal, &source, &added))
+            .map(|removal| (removal.start, removal.end))
+            .collect();
+        // The import on line 1 predates the base, so that pair is left alone
+        assert_eq!(kept, [(5, 7)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn filter_cmd_decides_each_candidate() {
+        let source = lines("import pdb; pdb.set_trace()\nlog(pdb)\nx = 1\nipdb.set_trace()\n");
+        let selection = Selection {
+            added_since: None,
+            filter_cmd: Some("grep -q '^[0-9]*:.*ipdb' && [ \"$RMDBG_RULE\" = set-trace ]".into()),
+        };
+        let found: Vec<_> = selection
+            .removals(Path::new("app.py"), &source, usize::MAX)
+            .unwrap()
+            .iter()
+            .map(|r| (r.start, r.rule))
+            .collect();
+        assert_eq!(found, [(3, "set-trace")]);
+
+        let candidates: Vec<_> = with_filter_candidates(&source, find_removals(&source))
+            .iter()
+            .map(|r| (r.start, r.rule))
+            .collect();
+        assert_eq!(
+            candidates,
+            [
+                (0, "inline-import-set-trace"),
+                (1, "filter-candidate"),
+                (3, "set-trace")
+            ]
+        );
+    }
+
+    fn cleaned(source: &str) -> Vec<String> {
+        let source = lines(source);
+        let mut removals = find_removals(&source);
+        clean_lines(
+            &source,
+            &code_regions(Path::new("-"), &source),
+            &mut removals,
+        )
+    }
+
+    #[test]
+    fn cleans_only_python_blocks_in_markdown() {
+        let source = lines(
+            "Call `pdb.set_trace()` to debug:\n\n```python\ndef f():\n    import pdb\n    pdb.set_trace()\n```\n\n```\npdb.set_trace()\n```\n\n````py\nx = 1\n```\npdb.set_trace()\n````\n",
+        );
+        let path = Path::new("guide.md");
+        let mut removals = Selection::default()
+            .removals(path, &source, usize::MAX)
+            .expect("no git or filter needed");
+        assert_eq!(
+            removals.iter().map(|r| r.start..r.end).collect::<Vec<_>>(),
+            [4..6, 15..16]
+        );
+        assert_eq!(
+            clean_lines(&source, &code_regions(path, &source), &mut removals),
+            [
+                "Call `pdb.set_trace()` to debug:",
+                "",
+                "```python",
+                "def f():",
+                "    pass",
+                "```",
+                "",
+                "```",
+                "pdb.set_trace()",
+                "```",
+                "",
+                "````py",
+                "x = 1",
+                "```",
+                "````",
+            ]
+        );
+    }
+
+    #[test]
+    fn cleans_only_python_directives_in_rst() {
+        let source = lines(
+            ".. code-block:: python\n   :linenos:\n\n   pdb.set_trace()\n   x = 1\n\npdb.set_trace()\n\n.. code:: bash\n\n   pdb.set_trace()\n",
+        );
+        let path = Path::new("guide.rst");
+        assert_eq!(
+            code_regions(path, &source),
+            vec![Range { start: 2, end: 6 }]
+        );
+        let removals = Selection::default()
+            .removals(path, &source, usize::MAX)
+            .expect("no git or filter needed");
+        assert_eq!(removals.iter().map(|r| r.start).collect::<Vec<_>>(), [3]);
+    }
+
+    #[test]
+    fn keeps_class_bodies_valid() {
+        let class_only = "class Config:\n    pdb.set_trace()\n\nx = 1\n";
+        assert_eq!(
+            cleaned(class_only),
+            ["class Config:", "    pass", "", "x = 1"]
+        );
+
+        let class_with_method =
+            "class Config:\n    import pdb\n    pdb.set_trace()\n    def f(self):\n        return 1\n";
+        assert_eq!(
+            cleaned(class_with_method),
+            ["class Config:", "    def f(self):", "        return 1"]
+        );
+    }
+
+    #[test]
+    fn drops_breakpoints_after_decorators_without_pass() {
+        let decorated = "class A:\n    @property\n    pdb.set_trace()\n    def name(self):\n        return 'a'\n";
+        assert_eq!(
+            cleaned(decorated),
+            [
+                "class A:",
+                "    @property",
+                "    def name(self):",
+                "        return 'a'"
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_nested_defs_valid() {
+        let nested = "def outer():\n    def inner():  # helper\n        # debug\n        ipdb.set_trace()\n        ipdb.set_trace()\n    return inner\n";
+        assert_eq!(
+            cleaned(nested),
+            [
+                "def outer():",
+                "    def inner():  # helper",
+                "        # debug",
+                "        pass",
+                "    return inner"
+            ]
+        );
+
+        let at_end = "if x:\n    import ipdb; ipdb.set_trace()\n";
+        assert_eq!(cleaned(at_end), ["if x:", "    pass"]);
+    }
+
+    #[test]
+    fn keeps_import_without_set_trace() {
+        assert!(find_removals(&lines("import pdb\n\nprint(pdb)\n")).is_empty());
+    }
+
+    fn streamed(source: &str) -> (String, usize) {
+        let mut out = Vec::new();
+        let removed = clean_stream(source.as_bytes(), &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), removed)
+    }
+
+    fn whole_file(source: &str) -> (String, usize) {
+        let source = lines(source);
+        let mut removals = find_removals(&source);
+        let kept: String = clean_lines(
+            &source,
+            &code_regions(Path::new("-"), &source),
+            &mut removals,
+        )
+        .into_iter()
+        .map(|line| format!("{line}\n"))
+        .collect();
+        (kept, removals.len())
+    }
+
+    /// Python exercising every rule, the `pass` guard and the import lookahead.
+    const MIXED: &str = "import os\n\ndef f():\n    import pdb\n\n    pdb.set_trace()\n\nclass A:\n    # note\n    pdb.set_trace()\n    ipdb.set_trace()\n\n    def g(self):\n        x = 1; pdb.set_trace()\n        import ipdb\n\n        return x\n\n@decorator\npdb.set_trace()\ndef h():\n    if x:\n        import pdb; pdb.set_trace()\n    elif y:\n        pdb.set_trace()\n        z = 2\n    import pdb\n    \n    ipdb.set_trace()\n";
+
+    #[test]
+    fn streaming_matches_whole_file_cleaning() {
+        let cases = [
+            MIXED,
+            "",
+            "x = 1",
+            "if x:\n    pdb.set_trace()",
+            "if x:\n    pdb.set_trace()\n    # trailing\n\n",
+            "if x:\n    import pdb\n\n",
+            "import pdb\nimport pdb\npdb.set_trace()\n",
+            "while True:\n    pdb.set_trace()\n    y = f(); pdb.set_trace()\n",
+            "def f():\r\n    pdb.set_trace()\r\nx = 1\r\n",
+        ];
+        for source in cases {
+            assert_eq!(streamed(source), whole_file(source), "{source:?}");
+        }
+    }
+
+    #[test]
+    #[ignore = "benchmark; run with --ignored --nocapture"]
+    fn bench_streaming_against_whole_file() {
+        use std::time::Instant;
+
+        let source = MIXED.repeat(20_000);
+        let start = Instant::now();
+        let whole = whole_file(&source);
+        let whole_time = start.elapsed();
+        let start = Instant::now();
+        let stream = streamed(&source);
+        let stream_time = start.elapsed();
+        assert_eq!(stream, whole);
+        eprintln!("whole file: {whole_time:?}, streaming: {stream_time:?}");
+    }
+
+    #[test]
+    fn annotation_goes_after_shebang_and_encoding() {
+        let mut output = vec![
+            "#!/usr/bin/env python",
+            "# -*- coding: utf-8 -*-",
+            "# rmdbg: removed 1 debug statement(s) on 2020-01-01",
+            "x = 1",
+        ];
+        annotate(
+            &mut output,
+            "# rmdbg: removed 2 debug statement(s) on 2024-05-06",
+        );
+        assert_eq!(
+            output,
+            [
+                "#!/usr/bin/env python",
+                "# -*- coding: utf-8 -*-",
+                "# rmdbg: removed 2 debug statement(s) on 2024-05-06",
+                "x = 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn edits_report_uses_original_line_numbers() {
+        let source = lines("import pdb\npdb.set_trace()\nx = 1\ny = 2; pdb.set_trace()\n");
+        let removals = find_removals(&source);
+        assert_eq!(
+            edits_report(&removals),
+            "[{\"start\": 1, \"end\": 2, \"rule\": \"import-set-trace\", \"replacement\": null}, \
+             {\"start\": 4, \"end\": 4, \"rule\": \"chained-set-trace\", \"replacement\": \"y = 2\"}]"
+        );
+        assert_eq!(edits_report(&[]), "[]");
+    }
+
+    #[test]
+    fn sarif_report_lists_findings() {
+        let findings = vec![(
+            PathBuf::from("src/app.py"),
+            find_removals(&lines("x = 1\npdb.set_trace()\n")),
+        )];
+        let report = sarif_report(&findings);
+        assert!(report.contains("\"ruleId\":\"set-trace\""));
+        assert!(report.contains("\"uri\":\"src/app.py\""));
+        assert!(report.contains("\"startLine\":2,\"endLine\":2"));
+    }
+
+    #[test]
+    fn rewrites_read_only_files_and_keeps_them_read_only() {
+        let dir = std::env::temp_dir().join(format!("rmdbg-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("readonly.py");
+        fs::write(&path, "x = 1\npdb.set_trace()\n").unwrap();
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+
+        let options = Options {
+            annotate: false,
+            verify_with: None,
+            selection: Selection::default(),
+        };
+        process_file(&path, &options).unwrap();
+
+        let metadata = fs::metadata(&path).unwrap();
+        assert!(metadata.permissions().readonly());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "x = 1\n");
+        assert!(!path.with_extension("tmp").exists());
+
+        let mut permissions = metadata.permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(&path, permissions).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
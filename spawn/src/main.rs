@@ -1,47 +1,133 @@
 use anyhow::{Context, Result, bail};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use log::{LevelFilter, debug, warn};
 use regex::Regex;
 use shell_words::split as shell_split;
+use std::collections::BTreeSet;
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::io::{IsTerminal, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
 
 #[derive(Parser, Debug)]
 #[command(
     name = "spawn",
     version,
-    about = "Spawn tmux sessions from markdown todos"
+    about = "Spawn tmux, zellij or screen sessions from markdown todos"
 )]
 struct Cli {
-    /// Path to markdown file containing todos
+    /// Path to markdown file containing todos (required for run)
     #[arg(short, long, value_name = "FILE")]
-    file: PathBuf,
+    file: Option<PathBuf>,
 
-    /// Tmux session name
-    #[arg(long, default_value = "spawn")]
+    /// Session name in the --backend multiplexer (falls back to $SPAWN_SESSION, then "spawn")
+    #[arg(long, env = "SPAWN_SESSION", default_value = "spawn")]
     session: String,
 
-    /// Prefix to add before each prompt
+    /// Prefix to add before each prompt ({index}, {title}, {section}, {file} and {doc_title}
+    /// are filled in; see README)
     #[arg(long)]
     prefix: Option<String>,
 
-    /// Suffix to add after each prompt
+    /// Suffix to add after each prompt (takes the same tokens as --prefix)
     #[arg(long)]
     suffix: Option<String>,
 
+    /// Prompt template file with {item} (the todo's text) and the tokens --prefix takes,
+    /// used instead of --prefix/--suffix
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["prefix", "suffix", "prefix_sep", "suffix_sep"]
+    )]
+    template: Option<PathBuf>,
+
+    /// Separator between prefix, item and suffix (supports \n and \t escapes)
+    #[arg(long, value_name = "TEXT", default_value = "\\n\\n")]
+    separator: String,
+
+    /// Separator between prefix and item (defaults to --separator)
+    #[arg(long, value_name = "TEXT")]
+    prefix_sep: Option<String>,
+
+    /// Separator between item and suffix (defaults to --separator)
+    #[arg(long, value_name = "TEXT")]
+    suffix_sep: Option<String>,
+
+    /// Terminal multiplexer to spawn windows in
+    #[arg(long, value_enum, default_value_t = Backend::Tmux)]
+    backend: Backend,
+
     /// Tmux binary to use
     #[arg(long, default_value = "tmux")]
     tmux_bin: String,
 
-    /// Replace existing tmux session if it already exists
+    /// Zellij binary to use (with --backend zellij)
+    #[arg(long, default_value = "zellij")]
+    zellij_bin: String,
+
+    /// GNU screen binary to use (with --backend screen)
+    #[arg(long, default_value = "screen")]
+    screen_bin: String,
+
+    /// Extra argument appended verbatim to tmux new-session/new-window (repeatable)
+    #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+    tmux_arg: Vec<String>,
+
+    /// Maximum length of --name-from-todo window names; longer titles are cut with a trailing "…"
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 20,
+        requires = "name_from_todo"
+    )]
+    name_width: usize,
+
+    /// Name each window after its number and todo, e.g. 3-fix-login-bug (cut to --name-width)
+    #[arg(long, conflicts_with = "layout")]
+    name_from_todo: bool,
+
+    /// After spawning, mark the launched todos as done ([x]) in --file
+    #[arg(long)]
+    check_off: bool,
+
+    /// Working directory for every spawned window; relative @cwd(PATH) tags resolve against it
+    #[arg(long, value_name = "PATH")]
+    cwd: Option<PathBuf>,
+
+    /// Named harness command for todos tagged @harness(NAME), e.g. "claude=claude -p {item}" (repeatable)
+    #[arg(long, value_name = "NAME=CMD", value_parser = parse_harness)]
+    harness: Vec<NamedHarness>,
+
+    /// Variable exported before the harness command in every window (repeatable; the value
+    /// may use the tokens --prefix takes)
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    env: Vec<(String, String)>,
+
+    /// Hide --env values in the confirmation and --dry-run output
+    #[arg(long)]
+    mask_env: bool,
+
+    /// Dotenv file whose variables are set in every spawned window
+    #[arg(long, value_name = "FILE")]
+    env_file: Option<PathBuf>,
+
+    /// Run every prompt in its own pane of a single window, arranged with this tmux layout
+    /// (more than 16 prompts fall back to one window each)
+    #[arg(long, value_enum, value_name = "LAYOUT")]
+    layout: Option<Layout>,
+
+    /// Replace the session if it already exists
     #[arg(long)]
     replace: bool,
 
-    /// Print prompts instead of launching tmux
+    /// Print prompts instead of launching anything
     #[arg(long)]
     dry_run: bool,
 
-    /// Attach to the tmux session after spawning
+    /// Attach to the session after spawning
     #[arg(long)]
     attach: bool,
 
@@ -49,86 +135,621 @@ struct Cli {
     #[arg(long)]
     yes: bool,
 
+    /// Fail instead of warning when prompts span several lines (see README)
+    #[arg(long)]
+    strict: bool,
+
+    /// Show each full prompt and ask whether to include it, one at a time
+    #[arg(long, conflicts_with = "yes")]
+    review: bool,
+
+    /// Pick the todos to spawn from a checklist (falls back to the confirmation prompt without a terminal)
+    #[arg(long, conflicts_with_all = ["yes", "review"])]
+    interactive: bool,
+
+    /// Comma-separated checkbox states to treat as actionable (" " = unchecked, "x" = done, ...)
+    #[arg(long, value_name = "CHARS", default_value = " ")]
+    states: String,
+
+    /// Only spawn todos under this markdown heading (up to the next heading of the same or higher level)
+    #[arg(long, value_name = "HEADING")]
+    section: Option<String>,
+
+    /// Drop todos whose first line matches this regex (repeatable)
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    skip: Vec<Regex>,
+
+    /// Keep only todos whose first line matches this regex (repeatable; any match keeps a todo)
+    #[arg(long = "match", value_name = "REGEX", value_parser = Regex::new)]
+    match_: Vec<Regex>,
+
+    /// Only spawn these todos, by 1-based position after filtering (e.g. "2,5,7-9")
+    #[arg(long, value_name = "LIST", value_parser = parse_only)]
+    only: Option<BTreeSet<usize>>,
+
+    /// Spawn at most N windows: the first N matching todos, skipping the rest
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max: Option<u32>,
+
+    /// Also match checked todos ([x] and [X]), e.g. to re-run everything
+    #[arg(long)]
+    include_done: bool,
+
+    /// Match only checked todos ([x] and [X]), for replaying finished work
+    #[arg(long, conflicts_with_all = ["states", "include_done"])]
+    only_done: bool,
+
+    /// Log verbosity (off, error, warn, info, debug, trace); overrides RUST_LOG
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<LevelFilter>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+/// A `--harness NAME=CMD` command that todos tagged `@harness(NAME)` run instead of the default.
+#[derive(Debug, Clone)]
+struct NamedHarness {
+    name: String,
+    cmd: Vec<String>,
+}
+
+fn parse_harness(raw: &str) -> Result<NamedHarness> {
+    let Some((name, cmd)) = raw.split_once('=') else {
+        bail!("expected NAME=CMD");
+    };
+    let name = name.trim();
+    if !harness_tag_name(name) {
+        bail!("invalid harness name '{name}': use letters, digits, '_' and '-'");
+    }
+    let cmd = normalize_harness_cmd(&[cmd.to_string()])?;
+    if !contains_item_token(&cmd) {
+        bail!("harness command must include {{item}}");
+    }
+    Ok(NamedHarness {
+        name: name.to_string(),
+        cmd,
+    })
+}
+
+fn harness_tag_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// The terminal multiplexers `--backend` can spawn into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Backend {
+    Tmux,
+    Zellij,
+    Screen,
+}
+
+impl Backend {
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Tmux => "tmux",
+            Backend::Zellij => "zellij",
+            Backend::Screen => "screen",
+        }
+    }
+
+    fn attach_hint(self, session: &str) -> String {
+        match self {
+            Backend::Tmux => format!("tmux attach -t {session}"),
+            Backend::Zellij => format!("zellij attach {session}"),
+            Backend::Screen => format!("screen -r {session}"),
+        }
+    }
+}
+
+/// Fails on options only the tmux backend implements, before anything is spawned.
+fn check_backend_options(cli: &Cli) -> Result<()> {
+    let backend = cli.backend.name();
+    if cli.backend == Backend::Tmux {
+        return Ok(());
+    }
+    if cli.layout.is_some() {
+        bail!("--layout needs the tmux backend; {backend} windows can't be split into panes");
+    }
+    if cli.env_file.is_some() {
+        bail!("--env-file needs the tmux backend; use --env with {backend}");
+    }
+    if !cli.tmux_arg.is_empty() {
+        bail!("--tmux-arg only applies to the tmux backend");
+    }
+    Ok(())
+}
+
+/// The tmux layouts `--layout` can arrange panes in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Layout {
+    Tiled,
+    EvenHorizontal,
+    EvenVertical,
+}
+
+impl Layout {
+    fn tmux_name(self) -> &'static str {
+        match self {
+            Layout::Tiled => "tiled",
+            Layout::EvenHorizontal => "even-horizontal",
+            Layout::EvenVertical => "even-vertical",
+        }
+    }
+}
+
+/// Most panes `--layout` puts in one window; beyond this they get too small to use (and
+/// tmux refuses to split further on small terminals).
+const MAX_PANES: usize = 16;
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Run a harness command template that includes {item}
     Run {
-        /// Harness command template (use {item} to insert the prompt)
+        /// Harness command template (use {item} to insert the prompt; the tokens --prefix
+        /// takes work too)
         #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
         harness_cmd: Vec<String>,
     },
+    /// Kill the windows created by the last run in the session
+    Undo,
+    /// Print how many todos in --file would be spawned, without touching the multiplexer
+    Count {
+        /// Print {"count": N} instead of a bare number
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    let content = fs::read_to_string(&cli.file)
-        .with_context(|| format!("failed to read {}", cli.file.display()))?;
+    init_logging(cli.log_level);
+    match &cli.command {
+        Commands::Run { harness_cmd } => run(&cli, harness_cmd),
+        Commands::Undo => undo_last_run(&cli),
+        Commands::Count { json } => count(&cli, *json),
+    }
+}
+
+fn count(cli: &Cli, json: bool) -> Result<()> {
+    let file = cli.file.as_ref().context("--file is required for count")?;
+    let content =
+        fs::read_to_string(file).with_context(|| format!("failed to read {}", file.display()))?;
+    let mut items = pick_todos(cli, filter_todos(cli, select_todos(cli, &content)?))?;
+    if let Some(max) = cli.max {
+        items.truncate(max as usize);
+    }
+    if json {
+        println!("{{\"count\": {}}}", items.len());
+    } else {
+        println!("{}", items.len());
+    }
+    Ok(())
+}
 
-    let items = extract_unchecked_todos(&content);
-    if items.is_empty() {
-        bail!("no unchecked todos found in {}", cli.file.display());
+/// Logs to stderr, filtered by `--log-level` when given, else by `RUST_LOG` (default: warn).
+fn init_logging(level: Option<LevelFilter>) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"));
+    if let Some(level) = level {
+        builder.filter_level(level);
     }
+    builder.format_timestamp(None).init();
+}
 
-    let prompts: Vec<String> = items
-        .into_iter()
-        .map(|item| build_prompt(&item, cli.prefix.as_deref(), cli.suffix.as_deref()))
+fn run(cli: &Cli, harness_cmd: &[String]) -> Result<()> {
+    check_backend_options(cli)?;
+    if cli.tmux_arg.iter().any(|arg| arg.is_empty()) {
+        bail!("--tmux-arg values must not be empty");
+    }
+    let file = cli.file.as_ref().context("--file is required for run")?;
+    let content =
+        fs::read_to_string(file).with_context(|| format!("failed to read {}", file.display()))?;
+
+    let todos = select_todos(cli, &content)?;
+    let extracted = todos.len();
+    let todos = filter_todos(cli, todos);
+    if todos.is_empty() {
+        bail!("no matching todos found in {}", file.display());
+    }
+    let filtered =
+        (!cli.skip.is_empty() || !cli.match_.is_empty()).then_some((todos.len(), extracted));
+    let mut todos = pick_todos(cli, todos)?;
+    let interactive =
+        cli.interactive && std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+    if cli.interactive && !interactive {
+        warn!("--interactive needs a terminal; falling back to the confirmation prompt");
+    }
+    // Under --interactive, --max caps the selection instead of the list
+    if let Some(max) = cli.max.map(|max| max as usize)
+        && !interactive
+        && todos.len() > max
+    {
+        println!(
+            "note: --max {max}: using the first {max} of {} todos; skipping the rest.",
+            todos.len()
+        );
+        todos.truncate(max);
+    }
+    let document = Document::new(file, &content);
+
+    let env = match &cli.env_file {
+        Some(path) => load_env_file(path)?,
+        None => Vec::new(),
+    };
+
+    let prompts: Vec<String> = match &cli.template {
+        Some(path) => {
+            let template = fs::read_to_string(path)
+                .with_context(|| format!("failed to read template {}", path.display()))?;
+            todos
+                .iter()
+                .enumerate()
+                .map(|(i, todo)| render_template(&template, &Tokens::new(i + 1, todo, &document)))
+                .collect()
+        }
+        None => {
+            let separators = Separators::from_cli(cli);
+            todos
+                .iter()
+                .enumerate()
+                .map(|(i, todo)| {
+                    let tokens = Tokens::new(i + 1, todo, &document);
+                    let prefix = cli.prefix.as_ref().map(|p| fill_tokens(p, None, &tokens));
+                    let suffix = cli.suffix.as_ref().map(|s| fill_tokens(s, None, &tokens));
+                    build_prompt(
+                        &todo.text,
+                        prefix.as_deref(),
+                        suffix.as_deref(),
+                        &separators,
+                    )
+                })
+                .collect()
+        }
+    };
+
+    let (prompts, todos): (Vec<String>, Vec<&Todo>) = if cli.review {
+        let stdin = std::io::stdin();
+        let jobs = prompts
+            .into_iter()
+            .zip(&todos)
+            .map(|(prompt, todo)| Job { prompt, todo })
+            .collect();
+        let selected = review_prompts(jobs, &mut stdin.lock(), &mut std::io::stdout())?;
+        if selected.is_empty() {
+            println!("no prompts selected; nothing to spawn.");
+            return Ok(());
+        }
+        selected
+            .into_iter()
+            .map(|job| (job.prompt, job.todo))
+            .unzip()
+    } else if interactive {
+        let titles: Vec<&str> = todos.iter().map(|todo| todo_title(&todo.text)).collect();
+        let picked = pick_interactively(&titles, cli.max.map(|max| max as usize))?;
+        if picked.is_empty() {
+            println!("no todos selected; nothing to spawn.");
+            return Ok(());
+        }
+        let mut prompts: Vec<Option<String>> = prompts.into_iter().map(Some).collect();
+        picked
+            .into_iter()
+            .map(|idx| (prompts[idx].take().expect("picked once"), &todos[idx]))
+            .unzip()
+    } else {
+        (prompts, todos.iter().collect())
+    };
+    check_multiline_todos(&todos, cli.strict)?;
+    let titles: Vec<String> = todos
+        .iter()
+        .map(|todo| todo_title(&todo.text).to_string())
         .collect();
+    let overrides = todos
+        .iter()
+        .map(|todo| todo_harness(cli, todo))
+        .collect::<Result<Vec<_>>>()?;
+    let cwds = todos
+        .iter()
+        .map(|todo| todo_cwd(cli, todo))
+        .collect::<Result<Vec<_>>>()?;
+
+    let layout = match cli.layout {
+        Some(_) if prompts.len() > MAX_PANES => {
+            warn!(
+                "{} prompts are too many panes for one window (at most {MAX_PANES}); \
+                 spawning one window per prompt instead",
+                prompts.len()
+            );
+            None
+        }
+        layout => layout,
+    };
 
     if cli.dry_run {
-        for (i, prompt) in prompts.iter().enumerate() {
-            println!("--- prompt {} ---\n{}\n", i + 1, prompt);
+        for (i, (prompt, todo)) in prompts.iter().zip(&todos).enumerate() {
+            match &todo.harness {
+                Some(name) => println!("--- prompt {} (harness: {name}) ---\n{}\n", i + 1, prompt),
+                None => println!("--- prompt {} ---\n{}\n", i + 1, prompt),
+            }
+        }
+        if let Some(path) = &cli.env_file {
+            let keys: Vec<&str> = env.iter().map(|(key, _)| key.as_str()).collect();
+            println!("env from {}: {}\n", path.display(), keys.join(", "));
+        }
+        if !cli.env.is_empty() {
+            println!("env: {}\n", describe_env_vars(cli));
+        }
+        if let Err(err) = print_window_plan(cli, multiplexer(cli).as_ref(), &titles, layout) {
+            eprintln!(
+                "warning: could not inspect {} targets: {err:#}",
+                cli.backend.name()
+            );
         }
         return Ok(());
     }
 
-    let harness_cmd = match &cli.command {
-        Commands::Run { harness_cmd } => harness_cmd,
-    };
     let harness_cmd = normalize_harness_cmd(harness_cmd)?;
+    let launches: Vec<Launch> = prompts
+        .iter()
+        .zip(&todos)
+        .zip(overrides)
+        .zip(cwds)
+        .enumerate()
+        .map(|(i, (((prompt, todo), harness), cwd))| {
+            let tokens = Tokens::new(i + 1, todo, &document);
+            Launch {
+                prompt,
+                harness_cmd: harness.unwrap_or(&harness_cmd),
+                cwd,
+                exports: env_exports(&cli.env, &tokens),
+                tokens,
+            }
+        })
+        .collect();
 
-    if !cli.yes {
-        if !confirm_spawn(&cli, &harness_cmd, &prompts)? {
-            println!("aborted.");
-            return Ok(());
+    // Every prompt was already confirmed one by one under --review, or picked under --interactive
+    if !cli.yes
+        && !cli.review
+        && !interactive
+        && !confirm_spawn(cli, &launches, &prompts, layout, filtered)?
+    {
+        println!("aborted.");
+        return Ok(());
+    }
+    let mux = multiplexer(cli);
+    let spawned = spawn_windows(cli, mux.as_ref(), &launches, &titles, &env, layout)?;
+    if let Err(err) = save_last_run(
+        &cli.session,
+        spawned.first_window,
+        spawned.last_window,
+        &spawned.window_ids,
+    ) {
+        eprintln!("warning: failed to record run for undo: {err:#}");
+    }
+    if cli.check_off {
+        match write_checked_off(file, &content, &todos) {
+            Ok(checked) => println!("checked off {checked} todo(s) in {}.", file.display()),
+            Err(err) => eprintln!(
+                "warning: failed to check off todos in {}: {err:#}",
+                file.display()
+            ),
         }
     }
-    let used_existing_session = spawn_tmux(&cli, &harness_cmd, &prompts)?;
 
+    let backend = cli.backend.name();
     if cli.attach {
-        run_tmux(&cli.tmux_bin, ["attach", "-t", cli.session.as_str()])?;
+        mux.attach(&cli.session)?;
     } else {
-        if used_existing_session {
+        let added = match layout {
+            Some(_) => format!("1 window of {} pane(s)", prompts.len()),
+            None => format!("{} window(s)", prompts.len()),
+        };
+        if spawned.used_existing_session {
             println!(
-                "tmux session '{}' already existed; added {} window(s).",
-                cli.session,
-                prompts.len()
+                "{backend} session '{}' already existed; added {added}.",
+                cli.session
             );
         } else {
-            println!(
-                "tmux session '{}' created with {} window(s).",
-                cli.session,
-                prompts.len()
-            );
+            println!("{backend} session '{}' created with {added}.", cli.session);
         }
-        println!("attach with: tmux attach -t {}", cli.session);
+        println!("attach with: {}", cli.backend.attach_hint(&cli.session));
     }
 
     Ok(())
 }
 
-fn extract_unchecked_todos(content: &str) -> Vec<String> {
-    let re = Regex::new(r"^(\s*)([-*+])\s+\[\s*\]\s*(.*)$").unwrap();
+/// The checkbox states to match: `--states`, plus or replaced by the checked marks with
+/// `--include-done` or `--only-done`.
+fn actionable_states(cli: &Cli) -> Result<Vec<char>> {
+    const DONE: [char; 2] = ['x', 'X'];
+    if cli.only_done {
+        return Ok(DONE.to_vec());
+    }
+    let mut states = parse_states(&cli.states)?;
+    if cli.include_done {
+        for done in DONE {
+            if !states.contains(&done) {
+                states.push(done);
+            }
+        }
+    }
+    Ok(states)
+}
+
+fn parse_states(raw: &str) -> Result<Vec<char>> {
+    let mut states = Vec::new();
+    for part in raw.split(',') {
+        let Some(state) = checkbox_state(part) else {
+            bail!("invalid checkbox state '{part}': expected a single character");
+        };
+        if !states.contains(&state) {
+            states.push(state);
+        }
+    }
+    Ok(states)
+}
+
+/// Parses `--only`: comma-separated 1-based positions and inclusive `a-b` ranges.
+fn parse_only(raw: &str) -> Result<BTreeSet<usize>> {
+    let mut picked = BTreeSet::new();
+    for part in raw.split(',').map(str::trim) {
+        let position = |text: &str| match text.trim().parse::<usize>() {
+            Ok(0) => bail!("todo positions start at 1"),
+            Ok(n) => Ok(n),
+            Err(_) => bail!("invalid todo position '{part}': expected N or N-M"),
+        };
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (position(start)?, position(end)?);
+                if start > end {
+                    bail!("invalid todo range '{part}': start is after end");
+                }
+                picked.extend(start..=end);
+            }
+            None => {
+                picked.insert(position(part)?);
+            }
+        }
+    }
+    Ok(picked)
+}
+
+fn checkbox_state(mark: &str) -> Option<char> {
+    let mut chars = mark.trim().chars();
+    match (chars.next(), chars.next()) {
+        (None, _) => Some(' '),
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+/// A matching todo block and the markdown heading it sits under, if any.
+struct Todo {
+    text: String,
+    section: Option<String>,
+    /// Indexes of the lines the todo block spans in the file.
+    lines: Range<usize>,
+    /// The `@harness(NAME)` tag from the first line, which is left out of `text`.
+    harness: Option<String>,
+    /// The `@cwd(PATH)` tag from the first line, also left out of `text`.
+    cwd: Option<String>,
+}
+
+/// The todos in `content` that `--states` (and friends) and `--section` select, in document order.
+fn select_todos(cli: &Cli, content: &str) -> Result<Vec<Todo>> {
+    let states = actionable_states(cli)?;
+    let mut todos = extract_todos(content, &states);
+    if let Some(name) = &cli.section {
+        let sections = section_lines(content, name)?;
+        todos.retain(|todo| {
+            sections
+                .iter()
+                .any(|lines| lines.contains(&todo.lines.start))
+        });
+    }
+    Ok(todos)
+}
+
+/// Applies `--skip` and `--match` to each todo's first line.
+fn filter_todos(cli: &Cli, mut todos: Vec<Todo>) -> Vec<Todo> {
+    todos.retain(|todo| {
+        let first = todo.text.lines().next().unwrap_or_default();
+        !cli.skip.iter().any(|re| re.is_match(first))
+            && (cli.match_.is_empty() || cli.match_.iter().any(|re| re.is_match(first)))
+    });
+    todos
+}
+
+/// Keeps the todos `--only` picks, failing if it names a position past the last one.
+fn pick_todos(cli: &Cli, todos: Vec<Todo>) -> Result<Vec<Todo>> {
+    let Some(only) = &cli.only else {
+        return Ok(todos);
+    };
+    if let Some(&last) = only.last()
+        && last > todos.len()
+    {
+        let missing: Vec<String> = only
+            .range(todos.len() + 1..)
+            .map(usize::to_string)
+            .collect();
+        bail!(
+            "--only: no todo at position(s) {} (only {} matched)",
+            missing.join(", "),
+            todos.len()
+        );
+    }
+    Ok(todos
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, _)| only.contains(&(idx + 1)))
+        .map(|(_, todo)| todo)
+        .collect())
+}
+
+/// The line ranges of every section headed `name`: from its heading up to the next heading
+/// of the same or a higher level. Fails, listing the headings there are, if none matches.
+fn section_lines(content: &str, name: &str) -> Result<Vec<Range<usize>>> {
+    let heading = Regex::new(r"^(#{1,6})\s+(.*?)[\s#]*$").unwrap();
+    let name = name.trim();
+    let mut headings = Vec::new();
+    let mut sections = Vec::new();
+    // Level and first line of the matching section being scanned
+    let mut open: Option<(usize, usize)> = None;
+    let mut total = 0;
+    for (i, line) in content.lines().enumerate() {
+        total = i + 1;
+        let Some(caps) = heading.captures(line) else {
+            continue;
+        };
+        let level = caps[1].len();
+        if let Some((open_level, start)) = open
+            && level <= open_level
+        {
+            sections.push(start..i);
+            open = None;
+        }
+        if open.is_none() && &caps[2] == name {
+            open = Some((level, i));
+        }
+        if !headings.iter().any(|heading| heading == &caps[2]) {
+            headings.push(caps[2].to_string());
+        }
+    }
+    if let Some((_, start)) = open {
+        sections.push(start..total);
+    }
+    if sections.is_empty() {
+        let available = if headings.is_empty() {
+            "none".to_string()
+        } else {
+            headings.join(", ")
+        };
+        bail!("section '{name}' not found; available sections: {available}");
+    }
+    Ok(sections)
+}
+
+fn extract_todos(content: &str, states: &[char]) -> Vec<Todo> {
+    // Bullets (`- [ ]`, `* [ ]`, `+ [ ]`) and numbered items (`1. [ ]`, `2) [ ]`)
+    let re = Regex::new(r"^(\s*)([-*+]|\d+[.)])\s+\[([^\]]*)\]\s*(.*)$").unwrap();
+    let heading = Regex::new(r"^#{1,6}\s+(.*?)[\s#]*$").unwrap();
     let lines: Vec<&str> = content.lines().collect();
     let mut items = Vec::new();
+    let mut section = None;
     let mut i = 0;
 
     while i < lines.len() {
         let line = lines[i];
-        if let Some(caps) = re.captures(line) {
+        if let Some(caps) = heading.captures(line) {
+            section = Some(caps[1].to_string());
+        }
+        let matched = re
+            .captures(line)
+            .filter(|caps| checkbox_state(&caps[3]).is_some_and(|state| states.contains(&state)));
+        if let Some(caps) = matched {
             let indent = caps.get(1).map(|m| m.as_str().len()).unwrap_or(0);
             let mut block = vec![line.to_string()];
             let mut j = i + 1;
@@ -150,7 +771,16 @@ fn extract_unchecked_todos(content: &str) -> Vec<String> {
                 break;
             }
 
-            items.push(block.join("\n"));
+            let (first, harness) = take_tag(&block[0], "harness");
+            let (first, cwd) = take_tag(&first, "cwd");
+            block[0] = first;
+            items.push(Todo {
+                text: block.join("\n"),
+                section: section.clone(),
+                lines: i..j,
+                harness,
+                cwd,
+            });
             i = j;
         } else {
             i += 1;
@@ -160,202 +790,2091 @@ fn extract_unchecked_todos(content: &str) -> Vec<String> {
     items
 }
 
-fn build_prompt(item: &str, prefix: Option<&str>, suffix: Option<&str>) -> String {
-    let mut parts = Vec::new();
-    if let Some(p) = prefix {
-        if !p.trim().is_empty() {
-            parts.push(p.trim_end().to_string());
+/// Marks each of `todos` as done in `content`, leaving every other byte as it was.
+/// Returns the new content and how many checkboxes changed.
+fn check_off_todos(content: &str, todos: &[&Todo]) -> (String, usize) {
+    let checkbox = Regex::new(r"^(\s*(?:[-*+]|\d+[.)])\s+\[)([^\]]*)\]").unwrap();
+    let mut checked = 0;
+    let mut output = String::with_capacity(content.len());
+    for (i, line) in content.split_inclusive('\n').enumerate() {
+        let caps = todos
+            .iter()
+            .any(|todo| todo.lines.start == i)
+            .then(|| checkbox.captures(line))
+            .flatten()
+            .filter(|caps| !matches!(&caps[2], "x" | "X"));
+        match caps {
+            Some(caps) => {
+                let end = caps.get(0).map_or(0, |m| m.end());
+                output.push_str(&caps[1]);
+                output.push_str("x]");
+                output.push_str(&line[end..]);
+                checked += 1;
+            }
+            None => output.push_str(line),
         }
     }
-    parts.push(item.trim_end().to_string());
-    if let Some(s) = suffix {
-        if !s.trim().is_empty() {
-            parts.push(s.trim_start().to_string());
+    (output, checked)
+}
+
+/// Rewrites `path` with `todos` checked off, through a temp file renamed over it.
+/// Refuses if the file no longer holds `content`, so edits made meanwhile aren't lost.
+fn write_checked_off(path: &Path, content: &str, todos: &[&Todo]) -> Result<usize> {
+    let current = fs::read_to_string(path)?;
+    if current != content {
+        bail!("the file changed since it was read");
+    }
+    let (output, checked) = check_off_todos(content, todos);
+    if checked == 0 {
+        return Ok(0);
+    }
+    let permissions = fs::metadata(path)?.permissions();
+    let temp_path = path.with_extension("tmp");
+    let result = fs::write(&temp_path, output)
+        .and_then(|()| fs::set_permissions(&temp_path, permissions))
+        .and_then(|()| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(checked)
+}
+
+/// Splits an `@<name>(VALUE)` tag such as `@harness(claude)` off a todo's first line,
+/// with the space before it.
+fn take_tag(line: &str, name: &str) -> (String, Option<String>) {
+    let tag = Regex::new(&format!(r"\s*@{name}\(([^)]+)\)")).unwrap();
+    match tag.captures(line) {
+        Some(caps) => {
+            let whole = caps.get(0).expect("group 0 always matches");
+            let stripped = format!("{}{}", &line[..whole.start()], &line[whole.end()..]);
+            (stripped, Some(caps[1].trim().to_string()))
         }
+        None => (line.to_string(), None),
     }
-    parts.join("\n\n")
 }
 
-fn spawn_tmux(cli: &Cli, harness_cmd: &[String], prompts: &[String]) -> Result<bool> {
-    let session = cli.session.as_str();
-    let tmux = cli.tmux_bin.as_str();
+/// The `--harness` command a tagged todo runs (`None` for the default), failing on a
+/// tag no `--harness` defines.
+fn todo_harness<'a>(cli: &'a Cli, todo: &Todo) -> Result<Option<&'a [String]>> {
+    let Some(name) = &todo.harness else {
+        return Ok(None);
+    };
+    // A later --harness with the same name overrides an earlier one
+    match cli
+        .harness
+        .iter()
+        .rev()
+        .find(|harness| &harness.name == name)
+    {
+        Some(harness) => Ok(Some(&harness.cmd)),
+        None => bail!(
+            "todo '{}' is tagged @harness({name}), but no --harness {name}=CMD was given",
+            todo_title(&todo.text)
+        ),
+    }
+}
 
-    let mut created_session = false;
-    let mut used_existing_session = false;
-    let start_index = if tmux_has_session(tmux, session)? {
-        if cli.replace {
-            run_tmux(tmux, ["kill-session", "-t", session])?;
-            run_tmux(tmux, ["new-session", "-d", "-s", session, "-n", "1"])?;
-            created_session = true;
-            1
-        } else {
-            used_existing_session = true;
-            tmux_next_window_index(tmux, session)?
-        }
-    } else {
-        run_tmux(tmux, ["new-session", "-d", "-s", session, "-n", "1"])?;
-        created_session = true;
-        1
+/// The directory a todo's window starts in: its `@cwd(PATH)` tag, resolved against
+/// `--cwd` when relative, else `--cwd` itself. Fails unless it is an existing directory.
+fn todo_cwd(cli: &Cli, todo: &Todo) -> Result<Option<String>> {
+    let dir = match (&cli.cwd, &todo.cwd) {
+        (Some(base), Some(tag)) => base.join(tag),
+        (None, Some(tag)) => PathBuf::from(tag),
+        (Some(base), None) => base.clone(),
+        (None, None) => return Ok(None),
     };
+    // Absolute, since tmux would resolve a relative path against its server's directory
+    let resolved = fs::canonicalize(&dir)
+        .ok()
+        .filter(|path| path.is_dir())
+        .with_context(|| {
+            format!(
+                "working directory {} for todo '{}' does not exist",
+                dir.display(),
+                todo_title(&todo.text)
+            )
+        })?;
+    Ok(Some(resolved.to_string_lossy().into_owned()))
+}
 
-    for (idx, prompt) in prompts.iter().enumerate() {
-        let window_number = start_index + idx as u32;
-        let window_name = format!("{}", window_number);
-        if created_session && idx == 0 {
-            // use the initial window created with the session
-        } else {
-            run_tmux(tmux, ["new-window", "-t", session, "-n", &window_name])?;
-        }
+/// Text placed between the prefix and the item, and between the item and the suffix.
+struct Separators {
+    prefix: String,
+    suffix: String,
+}
 
-        let target = format!("{}:{}", session, window_name);
-        let cmd = build_shell_command(harness_cmd, prompt)?;
-        run_tmux(tmux, ["send-keys", "-t", &target, "-l", &cmd])?;
-        run_tmux(tmux, ["send-keys", "-t", &target, "C-m"])?;
+impl Separators {
+    fn from_cli(cli: &Cli) -> Self {
+        let default = unescape(&cli.separator);
+        Self {
+            prefix: cli.prefix_sep.as_deref().map_or(default.clone(), unescape),
+            suffix: cli.suffix_sep.as_deref().map_or(default, unescape),
+        }
     }
-
-    Ok(used_existing_session)
 }
 
-fn build_shell_command(harness_cmd: &[String], prompt: &str) -> Result<String> {
-    if !contains_item_token(harness_cmd) {
-        bail!("harness command must include {{item}}");
-    }
-    let mut parts = Vec::with_capacity(harness_cmd.len());
-    for arg in harness_cmd {
-        let replaced = replace_item_token(arg, prompt);
-        parts.push(shell_escape(&replaced));
-    }
-    Ok(parts.join(" "))
+/// Expands `\n` and `\t` so separators can be passed as a single shell argument.
+fn unescape(raw: &str) -> String {
+    raw.replace("\\n", "\n").replace("\\t", "\t")
 }
 
-fn shell_escape(input: &str) -> String {
-    if input.is_empty() {
-        return "''".to_string();
+fn build_prompt(
+    item: &str,
+    prefix: Option<&str>,
+    suffix: Option<&str>,
+    separators: &Separators,
+) -> String {
+    let mut prompt = String::new();
+    if let Some(p) = prefix
+        && !p.trim().is_empty()
+    {
+        prompt.push_str(p.trim_end());
+        prompt.push_str(&separators.prefix);
     }
-    if !input.contains([
-        ' ', '\t', '\n', '\r', '\'', '"', '\\', '$', '`', '!', '(', ')',
-    ]) {
-        return input.to_string();
+    prompt.push_str(item.trim_end());
+    if let Some(s) = suffix
+        && !s.trim().is_empty()
+    {
+        prompt.push_str(&separators.suffix);
+        prompt.push_str(s.trim_start());
     }
-    let escaped = input.replace('\'', r#"'"'"'"#);
-    format!("'{}'", escaped)
+    prompt
 }
 
-fn normalize_harness_cmd(raw: &[String]) -> Result<Vec<String>> {
-    if raw.is_empty() {
-        bail!("harness command is empty");
-    }
-    if raw.len() == 1 {
-        let single = raw[0].trim();
-        if single.chars().any(char::is_whitespace) {
-            let parsed = shell_split(single).with_context(
-                || "failed to parse harness command string; try quoting individual args or use --",
-            )?;
-            if parsed.is_empty() {
-                bail!("harness command is empty");
-            }
-            return Ok(parsed);
-        }
-    }
-    Ok(raw.to_vec())
+/// Renders a `--template` for a todo, with `{item}` standing for the todo's text.
+fn render_template(template: &str, tokens: &Tokens) -> String {
+    let item = tokens.todo.text.trim_end();
+    fill_tokens(template, Some(item), tokens)
+        .trim_end()
+        .to_string()
 }
 
-fn confirm_spawn(cli: &Cli, harness_cmd: &[String], prompts: &[String]) -> Result<bool> {
-    let count = prompts.len();
-    println!("About to create tmux session '{}'", cli.session);
-    if cli.replace {
-        println!("  - will replace existing session if present");
+/// The text after the checkbox on a todo's first line.
+fn todo_title(text: &str) -> &str {
+    let first = text.lines().next().unwrap_or_default();
+    let re = Regex::new(r"^\s*(?:[-*+]|\d+[.)])\s+\[[^\]]*\]\s*").unwrap();
+    match re.find(first) {
+        Some(marker) => first[marker.end()..].trim(),
+        None => first.trim(),
     }
-    println!("  - windows: {}", count);
-    println!("  - harness: {}", harness_cmd.join(" "));
-    print_prompt_previews(prompts);
-    println!("Proceed? [Y/n] ");
+}
 
-    let mut input = String::new();
-    std::io::stdin()
-        .read_line(&mut input)
-        .context("failed to read confirmation input")?;
-    let answer = input.trim().to_ascii_lowercase();
-    Ok(answer.is_empty() || answer == "y" || answer == "yes")
+/// A prompt under `--review`, kept with the todo it was built from.
+struct Job<'a> {
+    prompt: String,
+    todo: &'a Todo,
 }
 
-fn print_prompt_previews(prompts: &[String]) {
-    let preview_count = 5.min(prompts.len());
-    println!("  - preview ({} of {}):", preview_count, prompts.len());
-    for (idx, prompt) in prompts.iter().take(preview_count).enumerate() {
-        let mut lines = prompt.lines();
-        let first = lines.next().unwrap_or("");
-        let mut preview = first.trim_end().to_string();
-        if let Some(next) = lines.next() {
-            if !next.trim().is_empty() {
-                preview.push_str(" …");
-            }
-        }
-        if preview.is_empty() {
-            preview = "<empty>".to_string();
-        }
-        println!("    {}. {}", idx + 1, preview);
-    }
-    if prompts.len() > preview_count {
-        println!("    … and {} more", prompts.len() - preview_count);
+impl AsRef<str> for Job<'_> {
+    fn as_ref(&self) -> &str {
+        &self.prompt
     }
 }
 
-fn contains_item_token(args: &[String]) -> bool {
-    args.iter()
-        .any(|arg| ITEM_TOKENS.iter().any(|token| arg.contains(token)))
+struct SpawnedWindows {
+    used_existing_session: bool,
+    first_window: u32,
+    last_window: u32,
+    window_ids: Vec<String>,
 }
 
-fn replace_item_token(arg: &str, prompt: &str) -> String {
-    let mut out = arg.to_string();
-    for token in ITEM_TOKENS {
-        out = out.replace(token, prompt);
-    }
-    out
+/// A prompt ready to start, with the harness command it runs and the directory it runs in.
+struct Launch<'a> {
+    prompt: &'a str,
+    harness_cmd: &'a [String],
+    cwd: Option<String>,
+    /// `--env` exports typed ahead of the harness command
+    exports: String,
+    tokens: Tokens<'a>,
 }
 
-const ITEM_TOKENS: [&str; 1] = ["{item}"];
-
-fn tmux_has_session(tmux: &str, session: &str) -> Result<bool> {
-    let status = Command::new(tmux)
-        .args(["has-session", "-t", session])
-        .status();
-    match status {
-        Ok(s) if s.success() => Ok(true),
-        Ok(_) => Ok(false),
-        Err(e) => Err(e).with_context(|| format!("failed to run {}", tmux)),
+impl Launch<'_> {
+    /// The shell line typed into the prompt's window or pane.
+    fn command(&self) -> Result<String> {
+        let cmd = build_shell_command(self.harness_cmd, self.prompt, &self.tokens)?;
+        Ok(format!("{}{cmd}", self.exports))
     }
 }
 
-fn tmux_next_window_index(tmux: &str, session: &str) -> Result<u32> {
-    let output = Command::new(tmux)
-        .args(["list-windows", "-t", session, "-F", "#I"])
-        .output()
-        .with_context(|| format!("failed to run {}", tmux))?;
-    if !output.status.success() {
-        bail!("tmux command failed: {}", tmux);
-    }
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut max_index: Option<u32> = None;
-    for line in stdout.lines() {
-        if let Ok(num) = line.trim().parse::<u32>() {
-            max_index = Some(max_index.map_or(num, |cur| cur.max(num)));
+fn spawn_windows(
+    cli: &Cli,
+    mux: &dyn Multiplexer,
+    launches: &[Launch],
+    titles: &[String],
+    env: &[(String, String)],
+    layout: Option<Layout>,
+) -> Result<SpawnedWindows> {
+    let session = cli.session.as_str();
+    let mut existing = None;
+    if mux.has_session(session)? {
+        if cli.replace {
+            mux.kill_session(session)?;
+        } else {
+            existing = Some(mux.windows(session)?);
         }
     }
-    Ok(max_index.unwrap_or(0).saturating_add(1))
-}
+    let used_existing_session = existing.is_some();
+    let start_index = existing.as_deref().map_or(1, next_window_index);
 
-fn run_tmux<I, S>(tmux: &str, args: I) -> Result<()>
+    // With a layout, every prompt shares the first window
+    let window_count = if layout.is_some() { 1 } else { launches.len() };
+    let names = window_names(cli, &titles[..window_count], start_index);
+    let mut window_ids = Vec::with_capacity(window_count);
+    for (idx, name) in names.iter().enumerate() {
+        let window = NewWindow {
+            name,
+            cwd: launches[idx].cwd.as_deref(),
+            env,
+        };
+        // Windows are targeted by what the backend returns (a tmux window id), whatever
+        // the names look like
+        let target = if idx == 0 && existing.is_none() {
+            mux.new_session(session, &window)?
+        } else {
+            mux.new_window(session, &window)?
+        };
+
+        match layout {
+            Some(layout) => fill_panes(mux, session, &target, layout, env, launches)?,
+            None => {
+                let cmd = launches[idx].command()?;
+                mux.send_keys(session, &target, &cmd)?;
+            }
+        }
+        window_ids.push(target);
+    }
+
+    Ok(SpawnedWindows {
+        used_existing_session,
+        first_window: start_index,
+        last_window: start_index + window_count.saturating_sub(1) as u32,
+        window_ids,
+    })
+}
+
+/// Splits the window `target` until every prompt has a pane, arranging them with `layout`,
+/// and starts each prompt in its pane. The layout is applied after every split so the next
+/// split still has room.
+fn fill_panes(
+    mux: &dyn Multiplexer,
+    session: &str,
+    target: &str,
+    layout: Layout,
+    env: &[(String, String)],
+    launches: &[Launch],
+) -> Result<()> {
+    // Pane ids (%N) stay valid however the layout renumbers the panes
+    let mut panes = vec![mux.first_pane(target)?];
+    for launch in &launches[1..] {
+        panes.push(mux.split_window(target, launch.cwd.as_deref(), env, layout)?);
+    }
+    for (launch, pane) in launches.iter().zip(&panes) {
+        let cmd = launch.command()?;
+        mux.send_keys(session, pane, &cmd)?;
+    }
+    Ok(())
+}
+
+/// Reports the windows a run would create, flagging names that existing windows already answer to.
+fn print_window_plan(
+    cli: &Cli,
+    mux: &dyn Multiplexer,
+    titles: &[String],
+    layout: Option<Layout>,
+) -> Result<()> {
+    let session = cli.session.as_str();
+    let count = titles.len();
+    let windows = if layout.is_some() { 1 } else { count };
+    let last_offset = windows.saturating_sub(1) as u32;
+
+    println!("--- {} plan ---", cli.backend.name());
+    if let Some(layout) = layout {
+        println!(
+            "{count} pane(s) in one window, {} layout",
+            layout.tmux_name()
+        );
+    }
+    if !mux.has_session(session)? || cli.replace {
+        let verb = if cli.replace { "replace" } else { "create" };
+        println!(
+            "would {} session '{}' with windows 1-{}",
+            verb,
+            session,
+            1 + last_offset
+        );
+        if cli.name_from_todo {
+            print_window_names(1, &window_names(cli, titles, 1));
+        }
+        return Ok(());
+    }
+
+    let windows = mux.windows(session)?;
+    let start = next_window_index(&windows);
+    println!(
+        "session '{}' exists; would add windows {}-{}",
+        session,
+        start,
+        start + last_offset
+    );
+    let names = window_names(cli, &titles[..=last_offset as usize], start);
+    if cli.name_from_todo {
+        print_window_names(start, &names);
+    }
+    let mut collisions = 0;
+    for (number, name) in (start..).zip(&names) {
+        for window in windows
+            .iter()
+            .filter(|w| w.index == number || w.name == *name)
+        {
+            println!(
+                "  collision: window '{}' would clash with existing window {} ('{}')",
+                name, window.index, window.name
+            );
+            collisions += 1;
+        }
+    }
+    if collisions == 0 {
+        println!("no window collisions");
+    } else {
+        println!("{} window collision(s)", collisions);
+    }
+    Ok(())
+}
+
+fn print_window_names(start: u32, names: &[String]) {
+    for (number, name) in (start..).zip(names) {
+        println!("  window {number}: {name}");
+    }
+}
+
+/// The names for windows numbered from `start`: their numbers, followed with
+/// `--name-from-todo` by slugs of `titles`.
+fn window_names(cli: &Cli, titles: &[String], start: u32) -> Vec<String> {
+    (start..)
+        .zip(titles)
+        .map(|(number, title)| {
+            let title = cli.name_from_todo.then_some(title.as_str());
+            window_name(number, title, cli.name_width)
+        })
+        .collect()
+}
+
+/// Cuts `slug` to `width` characters, marking a cut with `…`; empty if nothing fits.
+fn fit_slug(slug: &str, width: usize) -> String {
+    if slug.chars().count() <= width {
+        return slug.to_string();
+    }
+    let kept: String = slug.chars().take(width.saturating_sub(1)).collect();
+    let kept = kept.trim_end_matches('-');
+    if kept.is_empty() {
+        return String::new();
+    }
+    format!("{kept}…")
+}
+
+/// Names a window `<number>` or, given a title, `<number>-<slug>` cut to `width` characters.
+/// The number always survives truncation so names stay unique and sort in spawn order.
+fn window_name(number: u32, title: Option<&str>, width: usize) -> String {
+    let prefix = number.to_string();
+    let Some(slug) = title.map(slugify).filter(|slug| !slug.is_empty()) else {
+        return prefix;
+    };
+    // Leave room for the '-' separator
+    let kept = fit_slug(&slug, width.saturating_sub(prefix.len() + 1));
+    if kept.is_empty() {
+        return prefix;
+    }
+    format!("{prefix}-{kept}")
+}
+
+/// Lowercases `text` and joins its alphanumeric runs with dashes, which tmux accepts in targets.
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Recovers the spawn number from a name produced by [`window_name`].
+fn window_number(name: &str) -> Option<u32> {
+    name.split('-').next()?.parse().ok()
+}
+
+fn last_run_path(session: &str) -> Result<PathBuf> {
+    let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = std::env::var_os("HOME").context("HOME is not set")?;
+            PathBuf::from(home).join(".local").join("state")
+        }
+    };
+    let name: String = session
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    Ok(state_dir.join("spawn").join(format!("last-run-{name}")))
+}
+
+fn save_last_run(
+    session: &str,
+    first_window: u32,
+    last_window: u32,
+    window_ids: &[String],
+) -> Result<()> {
+    let path = last_run_path(session)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let mut record = format!("{first_window} {last_window}");
+    for id in window_ids {
+        record.push(' ');
+        record.push_str(id);
+    }
+    record.push('\n');
+    fs::write(&path, record).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Reads the window range of the last run and the ids of the windows it created.
+fn load_last_run(session: &str) -> Result<(u32, u32, Vec<String>)> {
+    let path = last_run_path(session)?;
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("no recorded run for session '{session}'"))?;
+    let mut parts = content.split_whitespace();
+    let mut number = || parts.next().map(str::parse::<u32>);
+    let (Some(Ok(first)), Some(Ok(last))) = (number(), number()) else {
+        bail!("malformed run record in {}", path.display());
+    };
+    let window_ids: Vec<String> = parts.map(String::from).collect();
+    if first > last || window_ids.is_empty() {
+        bail!("malformed run record in {}", path.display());
+    }
+    Ok((first, last, window_ids))
+}
+
+fn undo_last_run(cli: &Cli) -> Result<()> {
+    let session = cli.session.as_str();
+    let backend = cli.backend.name();
+    let mux = multiplexer(cli);
+    let (first, last, window_ids) = load_last_run(session)?;
+    if !mux.has_session(session)? {
+        bail!("{backend} session '{}' does not exist", session);
+    }
+
+    let mut killed = 0;
+    for window in mux.windows(session)? {
+        if window_ids.contains(&window.id) {
+            mux.kill_window(session, &window)?;
+            killed += 1;
+        }
+    }
+    fs::remove_file(last_run_path(session)?).ok();
+
+    println!(
+        "killed {} window(s) ({}-{}) in {backend} session '{}'.",
+        killed, first, last, session
+    );
+    Ok(())
+}
+
+fn valid_env_key(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parses `--env KEY=VALUE`; the value is kept as written, tokens included.
+fn parse_env_var(raw: &str) -> Result<(String, String)> {
+    let Some((key, value)) = raw.split_once('=') else {
+        bail!("expected KEY=VALUE");
+    };
+    if !valid_env_key(key) {
+        bail!("invalid variable name '{key}'");
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// The `export KEY=VALUE; ` prefix typed before each harness command for `--env`, with the
+/// tokens in each value filled for the prompt's todo.
+fn env_exports(vars: &[(String, String)], tokens: &Tokens) -> String {
+    vars.iter()
+        .map(|(key, value)| {
+            let value = fill_tokens(value, None, tokens);
+            format!("export {key}={}; ", shell_escape(&value))
+        })
+        .collect()
+}
+
+/// `--env` as shown in previews: `KEY=VALUE` pairs, or with `--mask-env` only the names.
+fn describe_env_vars(cli: &Cli) -> String {
+    cli.env
+        .iter()
+        .map(|(key, value)| match cli.mask_env {
+            true => format!("{key}=***"),
+            false => format!("{key}={value}"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn load_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    parse_env_file(&content).with_context(|| format!("invalid env file {}", path.display()))
+}
+
+/// Parses dotenv-style `KEY=value` lines. Blank lines, `#` comments and a leading
+/// `export` are ignored; values may be single-quoted (literal) or double-quoted
+/// (with `\n`, `\t`, `\"` and `\\` escapes), and unquoted values end at a ` #` comment.
+fn parse_env_file(content: &str) -> Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let Some((key, raw)) = line.split_once('=') else {
+            bail!("line {line_no}: expected KEY=VALUE");
+        };
+        let key = key.trim();
+        if !valid_env_key(key) {
+            bail!("line {line_no}: invalid variable name '{key}'");
+        }
+        let value = parse_env_value(raw.trim())
+            .with_context(|| format!("line {line_no}: unterminated quote in {key}"))?;
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+fn parse_env_value(raw: &str) -> Option<String> {
+    if let Some(rest) = raw.strip_prefix('\'') {
+        let end = rest.find('\'')?;
+        return Some(rest[..end].to_string());
+    }
+    if let Some(rest) = raw.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Some(value),
+                '\\' => match chars.next()? {
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    other => value.push(other),
+                },
+                other => value.push(other),
+            }
+        }
+        return None;
+    }
+    let value = match raw.find(" #") {
+        Some(comment) => &raw[..comment],
+        None => raw,
+    };
+    Some(value.trim_end().to_string())
+}
+
+fn build_shell_command(harness_cmd: &[String], prompt: &str, tokens: &Tokens) -> Result<String> {
+    if !contains_item_token(harness_cmd) {
+        bail!("harness command must include {{item}}");
+    }
+    let mut parts = Vec::with_capacity(harness_cmd.len());
+    for arg in harness_cmd {
+        let replaced = fill_tokens(arg, Some(prompt), tokens);
+        parts.push(shell_escape(&replaced));
+    }
+    Ok(parts.join(" "))
+}
+
+fn shell_escape(input: &str) -> String {
+    if input.is_empty() {
+        return "''".to_string();
+    }
+    if !input.contains([
+        ' ', '\t', '\n', '\r', '\'', '"', '\\', '$', '`', '!', '(', ')',
+    ]) {
+        return input.to_string();
+    }
+    let escaped = input.replace('\'', r#"'"'"'"#);
+    format!("'{}'", escaped)
+}
+
+fn normalize_harness_cmd(raw: &[String]) -> Result<Vec<String>> {
+    if raw.is_empty() {
+        bail!("harness command is empty");
+    }
+    if raw.len() == 1 {
+        let single = raw[0].trim();
+        if single.chars().any(char::is_whitespace) {
+            let parsed = shell_split(single).with_context(
+                || "failed to parse harness command string; try quoting individual args or use --",
+            )?;
+            if parsed.is_empty() {
+                bail!("harness command is empty");
+            }
+            return Ok(parsed);
+        }
+    }
+    Ok(raw.to_vec())
+}
+
+/// Warns (or with `strict`, fails) when the todos being spawned, numbered in spawn order,
+/// span several lines: `{item}` is substituted as a single argument typed into the window,
+/// so the newlines reach the harness literally and one that reads its input line by line
+/// may only act on the first line. Newlines the prefix, suffix, separators or template add
+/// around every todo aren't counted.
+fn check_multiline_todos(todos: &[&Todo], strict: bool) -> Result<()> {
+    let multiline: Vec<String> = todos
+        .iter()
+        .enumerate()
+        .filter(|(_, todo)| todo.text.trim_end().contains('\n'))
+        .map(|(i, _)| (i + 1).to_string())
+        .collect();
+    if multiline.is_empty() {
+        return Ok(());
+    }
+    let message = format!(
+        "todo(s) {} span several lines and are passed to the harness as one argument with literal newlines; \
+         a harness that reads line by line may only see the first line. Have the harness read the prompt \
+         from a file or stdin, or keep todos on one line",
+        multiline.join(", ")
+    );
+    if strict {
+        bail!("{message} (--strict)");
+    }
+    warn!("{message}");
+    Ok(())
+}
+
+/// Shows each prompt in full and asks whether to include it, like `git add -p`:
+/// y includes it, n skips it, a includes it and all the rest, q stops (keeping what was
+/// included so far). End of input counts as q.
+fn review_prompts<T: AsRef<str>>(
+    prompts: Vec<T>,
+    input: &mut impl std::io::BufRead,
+    output: &mut impl std::io::Write,
+) -> Result<Vec<T>> {
+    let total = prompts.len();
+    let mut selected = Vec::new();
+    let mut remaining = prompts.into_iter().enumerate();
+    while let Some((idx, prompt)) = remaining.next() {
+        writeln!(
+            output,
+            "--- prompt {}/{} ---\n{}\n",
+            idx + 1,
+            total,
+            prompt.as_ref()
+        )?;
+        loop {
+            write!(output, "Include this prompt? [y,n,a,q,?] ")?;
+            output.flush()?;
+            let mut answer = String::new();
+            if input
+                .read_line(&mut answer)
+                .context("failed to read review input")?
+                == 0
+            {
+                return Ok(selected);
+            }
+            match answer.trim().to_ascii_lowercase().as_str() {
+                "y" | "yes" => {
+                    selected.push(prompt);
+                    break;
+                }
+                "n" | "no" => break,
+                "a" => {
+                    selected.push(prompt);
+                    selected.extend(remaining.map(|(_, prompt)| prompt));
+                    return Ok(selected);
+                }
+                "q" => return Ok(selected),
+                _ => writeln!(
+                    output,
+                    "y - include this prompt\nn - skip it\na - include it and all remaining prompts\nq - stop here, keeping the prompts included so far"
+                )?,
+            }
+        }
+    }
+    Ok(selected)
+}
+
+/// A key the `--interactive` checklist reacts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PickerKey {
+    Up,
+    Down,
+    Toggle,
+    All,
+    Confirm,
+    Cancel,
+}
+
+/// The `--interactive` checklist: which todos are ticked and where the cursor is.
+/// Everything up to the cap starts out ticked.
+struct Picker<'a> {
+    titles: &'a [&'a str],
+    selected: Vec<bool>,
+    cursor: usize,
+    /// `--max`: how many todos may be ticked at once
+    cap: usize,
+    note: Option<String>,
+}
+
+impl<'a> Picker<'a> {
+    fn new(titles: &'a [&'a str], cap: Option<usize>) -> Self {
+        let cap = cap.unwrap_or(titles.len());
+        Self {
+            titles,
+            selected: (0..titles.len()).map(|idx| idx < cap).collect(),
+            cursor: 0,
+            cap,
+            note: None,
+        }
+    }
+
+    fn ticked(&self) -> usize {
+        self.selected.iter().filter(|&&ticked| ticked).count()
+    }
+
+    /// Applies `key`, returning the ticked positions (in document order) once the
+    /// list is confirmed, or nothing at all when it's cancelled.
+    fn handle(&mut self, key: PickerKey) -> Option<Vec<usize>> {
+        self.note = None;
+        let last = self.titles.len().saturating_sub(1);
+        match key {
+            PickerKey::Up => self.cursor = self.cursor.checked_sub(1).unwrap_or(last),
+            PickerKey::Down => {
+                self.cursor = if self.cursor == last {
+                    0
+                } else {
+                    self.cursor + 1
+                }
+            }
+            PickerKey::Toggle => {
+                if self.selected[self.cursor] {
+                    self.selected[self.cursor] = false;
+                } else if self.ticked() < self.cap {
+                    self.selected[self.cursor] = true;
+                } else {
+                    self.note = Some(format!("--max {}: untick another todo first", self.cap));
+                }
+            }
+            PickerKey::All => {
+                let tick = self.ticked() == 0;
+                for (idx, ticked) in self.selected.iter_mut().enumerate() {
+                    *ticked = tick && idx < self.cap;
+                }
+            }
+            PickerKey::Confirm => {
+                return Some(
+                    self.selected
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(idx, &ticked)| ticked.then_some(idx))
+                        .collect(),
+                );
+            }
+            PickerKey::Cancel => return Some(Vec::new()),
+        }
+        None
+    }
+
+    /// Draws the checklist in `rows` terminal lines, scrolling to keep the cursor visible.
+    fn render(&self, rows: usize) -> String {
+        let mut screen = format!(
+            "Pick todos to spawn: {}/{} selected{}\r\n",
+            self.ticked(),
+            self.titles.len(),
+            if self.cap < self.titles.len() {
+                format!(" (at most {})", self.cap)
+            } else {
+                String::new()
+            }
+        );
+        let visible = rows.saturating_sub(3).max(1);
+        let first = (self.cursor + 1).saturating_sub(visible);
+        for (idx, title) in self.titles.iter().enumerate().skip(first).take(visible) {
+            screen.push_str(&format!(
+                "{} [{}] {}. {}\r\n",
+                if idx == self.cursor { '>' } else { ' ' },
+                if self.selected[idx] { 'x' } else { ' ' },
+                idx + 1,
+                title
+            ));
+        }
+        screen.push_str(
+            self.note.as_deref().unwrap_or(
+                "space: toggle  a: all/none  j/k or arrows: move  enter: spawn  q: cancel",
+            ),
+        );
+        screen
+    }
+}
+
+/// Reads one key press from a terminal in raw mode; `None` for keys the picker ignores.
+/// End of input cancels.
+fn read_picker_key(input: &mut impl Read) -> Result<Option<PickerKey>> {
+    let mut next = || -> Result<Option<u8>> {
+        let mut byte = [0];
+        let read = input
+            .read(&mut byte)
+            .context("failed to read from the terminal")?;
+        Ok((read == 1).then_some(byte[0]))
+    };
+    let Some(byte) = next()? else {
+        return Ok(Some(PickerKey::Cancel));
+    };
+    Ok(match byte {
+        b' ' => Some(PickerKey::Toggle),
+        b'\r' | b'\n' => Some(PickerKey::Confirm),
+        b'a' => Some(PickerKey::All),
+        b'k' => Some(PickerKey::Up),
+        b'j' => Some(PickerKey::Down),
+        // q, ctrl-c
+        b'q' | 3 => Some(PickerKey::Cancel),
+        // Arrow keys arrive as ESC [ A / ESC [ B
+        0x1b => match (next()?, next()?) {
+            (Some(b'['), Some(b'A')) => Some(PickerKey::Up),
+            (Some(b'['), Some(b'B')) => Some(PickerKey::Down),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Runs `stty` against the controlling terminal (inherited as stdin).
+fn stty(args: &[&str]) -> Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .context("failed to run stty")?;
+    if !output.status.success() {
+        bail!("stty {} failed", args.join(" "));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Puts the terminal back the way it was when the picker exits, however it exits.
+struct RawTerminal {
+    saved: String,
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        // Leave the alternate screen and show the cursor again
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = std::io::stdout().flush();
+        if let Err(err) = stty(&[&self.saved]) {
+            warn!("failed to restore the terminal: {err:#}");
+        }
+    }
+}
+
+/// Shows the `--interactive` checklist on the terminal and returns the positions of
+/// the todos picked (in document order); empty when cancelled.
+fn pick_interactively(titles: &[&str], cap: Option<usize>) -> Result<Vec<usize>> {
+    let saved = stty(&["-g"])?;
+    let rows = stty(&["size"])
+        .ok()
+        .and_then(|size| size.split_whitespace().next()?.parse().ok())
+        .unwrap_or(24);
+    stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+    let _restore = RawTerminal { saved };
+    let mut picker = Picker::new(titles, cap);
+    let mut stdout = std::io::stdout();
+    let mut stdin = std::io::stdin().lock();
+    // Switch to the alternate screen and hide the cursor
+    write!(stdout, "\x1b[?1049h\x1b[?25l")?;
+    loop {
+        write!(stdout, "\x1b[H\x1b[2J{}", picker.render(rows))?;
+        stdout.flush()?;
+        if let Some(key) = read_picker_key(&mut stdin)?
+            && let Some(picked) = picker.handle(key)
+        {
+            return Ok(picked);
+        }
+    }
+}
+
+/// `filtered` is the number of todos `--skip`/`--match` kept and how many there were, when given.
+fn confirm_spawn(
+    cli: &Cli,
+    launches: &[Launch],
+    prompts: &[String],
+    layout: Option<Layout>,
+    filtered: Option<(usize, usize)>,
+) -> Result<bool> {
+    let count = prompts.len();
+    println!(
+        "About to create {} session '{}'",
+        cli.backend.name(),
+        cli.session
+    );
+    if cli.replace {
+        println!("  - will replace existing session if present");
+    }
+    match layout {
+        Some(layout) => println!(
+            "  - panes: {} in one window ({})",
+            count,
+            layout.tmux_name()
+        ),
+        None => println!("  - windows: {}", count),
+    }
+    if let Some((kept, extracted)) = filtered {
+        println!("  - filters: kept {kept} of {extracted} todo(s)");
+    }
+    let mut harnesses: Vec<(&[String], usize)> = Vec::new();
+    for launch in launches {
+        let cmd = launch.harness_cmd;
+        match harnesses.iter_mut().find(|(seen, _)| *seen == cmd) {
+            Some((_, uses)) => *uses += 1,
+            None => harnesses.push((cmd, 1)),
+        }
+    }
+    if let [(cmd, _)] = harnesses[..] {
+        println!("  - harness: {}", cmd.join(" "));
+    } else {
+        for (cmd, uses) in harnesses {
+            println!("  - harness ({uses} prompt(s)): {}", cmd.join(" "));
+        }
+    }
+    if !cli.env.is_empty() {
+        println!("  - env: {}", describe_env_vars(cli));
+    }
+    print_prompt_previews(prompts);
+    println!("Proceed? [Y/n] ");
+
+    let mut input = String::new();
+    std::io::stdin()
+        .read_line(&mut input)
+        .context("failed to read confirmation input")?;
+    let answer = input.trim().to_ascii_lowercase();
+    Ok(answer.is_empty() || answer == "y" || answer == "yes")
+}
+
+fn print_prompt_previews(prompts: &[String]) {
+    let preview_count = 5.min(prompts.len());
+    println!("  - preview ({} of {}):", preview_count, prompts.len());
+    for (idx, prompt) in prompts.iter().take(preview_count).enumerate() {
+        let mut lines = prompt.lines();
+        let first = lines.next().unwrap_or("");
+        let mut preview = first.trim_end().to_string();
+        if let Some(next) = lines.next()
+            && !next.trim().is_empty()
+        {
+            preview.push_str(" …");
+        }
+        if preview.is_empty() {
+            preview = "<empty>".to_string();
+        }
+        println!("    {}. {}", idx + 1, preview);
+    }
+    if prompts.len() > preview_count {
+        println!("    … and {} more", prompts.len() - preview_count);
+    }
+}
+
+fn contains_item_token(args: &[String]) -> bool {
+    args.iter()
+        .any(|arg| ITEM_TOKENS.iter().any(|token| arg.contains(token)))
+}
+
+const ITEM_TOKENS: [&str; 1] = ["{item}"];
+
+/// The todo file a run reads, for the `{file}` and `{doc_title}` tokens.
+struct Document {
+    /// The `--file` path as given
+    file: String,
+    /// The first H1 (`# Title`), or the file name without its extension
+    title: String,
+}
+
+impl Document {
+    fn new(path: &Path, content: &str) -> Self {
+        let heading = Regex::new(r"^#\s+(.*?)[\s#]*$").unwrap();
+        let title = content
+            .lines()
+            .find_map(|line| heading.captures(line).map(|caps| caps[1].to_string()))
+            .unwrap_or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+        Self {
+            file: path.display().to_string(),
+            title,
+        }
+    }
+}
+
+/// What the tokens stand for when filling text for the todo at 1-based `index`.
+struct Tokens<'a> {
+    index: usize,
+    todo: &'a Todo,
+    document: &'a Document,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(index: usize, todo: &'a Todo, document: &'a Document) -> Self {
+        Self {
+            index,
+            todo,
+            document,
+        }
+    }
+}
+
+/// Fills `{index}`, `{title}` (the todo's first line without its checkbox), `{section}`
+/// (the heading above it, or empty), `{file}` and `{doc_title}` (which don't count as item
+/// tokens: the prompt still has to go somewhere) and, given an `item`, `{item}`, in a
+/// single pass so braces in the substituted text are left alone.
+fn fill_tokens(text: &str, item: Option<&str>, tokens: &Tokens) -> String {
+    let token = Regex::new(r"\{(item|index|title|section|file|doc_title)\}").unwrap();
+    token
+        .replace_all(text, |caps: &regex::Captures| match &caps[1] {
+            "item" => item.map_or_else(|| caps[0].to_string(), str::to_string),
+            "index" => tokens.index.to_string(),
+            "title" => todo_title(&tokens.todo.text).to_string(),
+            "section" => tokens.todo.section.clone().unwrap_or_default(),
+            "file" => tokens.document.file.clone(),
+            _ => tokens.document.title.clone(),
+        })
+        .into_owned()
+}
+
+/// A window in a session, as the backend lists it.
+struct MuxWindow {
+    /// What the backend targets the window by (a tmux window id, a zellij tab name)
+    id: String,
+    index: u32,
+    name: String,
+}
+
+/// The index a window added after `windows` gets.
+fn next_window_index(windows: &[MuxWindow]) -> u32 {
+    windows
+        .iter()
+        .map(|window| window.index)
+        .max()
+        .unwrap_or(0)
+        .saturating_add(1)
+}
+
+/// What a new window (or pane) starts with.
+struct NewWindow<'a> {
+    name: &'a str,
+    cwd: Option<&'a str>,
+    /// Set in the window's environment rather than typed, so values stay out of shell history
+    env: &'a [(String, String)],
+}
+
+/// A terminal multiplexer runs are spawned into, one implementation per `--backend`.
+/// Windows and panes are addressed by the target strings the constructors return.
+trait Multiplexer {
+    fn has_session(&self, session: &str) -> Result<bool>;
+    fn kill_session(&self, session: &str) -> Result<()>;
+    /// Creates `session` in the background with `window` as its only window.
+    fn new_session(&self, session: &str, window: &NewWindow) -> Result<String>;
+    fn new_window(&self, session: &str, window: &NewWindow) -> Result<String>;
+    /// Types `cmd` into the window or pane `target` and presses Enter.
+    fn send_keys(&self, session: &str, target: &str, cmd: &str) -> Result<()>;
+    /// The session's windows in order.
+    fn windows(&self, session: &str) -> Result<Vec<MuxWindow>>;
+    fn kill_window(&self, session: &str, window: &MuxWindow) -> Result<()>;
+    fn attach(&self, session: &str) -> Result<()>;
+
+    /// The pane a new window starts with, for `--layout`.
+    fn first_pane(&self, _window: &str) -> Result<String> {
+        bail!("this backend can't split windows into panes")
+    }
+
+    /// Splits another pane off `window` and rearranges the window's panes with `layout`.
+    fn split_window(
+        &self,
+        _window: &str,
+        _cwd: Option<&str>,
+        _env: &[(String, String)],
+        _layout: Layout,
+    ) -> Result<String> {
+        bail!("this backend can't split windows into panes")
+    }
+}
+
+fn multiplexer(cli: &Cli) -> Box<dyn Multiplexer + '_> {
+    match cli.backend {
+        Backend::Tmux => Box::new(Tmux {
+            bin: &cli.tmux_bin,
+            extra_args: &cli.tmux_arg,
+        }),
+        Backend::Zellij => Box::new(Zellij {
+            bin: &cli.zellij_bin,
+        }),
+        Backend::Screen => Box::new(Screen {
+            bin: &cli.screen_bin,
+        }),
+    }
+}
+
+struct Tmux<'a> {
+    bin: &'a str,
+    /// `--tmux-arg`s, appended to new-session and new-window
+    extra_args: &'a [String],
+}
+
+impl Tmux<'_> {
+    /// The `-c` and `-e` arguments that start a window or pane in `cwd` with `env`.
+    fn start_args(cwd: Option<&str>, env: &[(String, String)]) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(dir) = cwd {
+            args.extend(["-c".to_string(), dir.to_string()]);
+        }
+        for (key, value) in env {
+            args.extend(["-e".to_string(), format!("{key}={value}")]);
+        }
+        args
+    }
+
+    /// Runs new-session or new-window (`command`) for `window`, returning the window id.
+    fn create(&self, command: &[&str], window: &NewWindow) -> Result<String> {
+        let mut args: Vec<String> = command.iter().map(|arg| arg.to_string()).collect();
+        args.extend(["-P", "-F", "#{window_id}", "-n", window.name].map(String::from));
+        args.extend(Self::start_args(window.cwd, window.env));
+        args.extend(self.extra_args.iter().cloned());
+        mux_stdout(self.bin, &args)
+    }
+}
+
+impl Multiplexer for Tmux<'_> {
+    fn has_session(&self, session: &str) -> Result<bool> {
+        let output = mux_output(self.bin, ["has-session", "-t", session])?;
+        if !output.status.success() {
+            debug!(
+                "no session {session}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(output.status.success())
+    }
+
+    fn kill_session(&self, session: &str) -> Result<()> {
+        run_mux(self.bin, ["kill-session", "-t", session])
+    }
+
+    fn new_session(&self, session: &str, window: &NewWindow) -> Result<String> {
+        self.create(&["new-session", "-d", "-s", session], window)
+    }
+
+    fn new_window(&self, session: &str, window: &NewWindow) -> Result<String> {
+        self.create(&["new-window", "-t", session], window)
+    }
+
+    fn send_keys(&self, _session: &str, target: &str, cmd: &str) -> Result<()> {
+        run_mux(self.bin, ["send-keys", "-t", target, "-l", cmd])?;
+        run_mux(self.bin, ["send-keys", "-t", target, "C-m"])
+    }
+
+    fn windows(&self, session: &str) -> Result<Vec<MuxWindow>> {
+        let listing = mux_stdout(
+            self.bin,
+            &["list-windows", "-t", session, "-F", "#{window_id} #I #W"],
+        )?;
+        Ok(listing
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(3, ' ');
+                Some(MuxWindow {
+                    id: parts.next()?.to_string(),
+                    index: parts.next()?.parse().ok()?,
+                    name: parts.next().unwrap_or("").to_string(),
+                })
+            })
+            .collect())
+    }
+
+    fn kill_window(&self, _session: &str, window: &MuxWindow) -> Result<()> {
+        // By id, so tmux base-index doesn't matter
+        run_mux(self.bin, ["kill-window", "-t", &window.id])
+    }
+
+    fn attach(&self, session: &str) -> Result<()> {
+        run_mux(self.bin, ["attach", "-t", session])
+    }
+
+    fn first_pane(&self, window: &str) -> Result<String> {
+        mux_stdout(
+            self.bin,
+            &["display-message", "-p", "-t", window, "#{pane_id}"],
+        )
+    }
+
+    fn split_window(
+        &self,
+        window: &str,
+        cwd: Option<&str>,
+        env: &[(String, String)],
+        layout: Layout,
+    ) -> Result<String> {
+        let mut args: Vec<String> = ["split-window", "-d", "-P", "-F", "#{pane_id}", "-t", window]
+            .map(String::from)
+            .to_vec();
+        args.extend(Self::start_args(cwd, env));
+        let pane = mux_stdout(self.bin, &args)?;
+        run_mux(
+            self.bin,
+            ["select-layout", "-t", window, layout.tmux_name()],
+        )?;
+        Ok(pane)
+    }
+}
+
+/// Zellij, where each window is a tab. Tabs have no ids on the command line, so they are
+/// targeted by name and focused before keys are written to them.
+struct Zellij<'a> {
+    bin: &'a str,
+}
+
+impl Zellij<'_> {
+    /// Runs `zellij --session SESSION action ARGS`, returning its trimmed stdout.
+    fn action(&self, session: &str, args: &[&str]) -> Result<String> {
+        let args = [&["--session", session, "action"], args].concat();
+        mux_stdout(self.bin, &args)
+    }
+}
+
+impl Multiplexer for Zellij<'_> {
+    fn has_session(&self, session: &str) -> Result<bool> {
+        let output = mux_output(self.bin, ["list-sessions", "--short", "--no-formatting"])?;
+        // zellij exits non-zero when no sessions exist at all
+        if !output.status.success() {
+            debug!(
+                "no zellij sessions: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return Ok(false);
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line.trim() == session))
+    }
+
+    fn kill_session(&self, session: &str) -> Result<()> {
+        run_mux(self.bin, ["delete-session", "--force", session])
+    }
+
+    fn new_session(&self, session: &str, window: &NewWindow) -> Result<String> {
+        mux_stdout(self.bin, &["attach", "--create-background", session])?;
+        // The session starts with a default tab; replace it with one opened like every other
+        // window, so it gets its name and directory
+        let target = self.new_window(session, window)?;
+        self.action(session, &["go-to-tab", "1"])?;
+        self.action(session, &["close-tab"])?;
+        Ok(target)
+    }
+
+    fn new_window(&self, session: &str, window: &NewWindow) -> Result<String> {
+        let mut args = vec!["new-tab", "--name", window.name];
+        if let Some(dir) = window.cwd {
+            args.extend(["--cwd", dir]);
+        }
+        self.action(session, &args)?;
+        Ok(window.name.to_string())
+    }
+
+    fn send_keys(&self, session: &str, target: &str, cmd: &str) -> Result<()> {
+        self.action(session, &["go-to-tab-name", target])?;
+        self.action(session, &["write-chars", cmd])?;
+        // Enter
+        self.action(session, &["write", "13"])?;
+        Ok(())
+    }
+
+    fn windows(&self, session: &str) -> Result<Vec<MuxWindow>> {
+        let names = self.action(session, &["query-tab-names"])?;
+        Ok(parse_zellij_tabs(&names))
+    }
+
+    fn kill_window(&self, session: &str, window: &MuxWindow) -> Result<()> {
+        self.action(session, &["go-to-tab-name", &window.id])?;
+        self.action(session, &["close-tab"])?;
+        Ok(())
+    }
+
+    fn attach(&self, session: &str) -> Result<()> {
+        run_mux(self.bin, ["attach", session])
+    }
+}
+
+/// GNU screen. `-p` would read a numeric window title as a window number, so windows are
+/// targeted by number, found by comparing the window list before and after creating one.
+struct Screen<'a> {
+    bin: &'a str,
+}
+
+impl Screen<'_> {
+    /// Sends `screen -S SESSION [-p WINDOW] -X ARGS` to the running session.
+    fn command(&self, session: &str, window: Option<&str>, args: &[&str]) -> Result<()> {
+        let mut all = vec!["-S", session];
+        if let Some(window) = window {
+            all.extend(["-p", window]);
+        }
+        all.push("-X");
+        all.extend(args);
+        mux_stdout(self.bin, &all).map(drop)
+    }
+}
+
+impl Multiplexer for Screen<'_> {
+    fn has_session(&self, session: &str) -> Result<bool> {
+        // screen -list exits non-zero whether or not sessions exist, so only its listing counts
+        let output = mux_output(self.bin, ["-list"])?;
+        Ok(String::from_utf8_lossy(&output.stdout).lines().any(|line| {
+            line.split_whitespace()
+                .next()
+                .and_then(|socket| socket.split_once('.'))
+                .is_some_and(|(_pid, name)| name == session)
+        }))
+    }
+
+    fn kill_session(&self, session: &str) -> Result<()> {
+        self.command(session, None, &["quit"])
+    }
+
+    fn new_session(&self, session: &str, window: &NewWindow) -> Result<String> {
+        mux_stdout(self.bin, &["-dmS", session])?;
+        // The session starts with a default window; replace it with one opened like every
+        // other window, so it gets its title and directory
+        let initial = self.windows(session)?;
+        let target = self.new_window(session, window)?;
+        for old in &initial {
+            self.kill_window(session, old)?;
+        }
+        Ok(target)
+    }
+
+    fn new_window(&self, session: &str, window: &NewWindow) -> Result<String> {
+        let before = self.windows(session)?;
+        // New windows start in the session's current directory, so set it for each one
+        let dir = match window.cwd {
+            Some(dir) => dir.to_string(),
+            None => std::env::current_dir()
+                .context("failed to read the current directory")?
+                .to_string_lossy()
+                .into_owned(),
+        };
+        self.command(session, None, &["chdir", &dir])?;
+        self.command(session, None, &["screen", "-t", window.name])?;
+        let created = self
+            .windows(session)?
+            .into_iter()
+            .find(|after| before.iter().all(|old| old.index != after.index))
+            .with_context(|| format!("screen did not report the new window '{}'", window.name))?;
+        Ok(created.id)
+    }
+
+    fn send_keys(&self, session: &str, target: &str, cmd: &str) -> Result<()> {
+        let keys = format!("{}^M", stuff_escape(cmd));
+        self.command(session, Some(target), &["stuff", &keys])
+    }
+
+    fn windows(&self, session: &str) -> Result<Vec<MuxWindow>> {
+        let listing = mux_stdout(self.bin, &["-S", session, "-Q", "windows"])?;
+        Ok(parse_screen_windows(&listing))
+    }
+
+    fn kill_window(&self, session: &str, window: &MuxWindow) -> Result<()> {
+        self.command(session, Some(&window.id), &["kill"])
+    }
+
+    fn attach(&self, session: &str) -> Result<()> {
+        run_mux(self.bin, ["-r", session])
+    }
+}
+
+/// Parses `screen -Q windows`, entries like `0$ bash  1*$ fix-login-bug` separated by two
+/// spaces: the window number, its flags, then its title.
+fn parse_screen_windows(listing: &str) -> Vec<MuxWindow> {
+    listing
+        .split("  ")
+        .filter_map(|entry| {
+            let entry = entry.trim();
+            let digits = entry.len() - entry.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let index: u32 = entry[..digits].parse().ok()?;
+            let name = entry[digits..]
+                .split_once(' ')
+                .map_or("", |(_flags, title)| title);
+            Some(MuxWindow {
+                id: index.to_string(),
+                index,
+                name: name.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parses `zellij action query-tab-names`, one tab name per line. Tabs are targeted by
+/// name, so a tab named by [`window_name`] keeps the number in its name even after earlier
+/// tabs were closed; any other tab is numbered after the highest of those.
+fn parse_zellij_tabs(names: &str) -> Vec<MuxWindow> {
+    let mut next = names.lines().filter_map(window_number).max().unwrap_or(0);
+    names
+        .lines()
+        .map(|name| {
+            let index = window_number(name).unwrap_or_else(|| {
+                next += 1;
+                next
+            });
+            MuxWindow {
+                id: name.to_string(),
+                index,
+                name: name.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Escapes text for screen's `stuff`, which reads `\`, `^X` and `$VAR` itself, so that
+/// only the `^M` appended after it presses Enter.
+fn stuff_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '^' | '$') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Runs a multiplexer command with its terminal attached (so `attach` works) but stderr
+/// captured for errors.
+fn run_mux<I, S>(bin: &str, args: I) -> Result<()>
 where
     I: IntoIterator<Item = S>,
-    S: AsRef<std::ffi::OsStr>,
+    S: AsRef<OsStr>,
 {
-    let status = Command::new(tmux)
-        .args(args)
-        .status()
-        .with_context(|| format!("failed to run {}", tmux))?;
-    if !status.success() {
-        bail!("tmux command failed: {}", tmux);
+    let args: Vec<OsString> = args.into_iter().map(|a| a.as_ref().to_owned()).collect();
+    debug!("running {bin} {}", describe_mux_args(&args));
+    let output = Command::new(bin)
+        .args(&args)
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|child| child.wait_with_output())
+        .with_context(|| format!("failed to run {}", bin))?;
+    if !output.status.success() {
+        return Err(mux_failure(bin, &args, &output));
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.trim().is_empty() {
+        warn!("{bin}: {}", stderr.trim());
     }
     Ok(())
 }
+
+/// Runs a multiplexer command non-interactively and returns its trimmed stdout, failing
+/// like [`run_mux`].
+fn mux_stdout<S: AsRef<OsStr>>(bin: &str, args: &[S]) -> Result<String> {
+    let output = mux_output(bin, args)?;
+    if !output.status.success() {
+        return Err(mux_failure(bin, args, &output));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Runs a multiplexer command non-interactively, capturing stdout and stderr.
+fn mux_output<I, S>(bin: &str, args: I) -> Result<Output>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<OsString> = args.into_iter().map(|a| a.as_ref().to_owned()).collect();
+    debug!("running {bin} {}", describe_mux_args(&args));
+    Command::new(bin)
+        .args(&args)
+        .output()
+        .with_context(|| format!("failed to run {}", bin))
+}
+
+fn mux_failure<S: AsRef<OsStr>>(bin: &str, args: &[S], output: &Output) -> anyhow::Error {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut message = format!(
+        "command failed ({}): {bin} {}",
+        output.status,
+        describe_mux_args(args)
+    );
+    if !stderr.trim().is_empty() {
+        message.push_str(&format!("\n{}", stderr.trim_end()));
+    }
+    anyhow::anyhow!(message)
+}
+
+/// Shell-quotes multiplexer arguments for logs and errors, hiding the values of tmux's
+/// `-e KEY=VALUE`.
+fn describe_mux_args<S: AsRef<OsStr>>(args: &[S]) -> String {
+    let mut shown = Vec::with_capacity(args.len());
+    let mut after_env_flag = false;
+    for arg in args {
+        let arg = arg.as_ref().to_string_lossy();
+        let arg = match arg.split_once('=') {
+            Some((key, _)) if after_env_flag => format!("{key}=..."),
+            _ => arg.into_owned(),
+        };
+        after_env_flag = arg == "-e";
+        shown.push(shell_words::quote(&arg).into_owned());
+    }
+    shown.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn extract_unchecked_todos(content: &str, states: &[char]) -> Vec<String> {
+        extract_todos(content, states)
+            .into_iter()
+            .map(|todo| todo.text)
+            .collect()
+    }
+
+    #[test]
+    fn session_falls_back_to_environment() {
+        // Checks the wiring rather than setting SPAWN_SESSION, which every parse reads
+        let command = Cli::command();
+        let session = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == "session")
+            .expect("has --session");
+        assert_eq!(session.get_env(), Some(OsStr::new("SPAWN_SESSION")));
+        assert_eq!(session.get_default_values(), ["spawn"]);
+    }
+
+    #[test]
+    fn tmux_args_in_logs_hide_env_values() {
+        let args = [
+            "new-window",
+            "-e",
+            "TOKEN=secret",
+            "-n",
+            "1 fix it",
+            "FOO=shown",
+        ];
+        assert_eq!(
+            describe_mux_args(&args),
+            "new-window -e 'TOKEN=...' -n '1 fix it' 'FOO=shown'"
+        );
+    }
+
+    const TODOS: &str = "- [ ] open\n  - detail\n- [x] done\n- [~] in progress\n";
+
+    #[test]
+    fn extracts_only_unchecked_by_default() {
+        let items = extract_unchecked_todos(TODOS, &[' ']);
+        assert_eq!(items, ["- [ ] open\n  - detail"]);
+    }
+
+    #[test]
+    fn extracts_requested_states() {
+        let states = parse_states(" ,~").expect("valid states");
+        let items = extract_unchecked_todos(TODOS, &states);
+        assert_eq!(items, ["- [ ] open\n  - detail", "- [~] in progress"]);
+    }
+
+    #[test]
+    fn substitutes_index_alongside_item() {
+        let document = Document::new(Path::new("todos.md"), "");
+        let todo = &extract_todos("- [ ] task\n", &[' '])[0];
+        let tokens = Tokens::new(3, todo, &document);
+        let harness = ["mytool", "--run-id", "{index}", "{item}"].map(String::from);
+        let cmd =
+            build_shell_command(&harness, "fix {index} parsing", &tokens).expect("has {item}");
+        assert_eq!(cmd, "mytool --run-id 3 'fix {index} parsing'");
+        let index_only = ["mytool", "--run-id", "{index}"].map(String::from);
+        assert!(build_shell_command(&index_only, "task", &tokens).is_err());
+    }
+
+    #[test]
+    fn file_and_doc_title_tokens_use_the_first_h1_or_the_file_stem() {
+        let path = Path::new("notes/sprint-12.md");
+        let titled = Document::new(path, "intro\n## Backlog\n# Sprint 12 #\n# Later\n");
+        assert_eq!(titled.title, "Sprint 12");
+        let content = "## Backlog\n- [ ] task\n";
+        let untitled = Document::new(path, content);
+        assert_eq!(untitled.title, "sprint-12");
+        let todo = &extract_todos(content, &[' '])[0];
+        let tokens = Tokens::new(2, todo, &untitled);
+        assert_eq!(
+            fill_tokens(
+                "[{doc_title}] {file} #{index} {section}/{title}: {item}",
+                None,
+                &tokens
+            ),
+            "[sprint-12] notes/sprint-12.md #2 Backlog/task: {item}"
+        );
+        let file_only = ["tool", "--log", "{file}", "--name", "{doc_title}"].map(String::from);
+        assert!(build_shell_command(&file_only, "task", &tokens).is_err());
+    }
+
+    #[test]
+    fn extracts_numbered_and_bullet_todos_in_order() {
+        let todos = "1. [ ] first\n   - detail\n- [ ] bullet\n2) [ ] second\n10. [x] done\n";
+        let items = extract_todos(todos, &[' ']);
+        let texts: Vec<&str> = items.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            ["1. [ ] first\n   - detail", "- [ ] bullet", "2) [ ] second"]
+        );
+        assert_eq!(todo_title(&items[2].text), "second");
+    }
+
+    #[test]
+    fn include_and_only_done_match_checked_todos() {
+        let todos = "- [ ] open\n- [x] done\n  - detail\n- [X] shipped\n";
+        let cli = |extra: &[&str]| {
+            let mut args = vec!["spawn", "count"];
+            args.splice(1..1, extra.iter().copied());
+            Cli::parse_from(args)
+        };
+        let matched = |extra: &[&str]| {
+            let states = actionable_states(&cli(extra)).expect("valid states");
+            extract_unchecked_todos(todos, &states)
+        };
+        assert_eq!(matched(&[]), ["- [ ] open"]);
+        assert_eq!(
+            matched(&["--include-done"]),
+            ["- [ ] open", "- [x] done\n  - detail", "- [X] shipped"]
+        );
+        assert_eq!(
+            matched(&["--only-done"]),
+            ["- [x] done\n  - detail", "- [X] shipped"]
+        );
+        assert!(Cli::try_parse_from(["spawn", "--only-done", "--include-done", "count"]).is_err());
+    }
+
+    #[test]
+    fn section_keeps_todos_up_to_next_peer_heading() {
+        let content = "# Plan\n- [ ] top\n## Sprint 3\n- [ ] a\n### Details\n- [ ] b\n## Sprint 4\n- [ ] c\n# Later\n## Sprint 3\n- [ ] d\n";
+        let selected = |section: &str| {
+            let cli = Cli::parse_from(["spawn", "--section", section, "count"]);
+            select_todos(&cli, content).map(|todos| {
+                todos
+                    .iter()
+                    .map(|todo| todo_title(&todo.text).to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(selected("Sprint 3").expect("found"), ["a", "b", "d"]);
+        assert_eq!(selected("Plan").expect("found"), ["top", "a", "b", "c"]);
+        let err = selected("Sprint 9").expect_err("missing section");
+        assert_eq!(
+            err.to_string(),
+            "section 'Sprint 9' not found; available sections: Plan, Sprint 3, Details, Sprint 4, Later"
+        );
+    }
+
+    #[test]
+    fn skip_and_match_filter_on_first_line() {
+        let content = "- [ ] ship api\n- [ ] WIP docs\n- [ ] ship cli @blocked\n- [ ] ship ui\n  - WIP detail\n";
+        let titles = |args: &[&str]| {
+            let cli = Cli::parse_from([&["spawn"], args, &["count"]].concat());
+            filter_todos(&cli, extract_todos(content, &[' ']))
+                .iter()
+                .map(|todo| todo_title(&todo.text).to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            titles(&["--skip", "WIP", "--skip", "@blocked"]),
+            ["ship api", "ship ui"]
+        );
+        assert_eq!(
+            titles(&["--match", "^- \\[ \\] ship", "--skip", "cli"]),
+            ["ship api", "ship ui"]
+        );
+        assert_eq!(
+            titles(&["--match", "docs", "--match", "api"]),
+            ["ship api", "WIP docs"]
+        );
+        assert!(Cli::try_parse_from(["spawn", "--skip", "(unclosed", "count"]).is_err());
+    }
+
+    #[test]
+    fn only_picks_positions_after_filters() {
+        assert_eq!(
+            parse_only("2,5, 7-9,8").expect("valid"),
+            BTreeSet::from([2, 5, 7, 8, 9])
+        );
+        assert!(parse_only("0").is_err());
+        assert!(parse_only("3-1").is_err());
+        assert!(parse_only("2,").is_err());
+
+        let content = "- [ ] a\n- [ ] WIP b\n- [ ] c\n- [ ] d\n";
+        let picked = |args: &[&str]| {
+            let cli = Cli::parse_from([&["spawn"], args, &["count"]].concat());
+            pick_todos(&cli, filter_todos(&cli, extract_todos(content, &[' ']))).map(|todos| {
+                todos
+                    .iter()
+                    .map(|t| todo_title(&t.text).to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            picked(&["--only", "2-3", "--skip", "WIP"]).expect("in range"),
+            ["c", "d"]
+        );
+        let err = picked(&["--only", "1,3-5", "--skip", "WIP"]).expect_err("out of range");
+        assert_eq!(
+            err.to_string(),
+            "--only: no todo at position(s) 4, 5 (only 3 matched)"
+        );
+    }
+
+    #[test]
+    fn check_off_marks_only_launched_todos() {
+        let content =
+            "# Plan\r\n- [ ] one\r\n  - [ ] sub\r\n1. [~] two\n- [ ] three\n   - [x] done";
+        let todos = extract_todos(content, &[' ', '~']);
+        let launched: Vec<&Todo> = vec![&todos[0], &todos[1]];
+        let (output, checked) = check_off_todos(content, &launched);
+        assert_eq!(checked, 2);
+        assert_eq!(
+            output,
+            "# Plan\r\n- [x] one\r\n  - [ ] sub\r\n1. [x] two\n- [ ] three\n   - [x] done"
+        );
+    }
+
+    #[test]
+    fn harness_tags_pick_named_commands() {
+        let content = "- [ ] fix bug @harness(claude)\n  - keep @harness(x) in details\n- [ ] write docs\n- [ ] @harness(nope) tidy\n";
+        let todos = extract_todos(content, &[' ']);
+        assert_eq!(
+            todos[0].text,
+            "- [ ] fix bug\n  - keep @harness(x) in details"
+        );
+        assert_eq!(todos[0].harness.as_deref(), Some("claude"));
+        assert_eq!(todos[2].text, "- [ ] tidy");
+
+        let cli = Cli::parse_from([
+            "spawn",
+            "--harness",
+            "claude=other {item}",
+            "--harness",
+            "claude=claude -p {item}",
+            "count",
+        ]);
+        let claude = ["claude", "-p", "{item}"].map(String::from);
+        assert_eq!(
+            todo_harness(&cli, &todos[0]).expect("known"),
+            Some(&claude[..])
+        );
+        assert_eq!(todo_harness(&cli, &todos[1]).expect("untagged"), None);
+        let err = todo_harness(&cli, &todos[2]).expect_err("unknown tag");
+        assert!(err.to_string().contains("no --harness nope=CMD"));
+
+        assert!(parse_harness("claude").is_err());
+        assert!(parse_harness("bad name=x {item}").is_err());
+        assert!(parse_harness("claude=claude -p").is_err());
+    }
+
+    #[test]
+    fn cwd_tags_resolve_against_base_directory() {
+        let base = std::env::temp_dir().join(format!("spawn-cwd-{}", std::process::id()));
+        fs::create_dir_all(base.join("api")).expect("create dirs");
+        let content = "- [ ] serve @cwd(api) @harness(x)\n- [ ] root\n- [ ] gone @cwd(missing)\n";
+        let todos = extract_todos(content, &[' ']);
+        assert_eq!(todos[0].text, "- [ ] serve");
+        assert_eq!(todos[0].cwd.as_deref(), Some("api"));
+
+        let base_arg = base.to_str().expect("utf-8 temp dir");
+        let cli = Cli::parse_from(["spawn", "--cwd", base_arg, "count"]);
+        let canonical = |path: PathBuf| {
+            fs::canonicalize(path)
+                .expect("exists")
+                .to_string_lossy()
+                .into_owned()
+        };
+        assert_eq!(
+            todo_cwd(&cli, &todos[0]).expect("exists"),
+            Some(canonical(base.join("api")))
+        );
+        assert_eq!(
+            todo_cwd(&cli, &todos[1]).expect("exists"),
+            Some(canonical(base.clone()))
+        );
+        let err = todo_cwd(&cli, &todos[2]).expect_err("missing directory");
+        assert!(err.to_string().contains("for todo 'gone' does not exist"));
+        let no_base = Cli::parse_from(["spawn", "count"]);
+        assert_eq!(todo_cwd(&no_base, &todos[1]).expect("no cwd"), None);
+        fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn env_vars_are_exported_per_window() {
+        let cli = Cli::parse_from([
+            "spawn",
+            "--env",
+            "TASK_ID=task-{index}",
+            "--env",
+            "API_KEY=it's secret",
+            "count",
+        ]);
+        let document = Document::new(Path::new("todo.md"), "");
+        let todo = &extract_todos("- [ ] task\n", &[' '])[0];
+        assert_eq!(
+            env_exports(&cli.env, &Tokens::new(3, todo, &document)),
+            "export TASK_ID=task-3; export API_KEY='it'\"'\"'s secret'; "
+        );
+        assert_eq!(
+            describe_env_vars(&cli),
+            "TASK_ID=task-{index}, API_KEY=it's secret"
+        );
+        let masked = Cli::parse_from(["spawn", "--env", "API_KEY=sk-1", "--mask-env", "count"]);
+        assert_eq!(describe_env_vars(&masked), "API_KEY=***");
+        assert!(Cli::try_parse_from(["spawn", "--env", "1BAD=x", "count"]).is_err());
+        assert!(Cli::try_parse_from(["spawn", "--env", "NOVALUE", "count"]).is_err());
+    }
+
+    #[test]
+    fn zellij_backend_rejects_tmux_only_options() {
+        let cli = |extra: &[&str]| Cli::parse_from([&["spawn"], extra, &["count"]].concat());
+        assert!(check_backend_options(&cli(&["--backend", "zellij"])).is_ok());
+        assert!(check_backend_options(&cli(&["--layout", "tiled"])).is_ok());
+        for extra in [
+            &["--layout", "tiled"][..],
+            &["--env-file", ".env"],
+            &["--tmux-arg", "-d"],
+        ] {
+            let args = [&["--backend", "zellij"][..], extra].concat();
+            assert!(check_backend_options(&cli(&args)).is_err(), "{extra:?}");
+        }
+        assert_eq!(Backend::Zellij.attach_hint("work"), "zellij attach work");
+
+        let window = |index| MuxWindow {
+            id: format!("@{index}"),
+            index,
+            name: index.to_string(),
+        };
+        assert_eq!(next_window_index(&[]), 1);
+        assert_eq!(next_window_index(&[window(0), window(4), window(2)]), 5);
+    }
+
+    #[test]
+    fn zellij_tabs_keep_their_named_number_across_gaps() {
+        // Tab 2 was closed, so numbering tabs by position would name the next one "3" again
+        let tabs = parse_zellij_tabs("Tab #1\n1-fix-login\n3\n");
+        let listed: Vec<(u32, &str)> = tabs.iter().map(|t| (t.index, t.id.as_str())).collect();
+        assert_eq!(listed, [(4, "Tab #1"), (1, "1-fix-login"), (3, "3")]);
+        assert_eq!(next_window_index(&tabs), 5);
+        assert_eq!(next_window_index(&parse_zellij_tabs("1\n3\n")), 4);
+    }
+
+    #[test]
+    fn screen_windows_and_stuff_escaping() {
+        let windows = parse_screen_windows("0$ bash  1-$ 2  3*$ fix-login-bug");
+        let listed: Vec<(u32, &str, &str)> = windows
+            .iter()
+            .map(|w| (w.index, w.id.as_str(), w.name.as_str()))
+            .collect();
+        assert_eq!(
+            listed,
+            [(0, "0", "bash"), (1, "1", "2"), (3, "3", "fix-login-bug")]
+        );
+        assert_eq!(stuff_escape(r"echo $HOME ^C \n"), r"echo \$HOME \^C \\n");
+        assert_eq!(Backend::Screen.attach_hint("work"), "screen -r work");
+    }
+
+    #[test]
+    fn layout_names_match_tmux() {
+        let cli = Cli::parse_from(["spawn", "--layout", "even-horizontal", "count"]);
+        assert_eq!(cli.layout, Some(Layout::EvenHorizontal));
+        assert_eq!(Layout::EvenHorizontal.tmux_name(), "even-horizontal");
+        assert!(Cli::try_parse_from(["spawn", "--layout", "main-vertical", "count"]).is_err());
+    }
+
+    #[test]
+    fn max_must_be_positive() {
+        let cli = Cli::parse_from(["spawn", "--max", "3", "count"]);
+        assert_eq!(cli.max, Some(3));
+        assert!(Cli::try_parse_from(["spawn", "--max", "0", "count"]).is_err());
+        assert!(Cli::try_parse_from(["spawn", "--max", "-2", "count"]).is_err());
+    }
+
+    #[test]
+    fn joins_prompt_parts_with_separators() {
+        let separators = Separators {
+            prefix: unescape("\\n---\\n"),
+            suffix: "\n\n".to_string(),
+        };
+        let prompt = build_prompt("- [ ] task\n", Some("role"), Some("done"), &separators);
+        assert_eq!(prompt, "role\n---\n- [ ] task\n\ndone");
+    }
+
+    #[test]
+    fn renders_template_placeholders_per_todo() {
+        let content = "# Backend {api}\n\n- [ ] Fix {item} parsing\n  - keep tests\n\n## Docs ##\n- [ ] README\n";
+        let todos = extract_todos(content, &[' ']);
+        let document = Document::new(Path::new("plan.md"), content);
+        let template = "Task {index} in {section}: {title}\n\n{item}\n{unknown}\n";
+        assert_eq!(
+            render_template(template, &Tokens::new(1, &todos[0], &document)),
+            "Task 1 in Backend {api}: Fix {item} parsing\n\n- [ ] Fix {item} parsing\n  - keep tests\n{unknown}"
+        );
+        assert_eq!(
+            render_template(
+                "{file} ({doc_title}): {section}/{title}",
+                &Tokens::new(2, &todos[1], &document)
+            ),
+            "plan.md (Backend {api}): Docs/README"
+        );
+        let orphan = Todo {
+            text: "- [ ] alone".to_string(),
+            section: None,
+            lines: 0..1,
+            harness: None,
+            cwd: None,
+        };
+        assert_eq!(
+            render_template("[{section}] {title}", &Tokens::new(1, &orphan, &document)),
+            "[] alone"
+        );
+    }
+
+    #[test]
+    fn strict_rejects_multiline_todos() {
+        let todos = extract_todos("- [ ] one line\n- [ ] two\n  - lines\n", &[' ']);
+        let todos: Vec<&Todo> = todos.iter().collect();
+        assert!(check_multiline_todos(&todos[..1], true).is_ok());
+        assert!(check_multiline_todos(&todos, false).is_ok());
+        let err = check_multiline_todos(&todos, true).expect_err("strict fails");
+        assert!(err.to_string().starts_with("todo(s) 2 span several lines"));
+    }
+
+    #[test]
+    fn review_keeps_answered_prompts() {
+        let prompts = || ["one", "two", "three", "four"].map(String::from).to_vec();
+        let review = |answers: &str| {
+            let mut output = Vec::new();
+            review_prompts(prompts(), &mut answers.as_bytes(), &mut output).expect("reviews")
+        };
+        assert_eq!(review("y\nn\n?\ny\nn\n"), ["one", "three"]);
+        assert_eq!(review("n\na\n"), ["two", "three", "four"]);
+        assert_eq!(review("y\nq\n"), ["one"]);
+        assert_eq!(review("y\n"), ["one"]);
+    }
+
+    #[test]
+    fn picker_toggles_within_max() {
+        let titles = ["one", "two", "three", "four"];
+        let mut picker = Picker::new(&titles, Some(2));
+        assert_eq!(picker.ticked(), 2);
+        for key in [PickerKey::Down, PickerKey::Down, PickerKey::Toggle] {
+            assert_eq!(picker.handle(key), None);
+        }
+        // "three" can't be ticked while two already are
+        assert_eq!(picker.ticked(), 2);
+        assert!(picker.render(10).contains("--max 2"));
+        for key in [
+            PickerKey::Up,
+            PickerKey::Toggle,
+            PickerKey::Up,
+            PickerKey::Up,
+        ] {
+            picker.handle(key);
+        }
+        picker.handle(PickerKey::Toggle);
+        assert_eq!(picker.handle(PickerKey::Confirm), Some(vec![0, 3]));
+
+        let mut picker = Picker::new(&titles, None);
+        picker.handle(PickerKey::All);
+        assert_eq!(picker.handle(PickerKey::Confirm), Some(vec![]));
+        picker.handle(PickerKey::All);
+        assert_eq!(picker.ticked(), 4);
+        assert_eq!(picker.handle(PickerKey::Cancel), Some(vec![]));
+    }
+
+    #[test]
+    fn reads_picker_keys() {
+        let keys = |input: &[u8]| {
+            let mut input = input;
+            let mut keys = Vec::new();
+            loop {
+                let key = read_picker_key(&mut input).expect("reads");
+                keys.push(key);
+                if key == Some(PickerKey::Cancel) {
+                    return keys;
+                }
+            }
+        };
+        assert_eq!(
+            keys(b" \x1b[Bjx\r"),
+            [
+                Some(PickerKey::Toggle),
+                Some(PickerKey::Down),
+                Some(PickerKey::Down),
+                None,
+                Some(PickerKey::Confirm),
+                Some(PickerKey::Cancel),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_env_file_quotes_and_comments() {
+        let content = "# api keys\nexport API_KEY=abc123 # inline\nGREETING=\"hello \\\"world\\\"\\n\"\nRAW='a # b'\n\nEMPTY=\n";
+        let vars = parse_env_file(content).expect("valid env file");
+        assert_eq!(
+            vars,
+            [
+                ("API_KEY".to_string(), "abc123".to_string()),
+                ("GREETING".to_string(), "hello \"world\"\n".to_string()),
+                ("RAW".to_string(), "a # b".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ]
+        );
+        assert!(parse_env_file("1BAD=x").is_err());
+        assert!(parse_env_file("OPEN=\"never closed").is_err());
+    }
+
+    #[test]
+    fn truncates_window_names_but_keeps_number() {
+        assert_eq!(window_name(3, None, 20), "3");
+        assert_eq!(window_name(3, Some("Fix login bug"), 20), "3-fix-login-bug");
+        let long = window_name(12, Some("Refactor the payment gateway retries"), 20);
+        assert_eq!(long, "12-refactor-the-pay…");
+        assert_eq!(long.chars().count(), 20);
+        assert_eq!(window_number(&long), Some(12));
+        assert_eq!(window_name(7, Some("anything"), 2), "7");
+    }
+
+    #[test]
+    fn todo_window_names_keep_their_number() {
+        let cli = Cli::parse_from(["spawn", "--name-from-todo", "--name-width", "12", "count"]);
+        let titles = ["Fix login bug", "Fix login bug", "***"].map(String::from);
+        let names = window_names(&cli, &titles, 4);
+        assert_eq!(names, ["4-fix-login…", "5-fix-login…", "6"]);
+        assert_eq!(window_number(&names[1]), Some(5));
+
+        let cli = Cli::parse_from(["spawn", "count"]);
+        assert_eq!(window_names(&cli, &titles, 4), ["4", "5", "6"]);
+    }
+
+    #[test]
+    fn rejects_multi_char_state() {
+        assert!(parse_states("x,done").is_err());
+    }
+}